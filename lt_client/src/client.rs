@@ -1,104 +1,557 @@
+use std::collections::HashSet;
+use std::io::{self, Read};
 use std::mem::{self, MaybeUninit};
-use std::net::{Ipv4Addr, SocketAddrV4};
-use std::sync::Arc;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use lt_utilities::audio_features::{AtomicAudioFeatures, OSC_ADDR_BROADRANGERMS, OSC_ADDR_FLUX, OSC_ADDR_HIGHRANGERMS, OSC_ADDR_LOWRANGERMS, OSC_ADDR_MIDRANGERMS, OSC_ADDR_SPECTRALCENTROID, OSC_ADDR_ZCR};
-use rosc::OscPacket;
-use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::time::{Duration, Instant, SystemTime};
+use lt_utilities::atomic_float::FeatureDescriptor;
+use lt_utilities::audio_features::{AtomicAudioFeatures, FeatureFrame, FEATURES};
+use lt_utilities::namespace::Namespace;
+use lt_utilities::snapshot::Snapshot;
+use lt_server::discovery::{browse, DiscoveredServer, DiscoveryError};
+use lt_server::heartbeat::{ServerInfo, HEARTBEAT_INTERVAL};
+use lt_server::server::OSC_ADDR_SEQUENCE;
+use lt_server::stream::SlipDecoder;
+use lt_server::timetag::from_timetag;
+use lt_server::network::{bind_to_interface, domain_of, unspecified_like, MulticastConfig, NetworkInterface};
+use rosc::{OscMessage, OscPacket, OscType};
+use socket2::{Protocol, SockAddr, Socket, Type};
+
+use crate::scheduler::start_scheduler;
+use crate::stats::{ReceiveStats, SequenceTracker};
+
+/// Time without packets after which a server is considered gone
+pub const DEFAULT_CONNECTION_TIMEOUT: Duration = Duration::from_millis(HEARTBEAT_INTERVAL.as_millis() as u64 * 4);
+/// First wait before reconnecting to a stream server, doubled after every failed attempt
+pub const STREAM_RECONNECT_INTERVAL: Duration = Duration::from_millis(500);
+pub const MAX_STREAM_RECONNECT_INTERVAL: Duration = Duration::from_secs(8);
+
+#[derive(Clone, Debug)]
+pub struct ClientOptions {
+    pub port: u16,
+    /// Local address to bind, its address family selects IPv4 or IPv6
+    pub bind: IpAddr,
+    /// Group to join in addition to receiving broadcast and unicast packets
+    pub multicast: Option<MulticastConfig>,
+    /// Only receive packets arriving on this interface
+    pub interface: Option<NetworkInterface>,
+    pub connection_timeout: Duration,
+    /// Applies frames at their timetag plus this many milliseconds instead of on arrival.
+    /// Negative values apply them early, when the timetags are ahead of the client clock
+    pub schedule_offset_ms: Option<i64>,
+    /// Receives from the stream server at `host:port` over TCP instead of UDP.
+    /// The connection is reopened when it closes or the connection timeout passes without packets
+    pub stream: Option<String>,
+    /// Addresses the server sends features on
+    pub namespace: Namespace,
+    /// Features the server runs, only their addresses are read and they tell scalar and array features apart.
+    /// The built in features by default
+    pub features: Vec<FeatureDescriptor>,
+}
+
+impl ClientOptions {
+    pub fn new(port: u16) -> Self {
+        Self {
+            port,
+            bind: Ipv4Addr::UNSPECIFIED.into(),
+            multicast: None,
+            interface: None,
+            connection_timeout: DEFAULT_CONNECTION_TIMEOUT,
+            schedule_offset_ms: None,
+            stream: None,
+            namespace: Namespace::default(),
+            features: FEATURES.to_vec(),
+        }
+    }
+}
 
 pub struct LunaTechClient {
-    socket: Arc<Socket>,
-    pub audio_features: Arc<AtomicAudioFeatures>
+    /// `None` when receiving from a stream server
+    socket: Option<Arc<Socket>>,
+    pub audio_features: Arc<AtomicAudioFeatures>,
+    server_info: Arc<Mutex<Option<ServerInfo>>>,
+    last_seen: Arc<Mutex<Option<Instant>>>,
+    latency: Arc<Mutex<Option<Duration>>>,
+    sequence_tracker: Arc<Mutex<SequenceTracker>>,
+    connection_timeout: Duration,
+    scheduled: bool,
+    schedule_offset_ms: Arc<AtomicI64>,
+    /// Cleared when the client is dropped to stop reconnecting
+    alive: Arc<AtomicBool>,
 }
 
 impl LunaTechClient {
     
     pub fn new(port: u16) -> Self {
-        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).expect("Failed to create socket");
-        let bindaddr: SockAddr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port).into();
-        socket.set_broadcast(true).expect("Failed to set broadcast"); 
-        socket.set_reuse_address(true).expect("Failed to set reuse address");
-        socket.bind(&bindaddr).expect("Failed to bind socket");
-        
+        Self::with_options(ClientOptions::new(port))
+    }
+
+    pub fn with_options(options: ClientOptions) -> Self {
         let client = Self { 
-            socket: socket.into(), 
-            audio_features: AtomicAudioFeatures::default().into()
+            socket: options.stream.is_none().then(|| Self::udp_socket(&options).into()),
+            audio_features: AtomicAudioFeatures::default().into(),
+            server_info: Arc::new(Mutex::new(None)),
+            last_seen: Arc::new(Mutex::new(None)),
+            latency: Arc::new(Mutex::new(None)),
+            sequence_tracker: Arc::new(Mutex::new(SequenceTracker::default())),
+            connection_timeout: options.connection_timeout,
+            scheduled: options.schedule_offset_ms.is_some(),
+            schedule_offset_ms: Arc::new(AtomicI64::new(options.schedule_offset_ms.unwrap_or(0))),
+            alive: Arc::new(AtomicBool::new(true)),
         };
 
-        client.start_client();
+        client.start_client(options.stream, options.namespace, options.features);
         client
     }
 
-    fn start_client(&self) {
-        let socket_clone = self.socket.clone();
+    /// Receives from a stream server at `host:port` over TCP
+    pub fn connect_stream(server: &str) -> Self {
+        Self::with_options(ClientOptions {
+            stream: Some(server.to_owned()),
+            ..ClientOptions::new(0)
+        })
+    }
+
+    fn udp_socket(options: &ClientOptions) -> Socket {
+        // An IPv6 group can only be joined from an IPv6 socket
+        let bind_ip = match &options.multicast {
+            Some(multicast) if multicast.group.is_ipv6() && options.bind.is_ipv4() => unspecified_like(&multicast.group),
+            _ => options.bind,
+        };
+
+        let socket = Socket::new(domain_of(&bind_ip), Type::DGRAM, Some(Protocol::UDP)).expect("Failed to create socket");
+        let bindaddr: SockAddr = SocketAddr::new(bind_ip, options.port).into();
+        if bind_ip.is_ipv4() {
+            socket.set_broadcast(true).expect("Failed to set broadcast"); 
+        } else {
+            // Also accept IPv4 packets where the OS supports dual stack sockets
+            let _ = socket.set_only_v6(false);
+        }
+        socket.set_reuse_address(true).expect("Failed to set reuse address");
+
+        // Stays bound to the unspecified address, a socket bound to a unicast address misses broadcasts
+        if let Some(interface) = &options.interface {
+            if let Err(e) = bind_to_interface(&socket, interface) {
+                println!("Could not bind to device {}: {}", interface.name, e);
+            }
+        }
+        socket.bind(&bindaddr).expect("Failed to bind socket");
+
+        if let Some(multicast) = &options.multicast {
+            let mut multicast = multicast.clone();
+            if let Some(interface) = &options.interface {
+                if let (IpAddr::V4(addr), true) = (interface.addr, multicast.interface.is_unspecified()) {
+                    multicast.interface = addr;
+                }
+                if multicast.interface_index == 0 {
+                    multicast.interface_index = interface.index.unwrap_or(0);
+                }
+            }
+            multicast.validate().expect("Invalid multicast group");
+            multicast.join(&socket).expect("Failed to join multicast group");
+        }
+        socket
+    }
+
+    /// Looks for servers advertised on the network or this machine for `timeout`
+    pub fn discover(timeout: Duration) -> Result<Vec<DiscoveredServer>, DiscoveryError> {
+        browse(timeout)
+    }
+
+    /// Receives the features of a discovered server in its namespace.
+    /// Connects to its stream port if it has one, otherwise receives UDP and joins its multicast group if it has one
+    pub fn connect(server: &DiscoveredServer) -> Self {
+        Self::with_options(Self::connect_options(server))
+    }
+
+    fn connect_options(server: &DiscoveredServer) -> ClientOptions {
+        let options = ClientOptions {
+            multicast: server.multicast.map(MulticastConfig::new),
+            namespace: server.namespace.clone(),
+            ..ClientOptions::new(server.port)
+        };
+        match (server.addrs.first(), server.stream_port) {
+            (Some(addr), Some(port)) => ClientOptions { stream: Some(SocketAddr::new(*addr, port).to_string()), multicast: None, ..options },
+            // Servers only reachable over IPv6 send IPv6 packets
+            (Some(addr), None) if server.addrs.iter().all(IpAddr::is_ipv6) => ClientOptions { bind: unspecified_like(addr), ..options },
+            _ => options,
+        }
+    }
+
+    /// Latest received frame, every value is from the same bundle
+    pub fn snapshot(&self) -> Snapshot<FeatureFrame> {
+        self.audio_features.snapshot()
+    }
+
+    /// Whether any packet arrived from a server within the connection timeout
+    pub fn is_connected(&self) -> bool {
+        self.last_seen().map(|last_seen| last_seen.elapsed() < self.connection_timeout).unwrap_or(false)
+    }
+
+    /// Time the last packet arrived from a server
+    pub fn last_seen(&self) -> Option<Instant> {
+        *self.last_seen.lock().ok()?
+    }
+
+    /// Time from capturing the audio of the latest frame to receiving it.
+    /// Includes the clock difference between the server and client machines
+    pub fn latency(&self) -> Option<Duration> {
+        *self.latency.lock().ok()?
+    }
+
+    /// Lost, duplicated and reordered bundles and jitter since the client was created
+    pub fn stats(&self) -> ReceiveStats {
+        self.sequence_tracker.lock().map(|tracker| tracker.stats()).unwrap_or_default()
+    }
+
+    /// Offset frames are applied at, `None` if they are applied on arrival
+    pub fn schedule_offset_ms(&self) -> Option<i64> {
+        self.scheduled.then(|| self.schedule_offset_ms.load(Ordering::Relaxed))
+    }
+
+    /// Changes the offset of a client created with `schedule_offset_ms`, to line up with other outputs while running
+    pub fn set_schedule_offset_ms(&self, offset_ms: i64) {
+        self.schedule_offset_ms.store(offset_ms, Ordering::Relaxed);
+    }
+
+    /// Contents of the last heartbeat
+    pub fn server_info(&self) -> Option<ServerInfo> {
+        self.server_info.lock().ok()?.clone()
+    }
+
+    fn start_client(&self, stream: Option<String>, namespace: Namespace, features: Vec<FeatureDescriptor>) {
         let audio_features = self.audio_features.clone();
+        let server_info = self.server_info.clone();
+        let last_seen = self.last_seen.clone();
+        let latency = self.latency.clone();
+        let sequence_tracker = self.sequence_tracker.clone();
+        let scheduler = self.scheduled.then(|| start_scheduler(self.audio_features.clone(), self.schedule_offset_ms.clone()));
 
-        thread::spawn(move || {
-            let mut buf = [MaybeUninit::<u8>::uninit(); rosc::decoder::MTU];
-            loop {
-                let result = socket_clone.recv(&mut buf);
-
-                // Todo: Safe?
-                let data = unsafe { mem::transmute::<[std::mem::MaybeUninit<u8>; rosc::decoder::MTU], [u8; 1536]>(buf) };
-                
-                if let Ok(size) = result {
-                    match rosc::decoder::decode_udp(&data[..size]) {
-                        Ok((_, packet)) => {
-                            Self::handle_packet(packet, &audio_features);
+        let mut frame_counter = FrameCounter::default();
+        let tracker = self.sequence_tracker.clone();
+        let handle_data = move |data: &[u8]| {
+            match rosc::decoder::decode_udp(data) {
+                Ok((_, packet)) => {
+                    let received_at = SystemTime::now();
+                    if let Ok(mut last_seen) = last_seen.lock() {
+                        *last_seen = Some(Instant::now());
+                    }
+                    if let OscPacket::Message(msg) = &packet {
+                        if let (Some(info), Ok(mut server_info)) = (ServerInfo::from_message(msg), server_info.lock()) {
+                            // A restarted or different server counts from 0 again
+                            if server_info.as_ref().is_some_and(|previous| previous.server_id != info.server_id) {
+                                if let Ok(mut sequence_tracker) = sequence_tracker.lock() {
+                                    sequence_tracker.reset();
+                                }
+                            }
+                            *server_info = Some(info);
+                        }
+                    }
+                    let sequence = Self::bundle_sequence(&packet);
+                    let timetagged = matches!(packet, OscPacket::Bundle(_));
+                    let message_addr = match &packet {
+                        OscPacket::Message(msg) => Some(msg.addr.clone()),
+                        OscPacket::Bundle(_) => None,
+                    };
+                    if let Some(mut frame) = Self::handle_packet(packet, &namespace, &features, &audio_features) {
+                        if let (true, Ok(mut latency)) = (timetagged, latency.lock()) {
+                            *latency = received_at.duration_since(frame.captured_at).ok();
+                        }
+                        if let (Some(sequence), Ok(mut sequence_tracker)) = (sequence, sequence_tracker.lock()) {
+                            sequence_tracker.record(sequence, frame.captured_at, received_at);
                         }
-                        Err(e) => {
-                            println!("Got invalid packet: {}", e);
+                        frame.frame = match &message_addr {
+                            Some(addr) => frame_counter.message(addr),
+                            None => frame_counter.bundle(),
+                        };
+                        match &scheduler {
+                            Some(scheduler) => { let _ = scheduler.send(frame); },
+                            None => audio_features.store(&frame),
                         }
                     }
                 }
+                Err(e) => {
+                    println!("Got invalid packet: {}", e);
+                }
             }
-        });
-    }
-
-    fn handle_packet(packet: OscPacket, audio_features: &AtomicAudioFeatures) {
-            match packet {
-                OscPacket::Message(_msg) => {}
-                OscPacket::Bundle(bundle) => {
-                    //println!("OSC Bundle: {:?}", bundle);
-                    // TODO: Use timetag
-                    bundle.content.iter().for_each(|packet| {
-                        if let OscPacket::Message(msg) = packet {
-                            if let Some(val) = <rosc::OscType as Clone>::clone(&msg.args[0]).float() {
-                                match msg.addr.as_str() {
-                                   OSC_ADDR_BROADRANGERMS => {
-                                        audio_features.broad_range_rms.set(val);
-                                    }
-                                   OSC_ADDR_LOWRANGERMS => {
-                                        audio_features.low_range_rms.set(val);
-                                    }
-                                    OSC_ADDR_MIDRANGERMS=> {
-                                        audio_features.mid_range_rms.set(val);
-                                    }
-                                    OSC_ADDR_HIGHRANGERMS => {
-                                        audio_features.high_range_rms.set(val);
-                                    }
-                                    OSC_ADDR_ZCR => {
-                                        audio_features.zcr.set(val);
-                                    }
-                                    OSC_ADDR_SPECTRALCENTROID => {
-                                        audio_features.spectral_centroid.set(val);
-                                    }
-                                    OSC_ADDR_FLUX => {
-                                        audio_features.flux.set(val);
-                                    }
-                                    _ => {}
-                                }
-                            }
-                        };
-                    });
+        };
+
+        match (stream, &self.socket) {
+            (Some(server), _) => {
+                let alive = self.alive.clone();
+                let connection_timeout = self.connection_timeout;
+                thread::spawn(move || receive_stream(&server, connection_timeout, alive, tracker, handle_data));
+            }
+            (None, Some(socket)) => {
+                let socket_clone = socket.clone();
+                let mut handle_data = handle_data;
+                thread::spawn(move || {
+                    let mut buf = [MaybeUninit::<u8>::uninit(); rosc::decoder::MTU];
+                    loop {
+                        let result = socket_clone.recv(&mut buf);
+
+                        // Todo: Safe?
+                        let data = unsafe { mem::transmute::<[std::mem::MaybeUninit<u8>; rosc::decoder::MTU], [u8; 1536]>(buf) };
+                        
+                        if let Ok(size) = result {
+                            handle_data(&data[..size]);
+                        }
+                    }
+                });
+            }
+            (None, None) => unreachable!("Clients without a stream server have a socket"),
+        }
+    }
+
+    /// Sequence number of a feature bundle
+    fn bundle_sequence(packet: &OscPacket) -> Option<u32> {
+        let OscPacket::Bundle(bundle) = packet else {
+            return None;
+        };
+        bundle.content.iter().find_map(|packet| match packet {
+            OscPacket::Message(msg) if msg.addr == OSC_ADDR_SEQUENCE => match msg.args.first() {
+                Some(OscType::Int(sequence)) => Some(*sequence as u32),
+                _ => None,
+            },
+            _ => None,
+        })
+    }
+
+    /// Collects the features of a bundle into a frame.
+    /// A single message updates one feature of the latest frame, for servers sending the messages layout
+    fn handle_packet(packet: OscPacket, namespace: &Namespace, features: &[FeatureDescriptor], audio_features: &AtomicAudioFeatures) -> Option<FeatureFrame> {
+        match packet {
+            OscPacket::Message(msg) => {
+                let mut frame = FeatureFrame { captured_at: SystemTime::now(), ..(*audio_features.snapshot()).clone() };
+                Self::apply_message(&mut frame, &msg, namespace, features).then_some(frame)
+            }
+            OscPacket::Bundle(bundle) => {
+                //println!("OSC Bundle: {:?}", bundle);
+                let mut frame = FeatureFrame {
+                    captured_at: from_timetag(bundle.timetag).unwrap_or_else(SystemTime::now),
+                    ..Default::default()
+                };
+                bundle.content.iter().for_each(|packet| {
+                    if let OscPacket::Message(msg) = packet {
+                        Self::apply_message(&mut frame, msg, namespace, features);
+                    };
+                });
+                Some(frame)
+            }
+        }
+    }
+
+    /// Stores the feature a message holds in the frame, false if it holds none.
+    /// Only the addresses of `features` are read, other messages such as replies to control commands are not features
+    fn apply_message(frame: &mut FeatureFrame, msg: &OscMessage, namespace: &Namespace, features: &[FeatureDescriptor]) -> bool {
+        let descriptor = |name: &str| features.iter().find(|feature| feature.name == name);
+        let vals = msg.args.iter().filter_map(|arg| arg.clone().float()).collect::<Vec<f32>>();
+        // Subscriptions can select single values of an array
+        if let (Some((name, index)), [val]) = (namespace.feature_element(&msg.addr), vals.as_slice()) {
+            if descriptor(name).is_some_and(|feature| feature.is_array()) {
+                return frame.set_array_element(name, index, *val);
+            }
+        }
+        let Some(feature) = namespace.feature_name(&msg.addr).and_then(descriptor) else {
+            return false;
+        };
+        if feature.is_array() {
+            frame.set_array(&feature.name, vals);
+        } else if let Some(val) = vals.first() {
+            frame.set(&feature.name, *val);
+        } else {
+            return false;
+        }
+        true
+    }
+}
+
+impl Drop for LunaTechClient {
+    fn drop(&mut self) {
+        self.alive.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Numbers received frames. Every bundle is a frame, and messages sent one per feature
+/// belong to the same frame until an address repeats
+#[derive(Debug, Default)]
+struct FrameCounter {
+    frames: u64,
+    /// Addresses of the messages of the current frame
+    message_addrs: HashSet<String>,
+}
+
+impl FrameCounter {
+    fn bundle(&mut self) -> u64 {
+        self.message_addrs.clear();
+        self.frames += 1;
+        self.frames - 1
+    }
+
+    fn message(&mut self, addr: &str) -> u64 {
+        if self.message_addrs.is_empty() || self.message_addrs.contains(addr) {
+            self.message_addrs.clear();
+            self.frames += 1;
+        }
+        self.message_addrs.insert(addr.to_owned());
+        self.frames - 1
+    }
+}
+
+/// Receives SLIP framed packets from a stream server, reconnecting until the client is dropped.
+/// Servers send a heartbeat every 500 ms, so a connection without packets for `connection_timeout` is reopened
+fn receive_stream(server: &str, connection_timeout: Duration, alive: Arc<AtomicBool>, sequence_tracker: Arc<Mutex<SequenceTracker>>, mut handle_data: impl FnMut(&[u8])) {
+    let mut reconnect_interval = STREAM_RECONNECT_INTERVAL;
+    while alive.load(Ordering::Relaxed) {
+        match TcpStream::connect(server).and_then(|stream| stream.set_read_timeout(Some(connection_timeout)).map(|_| stream)) {
+            Ok(mut stream) => {
+                println!("Connected to stream server {}", server);
+                reconnect_interval = STREAM_RECONNECT_INTERVAL;
+                // Every connection counts its bundles from 0
+                if let Ok(mut sequence_tracker) = sequence_tracker.lock() {
+                    sequence_tracker.reset();
                 }
+
+                let mut decoder = SlipDecoder::default();
+                let mut buf = [0u8; 4096];
+                let reason = loop {
+                    if !alive.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    match stream.read(&mut buf) {
+                        Ok(0) => break "closed by server".to_owned(),
+                        Ok(size) => decoder.decode(&buf[..size]).iter().for_each(|packet| handle_data(packet)),
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                        Err(e) => break e.to_string(),
+                    }
+                };
+                println!("Lost connection to stream server {}: {}", server, reason);
             }
+            Err(e) => println!("Failed to connect to stream server {}: {}", server, e),
         }
+        thread::sleep(reconnect_interval);
+        reconnect_interval = (reconnect_interval * 2).min(MAX_STREAM_RECONNECT_INTERVAL);
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
 
+    use super::*;
+    use lt_server::layout::MessageLayout;
+    use lt_server::server::LunaTechServer;
+
+    fn server(addrs: Vec<IpAddr>, stream_port: Option<u16>) -> DiscoveredServer {
+        let mut namespace = Namespace::new("/venue");
+        namespace.set_instance(Some("stageA"));
+        DiscoveredServer {
+            instance_name: "Stage A".to_owned(),
+            addrs,
+            port: 8000,
+            version: None,
+            features: Vec::new(),
+            multicast: Some(Ipv4Addr::new(239, 1, 2, 3).into()),
+            oscquery_port: None,
+            stream_port,
+            namespace,
+            layout: MessageLayout::Bundle,
+        }
+    }
 
+    #[test]
+    fn connects_in_the_advertised_namespace() {
+        let options = LunaTechClient::connect_options(&server(vec![Ipv4Addr::new(10, 0, 0, 2).into()], None));
+        assert_eq!(options.namespace.address("Flux"), "/venue/stageA/Flux");
+        assert_eq!(options.port, 8000);
+        assert_eq!(options.multicast.map(|multicast| multicast.group), Some(Ipv4Addr::new(239, 1, 2, 3).into()));
+        assert_eq!(options.stream, None);
+        assert_eq!(options.bind, IpAddr::from(Ipv4Addr::UNSPECIFIED));
+    }
 
+    #[test]
+    fn prefers_the_advertised_stream() {
+        let options = LunaTechClient::connect_options(&server(vec![Ipv6Addr::LOCALHOST.into(), Ipv4Addr::LOCALHOST.into()], Some(9000)));
+        assert_eq!(options.stream.as_deref(), Some("[::1]:9000"));
+        assert!(options.multicast.is_none());
+        assert_eq!(options.namespace.address("Flux"), "/venue/stageA/Flux");
 
+        // Without addresses there is nothing to connect to
+        assert_eq!(LunaTechClient::connect_options(&server(Vec::new(), Some(9000))).stream, None);
+    }
 
-    
+    #[test]
+    fn receives_over_ipv6() {
+        let port = std::net::UdpSocket::bind("[::1]:0").unwrap().local_addr().unwrap().port();
+        let client = LunaTechClient::with_options(ClientOptions { bind: Ipv6Addr::UNSPECIFIED.into(), ..ClientOptions::new(port) });
+        let mut server = LunaTechServer::new(port);
+        server.set_broadcast(false).unwrap();
+        server.add_target(&format!("[::1]:{}", port)).unwrap();
+        let (tx, rx) = crossbeam::channel::unbounded();
+        server.set_thread_receiver(rx);
+        server.start_server();
 
+        let started = Instant::now();
+        while client.snapshot().flux != 0.5 && started.elapsed() < Duration::from_secs(2) {
+            tx.send(FeatureFrame { flux: 0.5, ..Default::default() }).unwrap();
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(client.snapshot().flux, 0.5);
+    }
+
+    #[test]
+    fn binds_ipv6_for_ipv6_servers() {
+        let options = LunaTechClient::connect_options(&server(vec![Ipv6Addr::LOCALHOST.into()], None));
+        assert_eq!(options.bind, IpAddr::from(Ipv6Addr::UNSPECIFIED));
+    }
+
+    fn message(addr: &str, args: Vec<f32>) -> OscMessage {
+        OscMessage { addr: addr.to_owned(), args: args.into_iter().map(OscType::Float).collect() }
+    }
+
+    fn apply(frame: &mut FeatureFrame, msg: OscMessage) -> bool {
+        LunaTechClient::apply_message(frame, &msg, &Namespace::default(), FEATURES)
+    }
+
+    #[test]
+    fn applies_feature_messages() {
+        let mut frame = FeatureFrame::default();
+        assert!(apply(&mut frame, message("/lt/Flux", vec![0.5])));
+        assert!(apply(&mut frame, message("/lt/SpectrumBands", vec![0.1, 0.2])));
+        assert!(apply(&mut frame, message("/lt/SpectrumBands/1", vec![0.3])));
+        assert_eq!(frame.flux, 0.5);
+        assert_eq!(frame.spectrum_bands, vec![0.1, 0.3]);
+    }
+
+    #[test]
+    fn ignores_other_messages() {
+        let mut frame = FeatureFrame::default();
+        let ack = OscMessage { addr: "/lt/ack".to_owned(), args: vec![OscType::String("/lt/ctl/gain".to_owned())] };
+        assert!(!apply(&mut frame, ack));
+        assert!(!apply(&mut frame, message("/lt/error", vec![])));
+        assert!(!apply(&mut frame, message("/lt/heartbeat", vec![1.])));
+        assert!(!apply(&mut frame, message("/lt/sequence", vec![3.])));
+        assert!(!apply(&mut frame, message("/lt/frame", vec![0.1, 0.2])));
+        assert!(!apply(&mut frame, message("/lt/Unknown", vec![0.1, 0.2])));
+        assert!(!apply(&mut frame, message("/lt/Flux", vec![])));
+        assert!(!apply(&mut frame, message("/other/Flux", vec![0.5])));
+        assert_eq!(frame, FeatureFrame::default());
+    }
+
+    #[test]
+    fn counts_frames_once() {
+        let mut counter = FrameCounter::default();
+        assert_eq!(counter.bundle(), 0);
+        assert_eq!(counter.bundle(), 1);
+        // One message per feature, the frame ends when an address repeats
+        assert_eq!(counter.message("/lt/Flux"), 2);
+        assert_eq!(counter.message("/lt/ZCR"), 2);
+        assert_eq!(counter.message("/lt/SpectrumBands"), 2);
+        assert_eq!(counter.message("/lt/Flux"), 3);
+        assert_eq!(counter.message("/lt/ZCR"), 3);
+        assert_eq!(counter.bundle(), 4);
+        assert_eq!(counter.message("/lt/ZCR"), 5);
+    }
+}
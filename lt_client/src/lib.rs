@@ -1 +1,3 @@
-pub mod client;
\ No newline at end of file
+pub mod client;
+pub mod scheduler;
+pub mod stats;
\ No newline at end of file
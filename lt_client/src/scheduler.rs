@@ -0,0 +1,106 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use lt_utilities::audio_features::{AtomicAudioFeatures, FeatureFrame};
+use lt_server::timetag::offset_time;
+
+/// Frames due further in the future are applied on arrival, the clocks of the machines disagree
+pub const MAX_SCHEDULE_WAIT: Duration = Duration::from_secs(2);
+
+/// Stores frames in `audio_features` at their capture time plus `offset_ms` milliseconds.
+/// Frames sent to the returned sender are expected in roughly the order they were captured
+pub fn start_scheduler(audio_features: Arc<AtomicAudioFeatures>, offset_ms: Arc<AtomicI64>) -> Sender<FeatureFrame> {
+    let (tx, rx) = mpsc::channel::<FeatureFrame>();
+
+    thread::spawn(move || {
+        let mut pending: VecDeque<FeatureFrame> = VecDeque::new();
+        loop {
+            let now = SystemTime::now();
+            let offset_ms = offset_ms.load(Ordering::Relaxed);
+            let wait = loop {
+                let Some(frame) = pending.front() else {
+                    break None;
+                };
+                match offset_time(frame.captured_at, offset_ms).duration_since(now) {
+                    Ok(wait) if !wait.is_zero() && wait <= MAX_SCHEDULE_WAIT => break Some(wait),
+                    _ => {
+                        if let Some(frame) = pending.pop_front() {
+                            audio_features.store(&frame);
+                        }
+                    }
+                }
+            };
+
+            let received = match wait {
+                Some(wait) => match rx.recv_timeout(wait) {
+                    Ok(frame) => frame,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                },
+                None => match rx.recv() {
+                    Ok(frame) => frame,
+                    Err(_) => break,
+                },
+            };
+            let index = pending.partition_point(|frame| frame.captured_at <= received.captured_at);
+            pending.insert(index, received);
+        }
+    });
+
+    tx
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    fn frame(flux: f32, captured_at: SystemTime) -> FeatureFrame {
+        FeatureFrame { flux, captured_at, ..Default::default() }
+    }
+
+    fn wait_for(audio_features: &AtomicAudioFeatures, sequence: u64) -> Duration {
+        let started = Instant::now();
+        while audio_features.sequence() < sequence && started.elapsed() < Duration::from_secs(2) {
+            thread::sleep(Duration::from_millis(5));
+        }
+        started.elapsed()
+    }
+
+    #[test]
+    fn applies_frames_at_their_time_plus_the_offset() {
+        let audio_features = Arc::new(AtomicAudioFeatures::default());
+        let tx = start_scheduler(audio_features.clone(), Arc::new(AtomicI64::new(200)));
+        tx.send(frame(0.5, SystemTime::now())).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(audio_features.sequence(), 0);
+        wait_for(&audio_features, 1);
+        assert_eq!(audio_features.snapshot().flux, 0.5);
+    }
+
+    #[test]
+    fn applies_late_and_far_future_frames_on_arrival() {
+        let audio_features = Arc::new(AtomicAudioFeatures::default());
+        let tx = start_scheduler(audio_features.clone(), Arc::new(AtomicI64::new(-100)));
+        tx.send(frame(0.25, SystemTime::now())).unwrap();
+        assert!(wait_for(&audio_features, 1) < Duration::from_millis(100));
+        tx.send(frame(0.5, SystemTime::now() + MAX_SCHEDULE_WAIT * 2)).unwrap();
+        assert!(wait_for(&audio_features, 2) < Duration::from_millis(100));
+        assert_eq!(audio_features.snapshot().flux, 0.5);
+    }
+
+    #[test]
+    fn applies_frames_in_capture_order() {
+        let audio_features = Arc::new(AtomicAudioFeatures::default());
+        let tx = start_scheduler(audio_features.clone(), Arc::new(AtomicI64::new(100)));
+        let now = SystemTime::now();
+        tx.send(frame(2., now + Duration::from_millis(20))).unwrap();
+        tx.send(frame(1., now)).unwrap();
+        wait_for(&audio_features, 2);
+        assert_eq!(audio_features.snapshot().flux, 2.);
+    }
+}
@@ -0,0 +1,208 @@
+use std::time::{Duration, SystemTime};
+
+/// Number of recent sequence numbers remembered to tell duplicates from late packets
+const SEQUENCE_WINDOW: usize = 1024;
+
+/// Receive statistics of the feature bundles
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReceiveStats {
+    pub received: u64,
+    /// Bundles that never arrived, late bundles are no longer counted once they arrive
+    pub dropped: u64,
+    pub duplicated: u64,
+    /// Bundles that arrived after a later one
+    pub reordered: u64,
+    /// Smoothed variation of the transit time, as in RFC 3550
+    pub jitter: Duration,
+}
+
+/// Follows the sequence numbers and timetags of bundles
+pub struct SequenceTracker {
+    stats: ReceiveStats,
+    highest: Option<u32>,
+    seen: Vec<Option<u32>>,
+    /// Timetag of the previous in order bundle
+    last_sent: Option<SystemTime>,
+    /// Transit time of the previous in order bundle, in seconds
+    last_transit: Option<f64>,
+    jitter: f64,
+}
+
+impl Default for SequenceTracker {
+    fn default() -> Self {
+        Self {
+            stats: ReceiveStats::default(),
+            highest: None,
+            seen: vec![None; SEQUENCE_WINDOW],
+            last_sent: None,
+            last_transit: None,
+            jitter: 0.0,
+        }
+    }
+}
+
+impl SequenceTracker {
+    /// Forgets the sequence of the previous server and keeps the counts,
+    /// for when a different server starts sending
+    pub fn reset(&mut self) {
+        self.highest = None;
+        self.last_sent = None;
+        self.last_transit = None;
+    }
+
+    pub fn stats(&self) -> ReceiveStats {
+        ReceiveStats { jitter: Duration::from_secs_f64(self.jitter), ..self.stats }
+    }
+
+    /// Records a bundle sent at `sent_at` that arrived at `received_at`
+    pub fn record(&mut self, sequence: u32, sent_at: SystemTime, received_at: SystemTime) {
+        let slot = sequence as usize % SEQUENCE_WINDOW;
+        if self.seen[slot] == Some(sequence) {
+            self.stats.duplicated += 1;
+            return;
+        }
+        self.stats.received += 1;
+
+        let Some(highest) = self.highest else {
+            self.restart(sequence, sent_at, received_at);
+            return;
+        };
+
+        // Serial number arithmetic, so wrapping around is not a jump
+        let distance = sequence.wrapping_sub(highest) as i32;
+        if distance.unsigned_abs() as usize >= SEQUENCE_WINDOW {
+            // The server restarted or too much was lost to tell
+            self.restart(sequence, sent_at, received_at);
+            return;
+        }
+
+        self.seen[slot] = Some(sequence);
+        if distance > 0 {
+            self.stats.dropped += distance as u64 - 1;
+            self.highest = Some(sequence);
+            self.update_jitter(sent_at, received_at);
+        } else {
+            self.stats.reordered += 1;
+            self.stats.dropped = self.stats.dropped.saturating_sub(1);
+        }
+    }
+
+    fn restart(&mut self, sequence: u32, sent_at: SystemTime, received_at: SystemTime) {
+        self.seen.iter_mut().for_each(|seen| *seen = None);
+        self.seen[sequence as usize % SEQUENCE_WINDOW] = Some(sequence);
+        self.highest = Some(sequence);
+        self.last_sent = None;
+        self.last_transit = None;
+        self.update_jitter(sent_at, received_at);
+    }
+
+    fn update_jitter(&mut self, sent_at: SystemTime, received_at: SystemTime) {
+        // Destinations with a fixed rate resend the last frame with its timetag when no new one was analyzed,
+        // its transit time grows by the time it was held
+        if self.last_sent == Some(sent_at) {
+            return;
+        }
+        self.last_sent = Some(sent_at);
+        let transit = match received_at.duration_since(sent_at) {
+            Ok(transit) => transit.as_secs_f64(),
+            Err(e) => -e.duration().as_secs_f64(),
+        };
+        if let Some(last_transit) = self.last_transit {
+            self.jitter += ((transit - last_transit).abs() - self.jitter) / 16.0;
+        }
+        self.last_transit = Some(transit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+
+    fn record_all(tracker: &mut SequenceTracker, sequences: &[u32]) {
+        sequences.iter().for_each(|sequence| tracker.record(*sequence, UNIX_EPOCH, UNIX_EPOCH));
+    }
+
+    #[test]
+    fn counts_in_order_bundles() {
+        let mut tracker = SequenceTracker::default();
+        record_all(&mut tracker, &[0, 1, 2, 3]);
+        assert_eq!(tracker.stats(), ReceiveStats { received: 4, ..Default::default() });
+    }
+
+    #[test]
+    fn counts_gaps_as_dropped() {
+        let mut tracker = SequenceTracker::default();
+        record_all(&mut tracker, &[0, 1, 4, 5]);
+        assert_eq!(tracker.stats().dropped, 2);
+        assert_eq!(tracker.stats().received, 4);
+    }
+
+    #[test]
+    fn late_bundles_are_reordered_not_dropped() {
+        let mut tracker = SequenceTracker::default();
+        record_all(&mut tracker, &[0, 2, 1, 3]);
+        let stats = tracker.stats();
+        assert_eq!((stats.received, stats.dropped, stats.reordered), (4, 0, 1));
+    }
+
+    #[test]
+    fn counts_duplicates_once() {
+        let mut tracker = SequenceTracker::default();
+        record_all(&mut tracker, &[0, 1, 1, 2, 1]);
+        let stats = tracker.stats();
+        assert_eq!((stats.received, stats.duplicated, stats.reordered), (3, 2, 0));
+    }
+
+    #[test]
+    fn wraps_around() {
+        let mut tracker = SequenceTracker::default();
+        record_all(&mut tracker, &[u32::MAX - 1, u32::MAX, 0, 1]);
+        let stats = tracker.stats();
+        assert_eq!((stats.received, stats.dropped, stats.reordered), (4, 0, 0));
+    }
+
+    #[test]
+    fn restarts_after_large_jumps() {
+        let mut tracker = SequenceTracker::default();
+        record_all(&mut tracker, &[5000, 5001, 0, 1]);
+        let stats = tracker.stats();
+        assert_eq!((stats.received, stats.dropped, stats.reordered), (4, 0, 0));
+    }
+
+    #[test]
+    fn reset_keeps_counts() {
+        let mut tracker = SequenceTracker::default();
+        record_all(&mut tracker, &[0, 2]);
+        tracker.reset();
+        record_all(&mut tracker, &[100, 101]);
+        assert_eq!((tracker.stats().received, tracker.stats().dropped), (4, 1));
+    }
+
+    #[test]
+    fn measures_jitter_of_transit_times() {
+        let mut tracker = SequenceTracker::default();
+        let sent_at = |sequence: u32| UNIX_EPOCH + Duration::from_millis(sequence as u64 * 10);
+        tracker.record(0, sent_at(0), sent_at(0) + Duration::from_millis(5));
+        tracker.record(1, sent_at(1), sent_at(1) + Duration::from_millis(5));
+        assert_eq!(tracker.stats().jitter, Duration::ZERO);
+        // 16 ms of extra transit adds a sixteenth of it
+        tracker.record(2, sent_at(2), sent_at(2) + Duration::from_millis(21));
+        assert_eq!(tracker.stats().jitter, Duration::from_millis(1));
+    }
+
+    #[test]
+    fn repeated_frames_are_not_jitter() {
+        let mut tracker = SequenceTracker::default();
+        let sent_at = UNIX_EPOCH + Duration::from_secs(1);
+        tracker.record(0, sent_at, sent_at + Duration::from_millis(5));
+        // A fixed rate destination sending the same frame again 20 and 40 ms later
+        tracker.record(1, sent_at, sent_at + Duration::from_millis(25));
+        tracker.record(2, sent_at, sent_at + Duration::from_millis(45));
+        let next = sent_at + Duration::from_millis(50);
+        tracker.record(3, next, next + Duration::from_millis(5));
+        assert_eq!(tracker.stats().jitter, Duration::ZERO);
+        assert_eq!(tracker.stats().received, 4);
+    }
+}
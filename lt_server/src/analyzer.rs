@@ -1,26 +1,78 @@
 use core::f32;
-use std::{f32::consts::PI, ops::Range}; 
-use lt_utilities::audio_features::{AtomicAudioFeatures, Features};
-use realfft::{num_traits::Signed, RealFftPlanner};
+use std::{collections::BTreeMap, ops::Range, sync::{atomic::{AtomicU32, AtomicU64, Ordering}, Arc, Mutex}, time::Duration};
+use lt_utilities::audio_features::{AtomicAudioFeatures, FeatureFrame};
+use lt_utilities::atomic_float::FeatureDescriptor;
+use lt_utilities::extractor::{AnalysisFrame, ExtractorRegistry};
+use realfft::RealFftPlanner;
 use rayon::prelude::*;
 
 use lt_utilities::ArcMutex;
 
-const LOW_RANGE: Range<f32> = 0.0..250.; // Hz
-const MID_RANGE: Range<f32> = 250.0..4000.; // Hz
-const HIGH_RANGE: Range<f32> = 4000.0..20000.; // Hz 
+use crate::extractors::default_registry;
+use crate::timetag::MonotonicClock;
+
 // const FLUX_BUFF_SIZE: usize = 256 * 16;
 
+/// Analysis settings that can be changed while a stream is running
+#[derive(Debug)]
+pub struct AnalyzerSettings {
+    gain: AtomicU32,
+    parameters: Mutex<BTreeMap<String, f32>>,
+    generation: AtomicU64,
+}
+
+impl AnalyzerSettings {
+    pub fn gain(&self) -> f32 {
+        f32::from_bits(self.gain.load(Ordering::Relaxed))
+    }
+
+    /// Sets the linear gain applied to samples before analysis
+    pub fn set_gain(&self, gain: f32) {
+        self.gain.store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Sets a parameter passed on to every extractor, such as `bands`
+    pub fn set_parameter(&self, name: &str, value: f32) {
+        if let Ok(mut parameters) = self.parameters.lock() {
+            parameters.insert(name.to_owned(), value);
+            self.generation.fetch_add(1, Ordering::Release);
+        }
+    }
+
+    pub fn parameters(&self) -> BTreeMap<String, f32> {
+        self.parameters.lock().map(|parameters| parameters.clone()).unwrap_or_default()
+    }
+
+    /// Changes every time a parameter is set
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+}
+
+impl Default for AnalyzerSettings {
+    fn default() -> Self {
+        Self {
+            gain: AtomicU32::new(1.0f32.to_bits()),
+            parameters: Mutex::new(BTreeMap::new()),
+            generation: AtomicU64::new(0),
+        }
+    }
+}
+
 pub struct Analyzer {
     fft_planner: ArcMutex<RealFftPlanner<f32>>, 
     channel_count: u16,
     sample_rate: u32,
-    last_frame_buffer: Vec<ArcMutex<Vec<f32>>>,
+    registry: ExtractorRegistry,
+    settings: Arc<AnalyzerSettings>,
+    applied_generation: Option<u64>,
+    clock: MonotonicClock,
+    frame_count: u64,
     //oss_envelope: Vec<f32>,
     pub audio_features: AtomicAudioFeatures,
 }
 
-fn compute_zcr(input: &[f32]) -> f32 {
+pub fn compute_zcr(input: &[f32]) -> f32 {
     let sign = |x: f32| {
         if x > 0. {
             1.
@@ -42,7 +94,7 @@ fn compute_zcr(input: &[f32]) -> f32 {
     zcr
 }
 
-fn compute_spectral_centroid(input: &[f32], freqs: &[f32]) -> f32 {
+pub fn compute_spectral_centroid(input: &[f32], freqs: &[f32]) -> f32 {
     let sum = input.iter().sum::<f32>();
     let spectral_centroid = if sum == 0. {
         0.
@@ -58,6 +110,10 @@ fn compute_spectral_centroid(input: &[f32], freqs: &[f32]) -> f32 {
 impl Analyzer {
 
     pub fn new(channel_count: u16, sample_rate: u32) -> Self { 
+        Self::with_registry(channel_count, sample_rate, default_registry())
+    }
+
+    pub fn with_registry(channel_count: u16, sample_rate: u32, registry: ExtractorRegistry) -> Self {
         if channel_count < 1 {
             panic!("Channel count must be greater than 0");
         }
@@ -66,27 +122,65 @@ impl Analyzer {
             fft_planner: ArcMutex!(RealFftPlanner::new()),
             channel_count,
             sample_rate,
-            last_frame_buffer: vec![ArcMutex!(Vec::new()); channel_count as usize],
+            registry,
+            settings: Arc::new(AnalyzerSettings::default()),
+            applied_generation: None,
+            clock: MonotonicClock::new(),
+            frame_count: 0,
             // oss_envelope: vec![0.0; FLUX_BUFF_SIZE],
             audio_features: AtomicAudioFeatures::default(),
         }
     }
 
-    pub fn feed_data(&mut self, data: &[f32]) {
+    pub fn features(&self) -> Vec<FeatureDescriptor> {
+        self.registry.features()
+    }
+
+    pub fn set_settings(&mut self, settings: Arc<AnalyzerSettings>) {
+        self.settings = settings;
+        self.applied_generation = None;
+    }
+
+    fn apply_parameters(&mut self) {
+        let generation = self.settings.generation();
+        if self.applied_generation == Some(generation) {
+            return;
+        }
+
+        let parameters = self.settings.parameters();
+        self.registry.iter_mut().for_each(|extractor| {
+            parameters.iter().for_each(|(name, value)| {
+                extractor.set_parameter(name, *value);
+            });
+        });
+        self.applied_generation = Some(generation);
+    }
+
+    pub fn feed_data(&mut self, data: &[f32]) -> FeatureFrame {
         assert!(self.channel_count > 0);
+        // The buffer started playing or recording one buffer length ago
+        let buffer_duration = Duration::from_secs_f64(data.len() as f64 / self.channel_count as f64 / self.sample_rate.max(1) as f64);
+        let captured_at = self.clock.now() - buffer_duration;
+        self.apply_parameters();
+        let gain = self.settings.gain();
         
         let channels: ArcMutex<Vec<Vec<f32>>> = ArcMutex!(Vec::new());
         (0..self.channel_count).collect::<Vec<u16>>().par_iter().for_each(|channel_index| {
-            let channel_data  = data.iter().skip((*channel_index) as usize).cloned().collect::<Vec<f32>>();
+            let channel_data  = data.iter().skip((*channel_index) as usize).map(|x| x * gain).collect::<Vec<f32>>();
             if let Ok(mut channels) = channels.lock() {
                 channels.push(channel_data);
             }
         });
+
+        // Per extractor, per value, per channel results
+        let mut channel_values: Vec<Vec<Vec<f32>>> = self.registry.iter().map(|extractor| {
+            let value_count = extractor.features().iter().map(|feature| feature.len()).sum();
+            vec![Vec::with_capacity(self.channel_count as usize); value_count]
+        }).collect();
         
         // TODO: Make proper multithreaded
-        let channel_lock = channels.lock();
-        let channel_features = if let Ok(channels) = channel_lock {
-            let result: Vec<Option<Features>> = channels.iter().enumerate() .map(|(channel_index, channel_data)| {
+        if let Ok(channels) = channels.lock() {
+            channels.iter().enumerate().for_each(|(channel_index, channel_data)| {
                 if let Ok(mut fft_planner) = self.fft_planner.lock() {          
 
                     // TODO: automatic gain correction?
@@ -109,8 +203,7 @@ impl Analyzer {
                             return 0.0;
                         }
 
-                        let z = a / size.sqrt(); // Normalization step
-                        z
+                        a / size.sqrt() // Normalization step
                     }).collect::<Vec<f32>>();
 
                     let broad_range_magnitudes_log_compressed = broad_range_magnitudes.iter().map(|x| {
@@ -119,56 +212,50 @@ impl Analyzer {
                     
                     //  https://www.ap.com/news/more-about-ffts (getting frequencies)
                     let bin_size = self.sample_rate as f32 / spectrum_vec.len() as f32;
-                    let freqs = &broad_range_magnitudes.iter().enumerate().map(|(i, &_)| bin_size * i as f32).collect::<Vec<f32>>();
-         
-                    let low_range_magnitudes = get_filtered_by_range(broad_range_magnitudes_log_compressed.as_slice(), freqs.as_slice(), LOW_RANGE);
-                    let mid_range_magnitudes = get_filtered_by_range(broad_range_magnitudes_log_compressed.as_slice(), freqs.as_slice(), MID_RANGE);
-                    let high_range_magnitudes = get_filtered_by_range(broad_range_magnitudes_log_compressed.as_slice(), freqs.as_slice(), HIGH_RANGE);
-
-                    let zcr = compute_zcr(channel_data);
-                    let spectral_centroid = compute_spectral_centroid(broad_range_magnitudes.as_slice(), freqs.as_slice());
-
-                    // Spectral flux
-                    let mut last_buf = self.last_frame_buffer.get(channel_index).unwrap().lock().unwrap();    
-                    let last_frame = last_buf.iter().cloned().collect::<Vec<f32>>();
-                    last_buf.clear();
-                    last_buf.extend(channel_data.iter().cloned());
-                    
-                    // check which vec is longer and cut it down to the length of the shorter one
-                    let broad_length = broad_range_magnitudes.len();
-                    let last_frame_length = last_frame.len();
-                    let broad_slice: &[f32] = &broad_range_magnitudes[..std::cmp::min(broad_length, last_frame_length)];
-                    let last_frame_slice: &[f32] = &last_frame[..std::cmp::min(broad_length, last_frame_length)];
-
-                    let flux = broad_slice.iter().enumerate().map(|(i, &x)| {
-                        (x - last_frame_slice[i]).powf(2.)
-                    }).sum::<f32>().sqrt();
-                    
-                    Some((
-                        compute_rms(&broad_range_magnitudes_log_compressed) / 2.0,
-                        compute_rms(&low_range_magnitudes) / 2.0,
-                        compute_rms(&mid_range_magnitudes) / 2.0,
-                        compute_rms(&high_range_magnitudes) / 2.0,
-                        zcr,
-                        spectral_centroid,
-                        flux,
-                    ))
-                } else {
-                    None
+                    let freqs = broad_range_magnitudes.iter().enumerate().map(|(i, &_)| bin_size * i as f32).collect::<Vec<f32>>();
+
+                    let frame = AnalysisFrame {
+                        channel_index,
+                        sample_rate: self.sample_rate,
+                        samples: channel_data,
+                        magnitudes: &broad_range_magnitudes,
+                        log_magnitudes: &broad_range_magnitudes_log_compressed,
+                        freqs: &freqs,
+                    };
+
+                    self.registry.iter_mut().zip(channel_values.iter_mut()).for_each(|(extractor, values)| {
+                        extractor.extract(&frame).into_iter().zip(values.iter_mut()).for_each(|(value, channel_results)| {
+                            channel_results.push(value);
+                        });
+                    });
                 }
-            }).collect();
-           result
-        } else {
-            vec![None]
+            });
+        }
+
+        let mut frame = FeatureFrame {
+            frame: self.frame_count,
+            captured_at,
+            sample_rate: self.sample_rate,
+            channel_count: self.channel_count,
+            ..Default::default()
         };
-        let channel_features: Vec<Features> = channel_features.into_iter().flatten().collect();
-        self.audio_features.broad_range_rms.set((channel_features.iter().map(|x| x.0).sum::<f32>() / self.channel_count as f32).clamp(0., 1.));
-        self.audio_features.low_range_rms.set((channel_features.iter().map(|x| x.1).sum::<f32>() / self.channel_count as f32).clamp(0., 1.));
-        self.audio_features.mid_range_rms.set((channel_features.iter().map(|x| x.2).sum::<f32>() / self.channel_count as f32).clamp(0., 1.));
-        self.audio_features.high_range_rms.set((channel_features.iter().map(|x| x.3).sum::<f32>() / self.channel_count as f32).clamp(0., 1.));
-        self.audio_features.spectral_centroid.set(channel_features.iter().map(|x| x.5).sum::<f32>() / self.channel_count as f32);
-        self.audio_features.zcr.set(channel_features.iter().map(|x| x.4).sum::<f32>() / self.channel_count as f32);
-        self.audio_features.flux.set(channel_features.iter().map(|x| x.6).sum::<f32>() / self.channel_count as f32);
+        self.frame_count += 1;
+
+        self.registry.iter().zip(channel_values.iter()).for_each(|(extractor, values)| {
+            let combined = values.iter().map(|channel_results| extractor.combine(channel_results)).collect::<Vec<f32>>();
+            let mut offset = 0;
+            extractor.features().into_iter().for_each(|feature| {
+                let end = (offset + feature.len()).min(combined.len());
+                if feature.is_array() {
+                    frame.set_array(&feature.name, combined[offset..end].to_vec());
+                } else if let Some(value) = combined.get(offset) {
+                    frame.set(&feature.name, *value);
+                }
+                offset = end;
+            });
+        });
+        self.audio_features.store(&frame);
+        frame
     }
 } 
 
@@ -211,3 +298,56 @@ pub fn get_normalized_mags(magnitudes: &[f32]) -> Vec<f32> {
         (x - minx) / (maxx - minx)
     }).collect::<Vec<f32>>()
 }
+
+#[cfg(test)]
+mod tests {
+    use lt_utilities::extractor::Extractor;
+
+    use super::*;
+
+    /// In house extractor reporting the channel it ran on and a parameter
+    struct ChannelIndex {
+        scale: f32,
+    }
+
+    impl Extractor for ChannelIndex {
+        fn features(&self) -> Vec<FeatureDescriptor> {
+            vec![FeatureDescriptor::scalar("Channel"), FeatureDescriptor::scalar("Scaled").with_len(2)]
+        }
+
+        fn extract(&mut self, frame: &AnalysisFrame) -> Vec<f32> {
+            let channel = frame.channel_index as f32;
+            vec![channel, channel * self.scale, self.scale]
+        }
+
+        fn set_parameter(&mut self, name: &str, value: f32) -> bool {
+            if name != "scale" {
+                return false;
+            }
+            self.scale = value;
+            true
+        }
+    }
+
+    #[test]
+    fn runs_registered_extractors() {
+        let mut registry = ExtractorRegistry::new();
+        registry.register(ChannelIndex { scale: 1. });
+        let mut analyzer = Analyzer::with_registry(2, 48000, registry);
+        assert_eq!(analyzer.features().len(), 2);
+
+        let frame = analyzer.feed_data(&[0.; 128]);
+        // Values of each channel are averaged
+        assert_eq!(frame.get("Channel"), Some(0.5));
+        assert_eq!(frame.get_array("Scaled"), Some([0.5, 1.].as_slice()));
+        assert_eq!((frame.frame, frame.channel_count, frame.sample_rate), (0, 2, 48000));
+        assert_eq!(analyzer.audio_features.get("Channel"), Some(0.5));
+
+        let settings = Arc::new(AnalyzerSettings::default());
+        settings.set_parameter("scale", 4.);
+        analyzer.set_settings(settings);
+        let frame = analyzer.feed_data(&[0.; 128]);
+        assert_eq!(frame.get_array("Scaled"), Some([2., 4.].as_slice()));
+        assert_eq!(frame.frame, 1);
+    }
+}
@@ -0,0 +1,325 @@
+use std::{collections::BTreeMap, io, net::{Ipv4Addr, SocketAddr, UdpSocket}, sync::Arc, thread, time::{Duration, Instant}};
+use crossbeam::channel::{Sender, TryRecvError};
+use serde_json::Value;
+use socket2::{Domain, Protocol, Socket, Type};
+
+use lt_utilities::atomic_float::FeatureDescriptor;
+use lt_utilities::audio_features::FeatureFrame;
+
+use crate::destination::SendErrorLog;
+use crate::dmx::{load_config, parse_rate, resolve_ipv4, DmxMap};
+
+/// UDP port Art-Net nodes and controllers listen on
+pub const ARTNET_PORT: u16 = 6454;
+/// Frames per second of DMX512, the usual Art-Net refresh rate
+pub const DEFAULT_REFRESH_RATE: f32 = 44.;
+/// Highest 15 bit port address
+pub const MAX_UNIVERSE: u16 = 0x7FFF;
+
+const ARTNET_ID: &[u8; 8] = b"Art-Net\0";
+const PROTOCOL_VERSION: u16 = 14;
+const OP_POLL: u16 = 0x2000;
+const OP_POLL_REPLY: u16 = 0x2100;
+const OP_DMX: u16 = 0x5000;
+/// Size of an ArtPollReply, including the filler at the end
+const POLL_REPLY_SIZE: usize = 239;
+/// Universes described by one ArtPollReply
+const PORTS_PER_REPLY: usize = 4;
+/// StController, a device sending DMX onto the network
+const STYLE_CONTROLLER: u8 = 0x01;
+
+/// ArtDmx packet carrying the values of one universe, `sequence` 0 disables reordering checks
+pub fn art_dmx(universe: u16, sequence: u8, data: &[u8]) -> Vec<u8> {
+    // Receivers expect an even length
+    let length = (data.len() + data.len() % 2).min(512);
+    let mut packet = Vec::with_capacity(18 + length);
+    packet.extend_from_slice(ARTNET_ID);
+    packet.extend_from_slice(&OP_DMX.to_le_bytes());
+    packet.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+    packet.push(sequence);
+    // Physical input port, informational only
+    packet.push(0);
+    // SubUni then Net, the low and high bytes of the port address
+    packet.extend_from_slice(&universe.to_le_bytes());
+    packet.extend_from_slice(&(length as u16).to_be_bytes());
+    packet.extend_from_slice(&data[..data.len().min(length)]);
+    packet.resize(18 + length, 0);
+    packet
+}
+
+/// Whether a packet is an ArtPoll, asking every node and controller to reply
+pub fn is_art_poll(packet: &[u8]) -> bool {
+    packet.len() >= 12 && packet.starts_with(ARTNET_ID) && u16::from_le_bytes([packet[8], packet[9]]) == OP_POLL
+}
+
+/// ArtPollReply describing up to four universes sharing the same net and sub-net as inputs of this controller.
+/// `bind_index` numbers the replies when more universes are sent
+pub fn art_poll_reply(ip: Ipv4Addr, name: &str, universes: &[u16], bind_index: u8) -> Vec<u8> {
+    let mut packet = vec![0u8; POLL_REPLY_SIZE];
+    packet[..8].copy_from_slice(ARTNET_ID);
+    packet[8..10].copy_from_slice(&OP_POLL_REPLY.to_le_bytes());
+    packet[10..14].copy_from_slice(&ip.octets());
+    packet[14..16].copy_from_slice(&ARTNET_PORT.to_le_bytes());
+    let universes = &universes[..universes.len().min(PORTS_PER_REPLY)];
+    if let Some(universe) = universes.first() {
+        packet[18] = (universe >> 8) as u8 & 0x7F;
+        packet[19] = (universe >> 4) as u8 & 0x0F;
+    }
+    // Indicators normal, addresses set by the network
+    packet[23] = 0xE0;
+    let copy_name = |packet: &mut [u8], start: usize, length: usize| {
+        // Null terminated
+        let bytes = &name.as_bytes()[..name.len().min(length - 1)];
+        packet[start..start + bytes.len()].copy_from_slice(bytes);
+    };
+    copy_name(&mut packet, 26, 18);
+    copy_name(&mut packet, 44, 64);
+    packet[173] = universes.len() as u8;
+    for (port, universe) in universes.iter().enumerate() {
+        // Can input DMX512 onto the network
+        packet[174 + port] = 0x40;
+        // Data received
+        packet[178 + port] = 0x80;
+        packet[186 + port] = *universe as u8 & 0x0F;
+    }
+    packet[200] = STYLE_CONTROLLER;
+    packet[207..211].copy_from_slice(&ip.octets());
+    packet[211] = bind_index;
+    // Supports 15 bit port addresses
+    packet[212] = 0x08;
+    packet
+}
+
+/// Universes, rate and targets of an Art-Net output
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArtNetConfig {
+    pub map: DmxMap,
+    /// ArtDmx packets sent per second and universe
+    pub rate: f32,
+    /// Nodes to send to, the IPv4 broadcast address by default
+    pub targets: Vec<SocketAddr>,
+}
+
+impl ArtNetConfig {
+    /// Parses a DMX config, see `DmxMap::from_json`, with an optional `rate` and `targets`
+    /// given as `host` or `host:port`, such as `{"rate": 30, "targets": ["10.0.0.50"], "fixtures": [...]}`
+    pub fn from_json(config: &Value, features: &[FeatureDescriptor]) -> Result<Self, String> {
+        let map = DmxMap::from_json(config, features)?;
+        if let Some(universe) = map.universes().into_iter().find(|universe| *universe > MAX_UNIVERSE) {
+            return Err(format!("Universe {} is above the highest Art-Net universe {}", universe, MAX_UNIVERSE));
+        }
+        let rate = parse_rate(config, DEFAULT_REFRESH_RATE)?;
+        let targets = match config.get("targets").and_then(Value::as_array) {
+            None => vec![SocketAddr::new(Ipv4Addr::BROADCAST.into(), ARTNET_PORT)],
+            Some(targets) => targets.iter().map(|target| {
+                let target = target.as_str().ok_or_else(|| "Targets must be strings".to_owned())?;
+                resolve_ipv4(target, ARTNET_PORT).ok_or_else(|| format!("No IPv4 address found for {}", target))
+            }).collect::<Result<Vec<SocketAddr>, String>>()?,
+        };
+        Ok(Self { map, rate, targets })
+    }
+
+    pub fn load(path: &str, features: &[FeatureDescriptor]) -> Result<Self, String> {
+        Self::from_json(&load_config(path)?, features)
+    }
+}
+
+/// Sends features as DMX to Art-Net nodes and answers ArtPoll
+pub struct ArtNetSender {
+    socket: Arc<UdpSocket>,
+    config: ArtNetConfig,
+    /// Short and long name in ArtPollReply
+    name: String,
+}
+
+impl ArtNetSender {
+    /// Listens for ArtPoll on `port`, `ARTNET_PORT` unless another program on this machine uses it
+    pub fn new(config: ArtNetConfig, port: u16) -> io::Result<Self> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        socket.set_broadcast(true)?;
+        socket.bind(&SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port).into())?;
+        Ok(Self { socket: Arc::new(socket.into()), config, name: "LunaTech".to_owned() })
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_owned();
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Sends the latest frame at the configured rate and returns the sender frames are read from,
+    /// see `LunaTechServer::add_frame_listener`
+    pub fn start(self) -> Sender<FeatureFrame> {
+        let (tx, rx) = crossbeam::channel::unbounded::<FeatureFrame>();
+
+        let universes = self.config.map.universes();
+        let (socket, name) = (self.socket.clone(), self.name.clone());
+        thread::spawn(move || {
+            let mut buf = [0u8; 1024];
+            while let Ok((size, sender)) = socket.recv_from(&mut buf) {
+                if !is_art_poll(&buf[..size]) {
+                    continue;
+                }
+                let ip = local_ip_for(sender).unwrap_or(Ipv4Addr::UNSPECIFIED);
+                for (bind_index, ports) in reply_groups(&universes).iter().enumerate() {
+                    let _ = socket.send_to(&art_poll_reply(ip, &name, ports, bind_index as u8 + 1), sender);
+                }
+            }
+        });
+
+        thread::spawn(move || {
+            let period = Duration::from_secs_f32(1. / self.config.rate);
+            let mut sequences = BTreeMap::<u16, u8>::new();
+            let mut latest = None;
+            let mut next_send = Instant::now();
+            let mut errors = SendErrorLog::default();
+            loop {
+                loop {
+                    match rx.try_recv() {
+                        Ok(frame) => latest = Some(frame),
+                        Err(TryRecvError::Empty) => break,
+                        // Every server sending frames is gone
+                        Err(TryRecvError::Disconnected) => return,
+                    }
+                }
+
+                for (universe, data) in self.config.map.render(latest.as_ref()) {
+                    // Counts from 1, 0 means sequencing is off
+                    let sequence = sequences.entry(universe).or_insert(0);
+                    *sequence = sequence.checked_add(1).unwrap_or(1);
+                    let packet = art_dmx(universe, *sequence, &data);
+                    for target in &self.config.targets {
+                        if let Err(e) = self.socket.send_to(&packet, target) {
+                            errors.print("Art-Net", target, &e);
+                        }
+                    }
+                }
+
+                next_send += period;
+                let now = Instant::now();
+                if next_send > now {
+                    thread::sleep(next_send - now);
+                } else {
+                    next_send = now;
+                }
+            }
+        });
+
+        tx
+    }
+}
+
+/// Universes in groups that fit one ArtPollReply, which share a net and sub-net
+fn reply_groups(universes: &[u16]) -> Vec<Vec<u16>> {
+    let mut groups: Vec<Vec<u16>> = Vec::new();
+    for universe in universes {
+        match groups.last_mut() {
+            Some(group) if group.len() < PORTS_PER_REPLY && group[0] >> 4 == universe >> 4 => group.push(*universe),
+            _ => groups.push(vec![*universe]),
+        }
+    }
+    groups
+}
+
+/// Local address packets to `peer` are sent from
+fn local_ip_for(peer: SocketAddr) -> Option<Ipv4Addr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect(peer).ok()?;
+    match socket.local_addr().ok()?.ip() {
+        std::net::IpAddr::V4(ip) => Some(ip),
+        std::net::IpAddr::V6(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn art_dmx_layout() {
+        let packet = art_dmx(0x1234, 7, &[1, 2, 3]);
+        assert_eq!(&packet[..8], b"Art-Net\0");
+        // OpCode low byte first, protocol version high byte first
+        assert_eq!(&packet[8..12], &[0x00, 0x50, 0, 14]);
+        assert_eq!(packet[12], 7);
+        assert_eq!(packet[13], 0);
+        // SubUni then Net
+        assert_eq!(&packet[14..16], &[0x34, 0x12]);
+        // Odd lengths are padded to an even length, high byte first
+        assert_eq!(&packet[16..18], &[0, 4]);
+        assert_eq!(&packet[18..], &[1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn art_dmx_carries_at_most_a_universe() {
+        let packet = art_dmx(0, 1, &[9; 600]);
+        assert_eq!(&packet[16..18], &[0x02, 0x00]);
+        assert_eq!(packet.len(), 18 + 512);
+    }
+
+    #[test]
+    fn recognizes_art_poll() {
+        let mut poll = ARTNET_ID.to_vec();
+        poll.extend_from_slice(&[0x00, 0x20, 0, 14, 0, 0]);
+        assert!(is_art_poll(&poll));
+        assert!(!is_art_poll(&art_dmx(0, 0, &[0; 2])));
+        assert!(!is_art_poll(&poll[..10]));
+        assert!(!is_art_poll(b"Art-Nat\0\x00\x20\x00\x0e"));
+    }
+
+    #[test]
+    fn art_poll_reply_layout() {
+        let ip = Ipv4Addr::new(10, 0, 0, 2);
+        let packet = art_poll_reply(ip, "A name longer than the short name field", &[0x0123, 0x0125, 0x012F, 0x0120, 0x0121], 2);
+        assert_eq!(packet.len(), 239);
+        assert_eq!(&packet[..8], b"Art-Net\0");
+        assert_eq!(&packet[8..10], &[0x00, 0x21]);
+        assert_eq!(&packet[10..14], &[10, 0, 0, 2]);
+        assert_eq!(&packet[14..16], &[0x36, 0x19]);
+        // Net and sub-net of the first universe
+        assert_eq!((packet[18], packet[19]), (0x01, 0x02));
+        // Short and long names are null terminated
+        assert_eq!(&packet[26..43], b"A name longer tha");
+        assert_eq!(packet[43], 0);
+        assert!(packet[44..108].starts_with(b"A name longer than the short name field\0"));
+        // Four ports at most, the port addresses' low nibbles
+        assert_eq!(packet[173], 4);
+        assert_eq!(&packet[174..178], &[0x40; 4]);
+        assert_eq!(&packet[178..182], &[0x80; 4]);
+        assert_eq!(&packet[186..190], &[0x3, 0x5, 0xF, 0x0]);
+        assert_eq!(packet[200], STYLE_CONTROLLER);
+        assert_eq!(&packet[207..211], &[10, 0, 0, 2]);
+        assert_eq!(packet[211], 2);
+        assert_eq!(packet[212], 0x08);
+    }
+
+    #[test]
+    fn groups_universes_by_sub_net() {
+        assert_eq!(reply_groups(&[0, 1, 2, 3, 4, 16, 17, 0x7FFF]), vec![vec![0, 1, 2, 3], vec![4], vec![16, 17], vec![0x7FFF]]);
+        assert!(reply_groups(&[]).is_empty());
+    }
+
+    #[test]
+    fn parses_configs() {
+        let config = serde_json::json!({"rate": 30, "targets": ["127.0.0.1", "127.0.0.1:7000"], "channels": [{"universe": 1, "channel": 1, "value": 255}]});
+        let config = ArtNetConfig::from_json(&config, lt_utilities::audio_features::FEATURES).unwrap();
+        assert_eq!(config.rate, 30.);
+        assert_eq!(config.targets, vec!["127.0.0.1:6454".parse().unwrap(), "127.0.0.1:7000".parse().unwrap()]);
+
+        let defaults = serde_json::json!({"channels": [{"universe": 1, "channel": 1, "value": 255}]});
+        let defaults = ArtNetConfig::from_json(&defaults, lt_utilities::audio_features::FEATURES).unwrap();
+        assert_eq!(defaults.rate, DEFAULT_REFRESH_RATE);
+        assert_eq!(defaults.targets, vec![SocketAddr::new(Ipv4Addr::BROADCAST.into(), ARTNET_PORT)]);
+
+        for invalid in [
+            serde_json::json!({"rate": 1e-40, "channels": [{"universe": 1, "channel": 1, "value": 255}]}),
+            serde_json::json!({"channels": [{"universe": 0x8000, "channel": 1, "value": 255}]}),
+            serde_json::json!({"targets": [1], "channels": [{"universe": 1, "channel": 1, "value": 255}]}),
+        ] {
+            assert!(ArtNetConfig::from_json(&invalid, lt_utilities::audio_features::FEATURES).is_err(), "{}", invalid);
+        }
+    }
+}
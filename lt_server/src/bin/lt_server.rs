@@ -1,5 +1,8 @@
 use std::env;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 use clap::{value_parser, ArgAction};
 use colored::Colorize;
 use cpal::traits::HostTrait;
@@ -7,14 +10,37 @@ use egui::Stroke;
 use egui::TextFormat;
 use egui::{Button, Grid, Label, RichText, Vec2};
 
-use lt_server::device_monitor::DeviceMonitor;
+use lt_utilities::atomic_float::FeatureDescriptor;
+use lt_utilities::audio_features::FeatureFrame;
+use lt_utilities::namespace::{Namespace, DEFAULT_PREFIX};
+
+use lt_server::analyzer::AnalyzerSettings;
+use lt_server::artnet::{ArtNetConfig, ArtNetSender, ARTNET_PORT};
+use lt_server::control::{ControlCommand, ControlRequest, ControlServer};
+use lt_server::device_monitor::{DeviceMonitor, RegistryFactory};
+use lt_server::discovery::{Advertisement, ServiceAdvertiser};
 use lt_server::device_monitor;
+use lt_server::extractors::default_registry;
+use lt_server::heartbeat::ServerState;
+use lt_server::layout::MessageLayout;
+use lt_server::led::{load_strips, LedSender};
+use lt_server::mapping::{load_mappings, Mapping};
+use lt_server::network;
+use lt_server::network::{find_interface, MulticastConfig, DEFAULT_MULTICAST_TTL};
+use lt_server::oscquery::OscQueryServer;
+use lt_server::output_rate::{Aggregation, OutputRate};
+use lt_server::prompts::find_device_by_name;
+use lt_server::sacn::{SacnConfig, SacnSender};
 use lt_server::server;
 use lt_server::server::LunaTechServer;
+use lt_server::stream::{StreamConnections, StreamServer};
+use lt_server::subscription::UdpSubscribers;
+use lt_server::websocket::WebSocketServer;
 
 const DEFAULT_SAMPLE_RATE: u32 = 44100;
 const DEFAULT_BUFFER_SIZE: u32 = 1024;
 const DEFAULT_PORT: u16 = 3000;
+const DEFAULT_INSTANCE_NAME: &str = "LunaTech";
 
 #[derive(PartialEq)]
 enum LTServerState {
@@ -28,6 +54,44 @@ struct LTServerOpts {
     port: u16,
     headless: bool,
     input_mode: bool,
+    broadcast: bool,
+    /// Unicast targets, see `parse_target`
+    targets: Vec<String>,
+    offset_ms: i64,
+    hold: bool,
+    rate: Option<f32>,
+    aggregation: Aggregation,
+    multicast: Option<MulticastConfig>,
+    interface: Option<String>,
+    broadcast_addr: Option<Ipv4Addr>,
+    /// Device to monitor instead of the default one
+    device_name: Option<String>,
+    /// Shared with every device monitor so control changes survive restarts
+    settings: Arc<AnalyzerSettings>,
+    /// Builds the extractors of every device monitor
+    registry_factory: RegistryFactory,
+    /// Features of the registry the analyzer runs, advertised, described and mappable
+    features: Vec<FeatureDescriptor>,
+    /// Name the server is advertised and described as
+    instance_name: String,
+    advertise: bool,
+    oscquery_port: Option<u16>,
+    /// Advertisement of the running server, dropping it removes the advertisement
+    advertiser: Option<ServiceAdvertiser>,
+    /// Frames sent here are streamed to WebSocket clients and output drivers
+    frame_listeners: Vec<crossbeam::channel::Sender<FeatureFrame>>,
+    /// Kept until exit, dropping it terminates the sACN streams
+    sacn_sender: Option<SacnSender>,
+    stream_port: Option<u16>,
+    /// Clients of the stream server, kept across restarts
+    streams: Option<StreamConnections>,
+    /// Clients subscribed over the control port, kept across restarts
+    subscribers: Option<Arc<UdpSubscribers>>,
+    /// Addresses features are sent on
+    namespace: Namespace,
+    layout: MessageLayout,
+    /// Features sent to other programs, see `--mappings`
+    mappings: Vec<Mapping>,
     lt_server_state: LTServerState,
 }
 
@@ -38,14 +102,109 @@ fn start_lt_server(lt_server_opts: &mut LTServerOpts, lt_server: &mut Option<Lun
     }
 
     let host = cpal::default_host();
-    let device = if lt_server_opts.input_mode {
+    let named_device = lt_server_opts.device_name.as_ref().and_then(|name| {
+        let device = find_device_by_name(&host, name);
+        if device.is_none() {
+            println!("Device {} not found, using the default device", name.bold().red());
+        }
+        device
+    });
+    let device = named_device.or_else(|| if lt_server_opts.input_mode {
         host.default_input_device()
     } else {
         host.default_output_device()
-    }.expect("Failed to get default device");
+    }).expect("Failed to get default device");
 
     *lt_server = Some(LunaTechServer::new(lt_server_opts.port));
-    *lt_device_monitor = Some(DeviceMonitor::new(lt_server_opts.sample_rate, lt_server_opts.buffer_size));
+    if let Some(lt_server) = lt_server {
+        if let Some(interface) = &lt_server_opts.interface {
+            match find_interface(interface).and_then(|interface| lt_server.set_interface(interface)) {
+                Ok(_) => println!("Using interface {}", interface.bold().green()),
+                Err(e) => println!("Failed to use interface {}: {}", interface.bold(), e.to_string().bold().red()),
+            }
+        }
+        if let Some(broadcast_addr) = lt_server_opts.broadcast_addr {
+            if let Err(e) = lt_server.set_broadcast_addr(broadcast_addr) {
+                println!("Failed to broadcast to {}: {}", broadcast_addr.to_string().bold(), e.to_string().bold().red());
+            }
+        }
+        if let Err(e) = lt_server.set_broadcast(lt_server_opts.broadcast) {
+            println!("Failed to broadcast: {}", e.to_string().bold().red());
+        }
+        let mut target_settings = Vec::new();
+        for target in &lt_server_opts.targets {
+            let (addr, offset_ms, rate) = match parse_target(target) {
+                Ok(target) => target,
+                Err(e) => {
+                    println!("Invalid target {}: {}", target.bold(), e.bold().red());
+                    continue;
+                }
+            };
+            match lt_server.add_target(addr) {
+                Ok(destination) => target_settings.push((destination, offset_ms, rate)),
+                Err(e) => println!("Invalid target {}: {}", target.bold(), e.to_string().bold().red()),
+            }
+        }
+        if let Some(multicast) = &lt_server_opts.multicast {
+            if let Err(e) = lt_server.set_multicast(multicast) {
+                println!("Failed to set multicast group {}: {}", multicast.group.to_string().bold(), e.to_string().bold().red());
+            }
+        }
+        lt_server.set_offset_ms(lt_server_opts.offset_ms);
+        let rate = lt_server_opts.rate.map(|hz| OutputRate::new(hz, lt_server_opts.aggregation));
+        match rate.map(|rate| rate.validate()) {
+            Some(Err(e)) => println!("{}", e.bold().red()),
+            _ => lt_server.set_rate(rate),
+        }
+        for (destination, offset_ms, rate) in target_settings {
+            if let Some(offset_ms) = offset_ms {
+                destination.set_offset_ms(offset_ms);
+            }
+            if let Some(hz) = rate {
+                destination.set_rate(Some(OutputRate::new(hz, lt_server_opts.aggregation)));
+            }
+        }
+        lt_server.destinations().iter().for_each(|destination| destination.set_hold(lt_server_opts.hold));
+        for frame_listener in &lt_server_opts.frame_listeners {
+            lt_server.add_frame_listener(frame_listener.clone());
+        }
+        if let Some(streams) = &lt_server_opts.streams {
+            lt_server.set_stream_connections(streams.clone());
+        }
+        if let Some(subscribers) = &lt_server_opts.subscribers {
+            lt_server.set_subscribers(subscribers.clone());
+        }
+        lt_server.set_namespace(lt_server_opts.namespace.clone());
+        lt_server.set_layout(lt_server_opts.layout);
+        match lt_server.add_mappings(&lt_server_opts.mappings) {
+            Ok(destinations) => destinations.iter().for_each(|destination| println!("Sending mapped features to {}", destination.to_string().bold().green())),
+            Err(e) => println!("Failed to add mapped targets: {}", e.to_string().bold().red()),
+        }
+        for destination in lt_server.destinations() {
+            println!("Sending to {}", destination.to_string().bold().green());
+        }
+    }
+    if lt_server_opts.advertise {
+        let advertisement = Advertisement {
+            multicast: lt_server_opts.multicast.as_ref().map(|multicast| multicast.group),
+            oscquery_port: lt_server_opts.oscquery_port,
+            stream_port: lt_server_opts.streams.as_ref().and(lt_server_opts.stream_port),
+            namespace: lt_server_opts.namespace.clone(),
+            layout: lt_server_opts.layout,
+            ..Advertisement::new(&lt_server_opts.instance_name, lt_server_opts.port, &lt_server_opts.features)
+        };
+        match ServiceAdvertiser::new(&advertisement) {
+            Ok(advertiser) => {
+                println!("Advertising as {}", lt_server_opts.instance_name.bold().green());
+                lt_server_opts.advertiser = Some(advertiser);
+            }
+            Err(e) => println!("Failed to advertise server: {}", e.to_string().bold().red()),
+        }
+    }
+    let mut device_monitor = DeviceMonitor::new(lt_server_opts.sample_rate, lt_server_opts.buffer_size);
+    device_monitor.set_settings(lt_server_opts.settings.clone());
+    device_monitor.set_registry_factory(lt_server_opts.registry_factory.clone());
+    *lt_device_monitor = Some(device_monitor);
     // Todo: Check if bounded is faster
     // Todo: Replace crossbeam channel with std
     let (tx, rx) = crossbeam::channel::unbounded();
@@ -67,11 +226,44 @@ fn start_lt_server(lt_server_opts: &mut LTServerOpts, lt_server: &mut Option<Lun
 
     lt_server_opts.lt_server_state = LTServerState::Running;
     println!("{}{} {}", "Luna".red().bold(), "Tech".purple().bold(), "server is now running".bold());
-    if let Some(lt_server) = lt_server {
+    if let (Some(lt_server), Some(device_monitor)) = (lt_server.as_ref(), lt_device_monitor.as_ref()) {
+        lt_server.set_device_info(device_monitor.device_name().unwrap_or_default(), device_monitor.sample_rate());
         lt_server.start_server();
     }
 }
 
+/// Splits a target given as `host:port[@offset_ms][/rate_hz]`, such as `10.0.0.5:3000@-40/44`
+fn parse_target(target: &str) -> Result<(&str, Option<i64>, Option<f32>), String> {
+    let (target, rate) = match target.rsplit_once('/') {
+        Some((target, rate)) => {
+            let rate = OutputRate::new(rate.parse::<f32>().map_err(|_| format!("Invalid rate {}", rate))?, Aggregation::Latest);
+            rate.validate()?;
+            (target, Some(rate.hz))
+        }
+        None => (target, None),
+    };
+    let (addr, offset_ms) = match target.rsplit_once('@') {
+        Some((addr, offset_ms)) => (addr, Some(offset_ms.parse::<i64>().map_err(|_| format!("Invalid offset {}", offset_ms))?)),
+        None => (target, None),
+    };
+    Ok((addr, offset_ms, rate))
+}
+
+/// Namespace given by `--prefix`, `--osc_instance` and `--address feature=address`
+fn parse_namespace(matches: &clap::ArgMatches, features: &[FeatureDescriptor]) -> Result<Namespace, String> {
+    let mut namespace = Namespace::new(matches.get_one::<String>("prefix").map(String::as_str).unwrap_or(DEFAULT_PREFIX));
+    namespace.set_instance(matches.get_one::<String>("osc_instance").map(String::as_str));
+    for address in matches.get_many::<String>("address").unwrap_or_default() {
+        let (name, addr) = address.split_once('=').ok_or_else(|| format!("Expected feature=address, got {}", address))?;
+        if !features.iter().any(|feature| feature.name == name) {
+            return Err(format!("Unknown feature {}", name));
+        }
+        namespace.set_override(name, addr);
+    }
+    namespace.validate(features)?;
+    Ok(namespace)
+}
+
 fn stop_lt_server(lt_server_opts: &mut LTServerOpts, lt_server: &mut Option<server::LunaTechServer>, device_monitor: &mut Option<device_monitor::DeviceMonitor>) {
     if lt_server_opts.lt_server_state == LTServerState::Stopped {
         println!("{}", "Server is already stopped, please start it first".bold().red());
@@ -81,11 +273,51 @@ fn stop_lt_server(lt_server_opts: &mut LTServerOpts, lt_server: &mut Option<serv
     if let Some(device_monitor) = device_monitor {
         device_monitor.stop_device_monitor();
     }
+    if let Some(lt_server) = lt_server {
+        lt_server.set_state(ServerState::Stopped);
+    }
+    lt_server_opts.advertiser = None;
 
     lt_server_opts.lt_server_state = LTServerState::Stopped;
     println!("{}{} {}", "Luna".red().bold(), "Tech".purple().bold(), "server is now stopped".bold()); 
 }
 
+/// Applies a control command that needs the server or device monitor, then replies to the sender
+fn handle_control_request(request: ControlRequest, lt_server_opts: &mut LTServerOpts, lt_server: &mut Option<LunaTechServer>, lt_device_monitor: &mut Option<DeviceMonitor>) {
+    let running = lt_server_opts.lt_server_state == LTServerState::Running;
+    match request.command {
+        ControlCommand::Start if running => request.reply.error("Server is already running"),
+        ControlCommand::Stop if !running => request.reply.error("Server is already stopped"),
+        ControlCommand::Start => {
+            start_lt_server(lt_server_opts, lt_server, lt_device_monitor);
+            if let Some(device_monitor) = lt_device_monitor {
+                device_monitor.start_device_monitor();
+            }
+            request.reply.ok();
+        }
+        ControlCommand::Stop => {
+            stop_lt_server(lt_server_opts, lt_server, lt_device_monitor);
+            request.reply.ok();
+        }
+        ControlCommand::Device(name) => {
+            if find_device_by_name(&cpal::default_host(), &name).is_none() {
+                request.reply.error("Device not found");
+                return;
+            }
+            lt_server_opts.device_name = Some(name);
+            if running {
+                stop_lt_server(lt_server_opts, lt_server, lt_device_monitor);
+                start_lt_server(lt_server_opts, lt_server, lt_device_monitor);
+                if let Some(device_monitor) = lt_device_monitor {
+                    device_monitor.start_device_monitor();
+                }
+            }
+            request.reply.ok();
+        }
+        _ => request.reply.error("Unknown command"),
+    }
+}
+
 fn main() {
     let matches = clap::Command::new("lunatech server")
         .version(env!("CARGO_PKG_VERSION"))
@@ -115,6 +347,202 @@ fn main() {
                 .action(ArgAction::Set)
                 .value_parser(value_parser!(u16))
         )
+        .arg(
+            clap::Arg::new("target")
+                .short('t')
+                .long("target")
+                .help("Also send to a unicast host:port, may be repeated. Append @ms to offset its timetags and /hz to send at a fixed rate")
+                .action(ArgAction::Append)
+        )
+        .arg(
+            clap::Arg::new("offset")
+                .long("offset")
+                .help("Milliseconds added to every timetag, negative values look ahead")
+                .action(ArgAction::Set)
+                .allow_negative_numbers(true)
+                .value_parser(value_parser!(i64))
+        )
+        .arg(
+            clap::Arg::new("hold")
+                .long("hold")
+                .help("Also hold packets back by positive offsets, for receivers that ignore timetags")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            clap::Arg::new("rate")
+                .long("rate")
+                .help("Send at a fixed rate in Hz instead of once per audio buffer")
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(f32))
+        )
+        .arg(
+            clap::Arg::new("aggregate")
+                .long("aggregate")
+                .help("Combine the buffers between fixed rate sends with latest, max or mean")
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(Aggregation))
+        )
+        .arg(
+            clap::Arg::new("no_broadcast")
+                .long("no_broadcast")
+                .help("Only send to targets instead of broadcasting")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            clap::Arg::new("multicast")
+                .short('m')
+                .long("multicast")
+                .help("Also send to an IPv4 or IPv6 multicast group")
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(IpAddr))
+        )
+        .arg(
+            clap::Arg::new("multicast_ttl")
+                .long("multicast_ttl")
+                .help("Sets the multicast TTL")
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(u32))
+        )
+        .arg(
+            clap::Arg::new("multicast_if")
+                .long("multicast_if")
+                .help("Local address of the interface to send IPv4 multicast on")
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(Ipv4Addr))
+        )
+        .arg(
+            clap::Arg::new("multicast_if_index")
+                .long("multicast_if_index")
+                .help("Index of the interface to send IPv6 multicast on")
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(u32))
+        )
+        .arg(
+            clap::Arg::new("no_multicast_loop")
+                .long("no_multicast_loop")
+                .help("Do not deliver multicast packets to this machine")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            clap::Arg::new("interface")
+                .long("interface")
+                .help("Name or address of the network interface to send on")
+                .action(ArgAction::Set)
+        )
+        .arg(
+            clap::Arg::new("broadcast_addr")
+                .long("broadcast_addr")
+                .help("Broadcast address to use, such as 10.0.0.255")
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(Ipv4Addr))
+        )
+        .arg(
+            clap::Arg::new("list_interfaces")
+                .long("list_interfaces")
+                .help("List network interfaces and exit")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            clap::Arg::new("device")
+                .short('d')
+                .long("device")
+                .help("Name of the device to monitor")
+                .action(ArgAction::Set)
+        )
+        .arg(
+            clap::Arg::new("control_port")
+                .short('c')
+                .long("control_port")
+                .help("Accept OSC control commands on this port")
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(u16))
+        )
+        .arg(
+            clap::Arg::new("oscquery_port")
+                .short('q')
+                .long("oscquery_port")
+                .help("Serve the OSCQuery namespace over HTTP on this port")
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(u16))
+        )
+        .arg(
+            clap::Arg::new("websocket_port")
+                .short('w')
+                .long("websocket_port")
+                .help("Stream features to WebSocket clients on this port")
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(u16))
+        )
+        .arg(
+            clap::Arg::new("stream_port")
+                .short('s')
+                .long("stream_port")
+                .help("Send features over TCP with SLIP framing to clients connecting on this port")
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(u16))
+        )
+        .arg(
+            clap::Arg::new("prefix")
+                .long("prefix")
+                .help("Address prefix features are sent under, /lt by default")
+                .action(ArgAction::Set)
+        )
+        .arg(
+            clap::Arg::new("osc_instance")
+                .long("osc_instance")
+                .help("Inserted after the prefix to tell several servers apart, as in /lt/stageA/Flux")
+                .action(ArgAction::Set)
+        )
+        .arg(
+            clap::Arg::new("address")
+                .long("address")
+                .help("Send a feature on its own address, given as feature=address, may be repeated")
+                .action(ArgAction::Append)
+        )
+        .arg(
+            clap::Arg::new("layout")
+                .long("layout")
+                .help("Send each frame as a bundle, as separate messages or as one frame message")
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(MessageLayout))
+        )
+        .arg(
+            clap::Arg::new("mappings")
+                .long("mappings")
+                .help("JSON file of features to send to other programs on their own addresses, scaled and clamped")
+                .action(ArgAction::Set)
+        )
+        .arg(
+            clap::Arg::new("artnet")
+                .long("artnet")
+                .help("JSON file of DMX fixtures and channels driven by features, sent over Art-Net")
+                .action(ArgAction::Set)
+        )
+        .arg(
+            clap::Arg::new("sacn")
+                .long("sacn")
+                .help("JSON file of DMX fixtures and channels driven by features, sent over sACN (E1.31)")
+                .action(ArgAction::Set)
+        )
+        .arg(
+            clap::Arg::new("leds")
+                .long("leds")
+                .help("JSON file of LED strips showing audio reactive patterns, sent over WLED realtime or DDP")
+                .action(ArgAction::Set)
+        )
+        .arg(
+            clap::Arg::new("name")
+                .short('n')
+                .long("name")
+                .help("Instance name the server is advertised as")
+                .action(ArgAction::Set)
+        )
+        .arg(
+            clap::Arg::new("no_advertise")
+                .long("no_advertise")
+                .help("Do not advertise the server over mDNS")
+                .action(ArgAction::SetTrue)
+        )
         .arg(
             clap::Arg::new("headless")
                 .short('H')
@@ -133,6 +561,14 @@ fn main() {
     println!("{}{} Server {}", "Luna".red().bold(), "Tech".purple().bold(), env!("CARGO_PKG_VERSION"));
     println!("Developed by {}", env!("CARGO_PKG_AUTHORS"));    
     println!();
+
+    if matches.get_flag("list_interfaces") {
+        match network::interfaces() {
+            Ok(interfaces) => interfaces.iter().for_each(|interface| println!("{}", interface)),
+            Err(e) => println!("Failed to list interfaces: {}", e.to_string().bold().red()),
+        }
+        return;
+    }
     
     let mut lt_server: Option<LunaTechServer> = None;
     let mut device_monitor: Option<DeviceMonitor> = None;
@@ -141,15 +577,131 @@ fn main() {
     let default_buffer_size = matches.get_one::<u32>("buffer_size").unwrap_or(&DEFAULT_BUFFER_SIZE);
     let default_port = matches.get_one::<u16>("port").unwrap_or(&DEFAULT_PORT);
 
+    let registry_factory: RegistryFactory = Arc::new(default_registry);
+    let features = registry_factory().features();
+
     let mut lt_server_opts = LTServerOpts {
         sample_rate: *default_sample_rate,
         buffer_size: *default_buffer_size,
         port: *default_port,
         headless: matches.get_flag("headless"),
         input_mode: matches.get_flag("input_mode"),
+        broadcast: !matches.get_flag("no_broadcast"),
+        targets: matches.get_many::<String>("target").unwrap_or_default().cloned().collect(),
+        offset_ms: *matches.get_one::<i64>("offset").unwrap_or(&0),
+        hold: matches.get_flag("hold"),
+        rate: matches.get_one::<f32>("rate").cloned(),
+        aggregation: *matches.get_one::<Aggregation>("aggregate").unwrap_or(&Aggregation::Latest),
+        multicast: matches.get_one::<IpAddr>("multicast").map(|group| MulticastConfig {
+            ttl: *matches.get_one::<u32>("multicast_ttl").unwrap_or(&DEFAULT_MULTICAST_TTL),
+            interface: *matches.get_one::<Ipv4Addr>("multicast_if").unwrap_or(&Ipv4Addr::UNSPECIFIED),
+            interface_index: *matches.get_one::<u32>("multicast_if_index").unwrap_or(&0),
+            loopback: !matches.get_flag("no_multicast_loop"),
+            ..MulticastConfig::new(*group)
+        }),
+        interface: matches.get_one::<String>("interface").cloned(),
+        broadcast_addr: matches.get_one::<Ipv4Addr>("broadcast_addr").cloned(),
+        device_name: matches.get_one::<String>("device").cloned(),
+        settings: Arc::new(AnalyzerSettings::default()),
+        registry_factory,
+        instance_name: matches.get_one::<String>("name").cloned().unwrap_or(DEFAULT_INSTANCE_NAME.to_owned()),
+        advertise: !matches.get_flag("no_advertise"),
+        oscquery_port: matches.get_one::<u16>("oscquery_port").cloned(),
+        advertiser: None,
+        frame_listeners: Vec::new(),
+        sacn_sender: None,
+        stream_port: matches.get_one::<u16>("stream_port").cloned(),
+        streams: None,
+        subscribers: None,
+        namespace: parse_namespace(&matches, &features).unwrap_or_else(|e| {
+            println!("Invalid namespace, using the default: {}", e.bold().red());
+            Namespace::default()
+        }),
+        layout: *matches.get_one::<MessageLayout>("layout").unwrap_or(&MessageLayout::Bundle),
+        mappings: matches.get_one::<String>("mappings").map(|path| load_mappings(path, &features).unwrap_or_else(|e| {
+            println!("Invalid mappings, none are sent: {}", e.bold().red());
+            Vec::new()
+        })).unwrap_or_default(),
+        features,
         lt_server_state: LTServerState::Stopped,
     };
 
+    let control_rx = matches.get_one::<u16>("control_port").and_then(|port| match ControlServer::new(*port) {
+        Ok(control_server) => {
+            println!("Accepting control commands on port {}", port.to_string().bold().green());
+            lt_server_opts.subscribers = Some(control_server.subscribers());
+            Some(control_server.start(lt_server_opts.settings.clone(), (lt_server_opts.registry_factory)()))
+        }
+        Err(e) => {
+            println!("Failed to open control port {}: {}", port.to_string().bold(), e.to_string().bold().red());
+            None
+        }
+    });
+
+    if let Some(port) = lt_server_opts.oscquery_port {
+        match OscQueryServer::new(port, &lt_server_opts.instance_name, lt_server_opts.port, &lt_server_opts.features, &lt_server_opts.namespace) {
+            Ok(mut oscquery_server) => {
+                oscquery_server.set_settings(lt_server_opts.settings.clone(), (lt_server_opts.registry_factory)());
+                println!("Serving OSCQuery namespace on port {}", port.to_string().bold().green());
+                oscquery_server.start();
+            }
+            Err(e) => println!("Failed to open OSCQuery port {}: {}", port.to_string().bold(), e.to_string().bold().red()),
+        }
+    }
+
+    if let Some(port) = matches.get_one::<u16>("websocket_port") {
+        match WebSocketServer::new(*port) {
+            Ok(mut websocket_server) => {
+                websocket_server.set_namespace(lt_server_opts.namespace.clone());
+                println!("Streaming features over WebSocket on port {}", port.to_string().bold().green());
+                lt_server_opts.frame_listeners.push(websocket_server.start());
+            }
+            Err(e) => println!("Failed to open WebSocket port {}: {}", port.to_string().bold(), e.to_string().bold().red()),
+        }
+    }
+
+    if let Some(port) = lt_server_opts.stream_port {
+        match StreamServer::new(port) {
+            Ok(stream_server) => {
+                println!("Accepting stream clients on port {}", port.to_string().bold().green());
+                lt_server_opts.streams = Some(stream_server.start());
+            }
+            Err(e) => println!("Failed to open stream port {}: {}", port.to_string().bold(), e.to_string().bold().red()),
+        }
+    }
+
+    if let Some(path) = matches.get_one::<String>("artnet") {
+        match ArtNetConfig::load(path, &lt_server_opts.features).and_then(|config| ArtNetSender::new(config, ARTNET_PORT).map_err(|e| e.to_string())) {
+            Ok(mut artnet_sender) => {
+                println!("Sending Art-Net from {}", path.bold().green());
+                artnet_sender.set_name(&lt_server_opts.instance_name);
+                lt_server_opts.frame_listeners.push(artnet_sender.start());
+            }
+            Err(e) => println!("Failed to start Art-Net output: {}", e.bold().red()),
+        }
+    }
+
+    if let Some(path) = matches.get_one::<String>("sacn") {
+        match SacnConfig::load(path, &lt_server_opts.features, &lt_server_opts.instance_name).and_then(|config| SacnSender::new(config).map_err(|e| e.to_string())) {
+            Ok(mut sacn_sender) => {
+                println!("Sending sACN from {}", path.bold().green());
+                lt_server_opts.frame_listeners.push(sacn_sender.start());
+                lt_server_opts.sacn_sender = Some(sacn_sender);
+            }
+            Err(e) => println!("Failed to start sACN output: {}", e.bold().red()),
+        }
+    }
+
+    if let Some(path) = matches.get_one::<String>("leds") {
+        match load_strips(path).and_then(|strips| LedSender::new(strips).map_err(|e| e.to_string())) {
+            Ok(led_sender) => {
+                println!("Sending LED strips from {}", path.bold().green());
+                lt_server_opts.frame_listeners.push(led_sender.start());
+            }
+            Err(e) => println!("Failed to start LED output: {}", e.bold().red()),
+        }
+    }
+
     if lt_server_opts.headless {
         start_lt_server(&mut lt_server_opts, &mut lt_server, &mut device_monitor);
     }
@@ -159,7 +711,14 @@ fn main() {
     }
 
     if lt_server_opts.headless {
-        thread::park();
+        match &control_rx {
+            Some(control_rx) => {
+                for request in control_rx.iter() {
+                    handle_control_request(request, &mut lt_server_opts, &mut lt_server, &mut device_monitor);
+                }
+            }
+            None => thread::park(),
+        }
     }
 
     let native_opts = eframe::NativeOptions {
@@ -180,6 +739,7 @@ fn main() {
             buffer_size_string: lt_server_opts.buffer_size.to_string(),
             sample_rate_string: lt_server_opts.sample_rate.to_string(),
             lt_server_opts,
+            control_rx,
             timeout: None,
     }))));
 }
@@ -192,12 +752,19 @@ struct LTServerApp {
     buffer_size_string: String,
     sample_rate_string: String,
     lt_server_opts: LTServerOpts,
+    control_rx: Option<crossbeam::channel::Receiver<ControlRequest>>,
     timeout: Option<std::time::Instant>,
 }
 
 impl eframe::App for LTServerApp {
     fn update(&mut self, ctx: &egui::Context, _: &mut eframe::Frame) {
         let mut no_errors = true; 
+        if let Some(control_rx) = &self.control_rx {
+            while let Ok(request) = control_rx.try_recv() {
+                handle_control_request(request, &mut self.lt_server_opts, &mut self.lt_server, &mut self.lt_device_monitor);
+            }
+            ctx.request_repaint_after(Duration::from_millis(100));
+        }
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.label(format!("LunaTech Server {}", env!("CARGO_PKG_VERSION")));
 
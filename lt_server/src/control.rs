@@ -0,0 +1,395 @@
+use std::{io, net::{Ipv4Addr, SocketAddr, UdpSocket}, sync::Arc, thread, time::Duration};
+use colored::Colorize;
+use crossbeam::channel::{Receiver, Sender};
+use rosc::{encoder, OscMessage, OscPacket, OscType};
+use socket2::{Domain, Protocol, Socket, Type};
+
+use lt_utilities::audio_features::FEATURE_SPECTRUMBANDS;
+use lt_utilities::extractor::ExtractorRegistry;
+
+use crate::analyzer::AnalyzerSettings;
+use crate::extractors::PARAMETER_BANDS;
+use crate::layout::MessageLayout;
+use crate::subscription::{Subscription, UdpSubscribers};
+
+pub const CONTROL_PREFIX: &str = "/lt/ctl/";
+pub const OSC_ADDR_CTL_GAIN: &str = "/lt/ctl/gain";
+pub const OSC_ADDR_CTL_BANDS: &str = "/lt/ctl/bands";
+pub const OSC_ADDR_CTL_DEVICE: &str = "/lt/ctl/device";
+pub const OSC_ADDR_CTL_START: &str = "/lt/ctl/start";
+pub const OSC_ADDR_CTL_STOP: &str = "/lt/ctl/stop";
+pub const OSC_ADDR_CTL_SUBSCRIBE: &str = "/lt/ctl/subscribe";
+pub const OSC_ADDR_CTL_UNSUBSCRIBE: &str = "/lt/ctl/unsubscribe";
+/// Reply to an applied command, the argument is the command address
+pub const OSC_ADDR_ACK: &str = "/lt/ack";
+/// Reply to a rejected command, the arguments are the command address and the reason
+pub const OSC_ADDR_ERROR: &str = "/lt/error";
+
+/// Wait after a failed receive, so a socket that keeps failing doesn't spin
+const RECV_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ControlCommand {
+    Gain(f32),
+    Bands(usize),
+    /// Extractor parameter, sent as `/lt/ctl/<name> <value>`
+    Parameter(String, f32),
+    Device(String),
+    Start,
+    Stop,
+    /// Sends only what the subscription selects to `port`, or the sending port, of the sender
+    Subscribe { port: Option<u16>, subscription: Subscription },
+    Unsubscribe { port: Option<u16> },
+}
+
+/// First argument as a number, NaN and infinity are rejected so they never reach the analyzer
+fn float_arg(msg: &OscMessage) -> Result<f32, String> {
+    let value = match msg.args.first() {
+        Some(OscType::Float(value)) => *value,
+        Some(OscType::Double(value)) => *value as f32,
+        Some(OscType::Int(value)) => *value as f32,
+        Some(OscType::Long(value)) => *value as f32,
+        _ => return Err("Expected a number argument".to_owned()),
+    };
+    if !value.is_finite() {
+        return Err("Expected a finite number argument".to_owned());
+    }
+    Ok(value)
+}
+
+fn port_arg(value: i32) -> Result<u16, String> {
+    u16::try_from(value).map_err(|_| format!("Invalid port {}", value))
+}
+
+/// Reads `/lt/ctl/subscribe` arguments in any order.
+/// Ints are the port, floats the rate, strings starting with a slash address patterns and other strings the format or layout
+fn subscribe_args(msg: &OscMessage) -> Result<ControlCommand, String> {
+    let mut port = None;
+    let mut subscription = Subscription::default();
+    let mut addresses = Vec::new();
+    for arg in &msg.args {
+        match arg {
+            OscType::Int(value) => port = Some(port_arg(*value)?),
+            OscType::Float(rate) => subscription.rate = Some(*rate),
+            OscType::Double(rate) => subscription.rate = Some(*rate as f32),
+            OscType::String(pattern) if pattern.starts_with('/') => addresses.push(pattern.clone()),
+            OscType::String(value) => match value.parse::<MessageLayout>() {
+                Ok(layout) => subscription.layout = layout,
+                Err(_) => subscription.format = value.parse().map_err(|_| format!("Unknown format or layout {}", value))?,
+            },
+            _ => return Err("Unexpected argument".to_owned()),
+        }
+    }
+    if !addresses.is_empty() {
+        subscription.addresses = Some(addresses);
+    }
+    subscription.validate()?;
+    Ok(ControlCommand::Subscribe { port, subscription })
+}
+
+impl ControlCommand {
+    pub fn from_message(msg: &OscMessage) -> Result<Self, String> {
+        match msg.addr.as_str() {
+            OSC_ADDR_CTL_GAIN => {
+                let gain = float_arg(msg)?;
+                if gain < 0. {
+                    return Err("Gain must be zero or a positive number".to_owned());
+                }
+                Ok(ControlCommand::Gain(gain))
+            }
+            OSC_ADDR_CTL_BANDS => {
+                let bands = float_arg(msg)?;
+                if bands < 1. || bands > FEATURE_SPECTRUMBANDS.len() as f32 {
+                    return Err(format!("Band count must be 1 to {}", FEATURE_SPECTRUMBANDS.len()));
+                }
+                Ok(ControlCommand::Bands(bands as usize))
+            }
+            OSC_ADDR_CTL_DEVICE => match msg.args.first() {
+                Some(OscType::String(name)) => Ok(ControlCommand::Device(name.clone())),
+                _ => Err("Expected a device name argument".to_owned()),
+            },
+            OSC_ADDR_CTL_START => Ok(ControlCommand::Start),
+            OSC_ADDR_CTL_STOP => Ok(ControlCommand::Stop),
+            OSC_ADDR_CTL_SUBSCRIBE => subscribe_args(msg),
+            OSC_ADDR_CTL_UNSUBSCRIBE => match msg.args.first() {
+                Some(OscType::Int(port)) => Ok(ControlCommand::Unsubscribe { port: Some(port_arg(*port)?) }),
+                None => Ok(ControlCommand::Unsubscribe { port: None }),
+                _ => Err("Expected a port argument".to_owned()),
+            },
+            addr => match addr.strip_prefix(CONTROL_PREFIX) {
+                Some(name) if !name.is_empty() && !name.contains('/') => Ok(ControlCommand::Parameter(name.to_owned(), float_arg(msg)?)),
+                _ => Err("Unknown command".to_owned()),
+            },
+        }
+    }
+}
+
+/// Reply to an applied command
+pub fn ack_message(command_addr: &str) -> OscMessage {
+    OscMessage { addr: OSC_ADDR_ACK.to_owned(), args: vec![OscType::String(command_addr.to_owned())] }
+}
+
+/// Reply to a rejected command
+pub fn error_message(command_addr: &str, reason: &str) -> OscMessage {
+    OscMessage { addr: OSC_ADDR_ERROR.to_owned(), args: vec![OscType::String(command_addr.to_owned()), OscType::String(reason.to_owned())] }
+}
+
+/// Sends the acknowledgement of a control command back to whoever sent it
+pub struct ControlReply {
+    socket: Arc<UdpSocket>,
+    addr: SocketAddr,
+    command_addr: String,
+}
+
+impl ControlReply {
+    pub fn ok(&self) {
+        self.send(ack_message(&self.command_addr));
+    }
+
+    pub fn error(&self, reason: &str) {
+        self.send(error_message(&self.command_addr, reason));
+    }
+
+    fn send(&self, message: OscMessage) {
+        if let Ok(buf) = encoder::encode(&OscPacket::Message(message)) {
+            if let Err(e) = self.socket.send_to(&buf, self.addr) {
+                println!("Failed to reply to {}: {}", self.addr, e.to_string().bold().red());
+            }
+        }
+    }
+}
+
+/// A command the control server can't apply by itself, it must be answered through `reply`
+pub struct ControlRequest {
+    pub command: ControlCommand,
+    pub reply: ControlReply,
+}
+
+/// Listens for OSC commands that change the server at runtime
+pub struct ControlServer {
+    socket: Arc<UdpSocket>,
+    subscribers: Arc<UdpSubscribers>,
+}
+
+impl ControlServer {
+    pub fn new(port: u16) -> io::Result<Self> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        socket.bind(&SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port).into())?;
+        let socket: Arc<UdpSocket> = Arc::new(socket.into());
+        Ok(Self { subscribers: Arc::new(UdpSubscribers::new(socket.clone())), socket })
+    }
+
+    /// Clients subscribed with `/lt/ctl/subscribe`, see `LunaTechServer::set_subscribers`
+    pub fn subscribers(&self) -> Arc<UdpSubscribers> {
+        self.subscribers.clone()
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Applies gain, extractor parameters and subscriptions directly.
+    /// Parameters no extractor in `registry` uses are rejected.
+    /// Device, start and stop commands are returned through the receiver
+    pub fn start(&self, settings: Arc<AnalyzerSettings>, mut registry: ExtractorRegistry) -> Receiver<ControlRequest> {
+        let (tx, rx) = crossbeam::channel::unbounded();
+        let socket = self.socket.clone();
+        let subscribers = self.subscribers.clone();
+
+        thread::spawn(move || {
+            let mut buf = [0u8; rosc::decoder::MTU];
+            loop {
+                let (size, addr) = match socket.recv_from(&mut buf) {
+                    Ok(result) => result,
+                    Err(e) => {
+                        println!("Control socket error: {}", e.to_string().bold().red());
+                        thread::sleep(RECV_ERROR_BACKOFF);
+                        continue;
+                    }
+                };
+
+                match rosc::decoder::decode_udp(&buf[..size]) {
+                    Ok((_, packet)) => {
+                        let mut messages = Vec::new();
+                        flatten_messages(packet, &mut messages);
+                        messages.into_iter().for_each(|msg| {
+                            handle_message(msg, ControlReply { socket: socket.clone(), addr, command_addr: String::new() }, &settings, &mut registry, &subscribers, &tx);
+                        });
+                    }
+                    Err(e) => {
+                        println!("Got invalid control packet: {}", e);
+                    }
+                }
+            }
+        });
+
+        rx
+    }
+}
+
+pub(crate) fn flatten_messages(packet: OscPacket, messages: &mut Vec<OscMessage>) {
+    match packet {
+        OscPacket::Message(msg) => messages.push(msg),
+        OscPacket::Bundle(bundle) => bundle.content.into_iter().for_each(|packet| flatten_messages(packet, messages)),
+    }
+}
+
+fn handle_message(msg: OscMessage, mut reply: ControlReply, settings: &AnalyzerSettings, registry: &mut ExtractorRegistry, subscribers: &UdpSubscribers, tx: &Sender<ControlRequest>) {
+    reply.command_addr = msg.addr.clone();
+    let command = match ControlCommand::from_message(&msg) {
+        Ok(command) => command,
+        Err(reason) => {
+            reply.error(&reason);
+            return;
+        }
+    };
+
+    match command {
+        ControlCommand::Gain(gain) => {
+            settings.set_gain(gain);
+            reply.ok();
+        }
+        ControlCommand::Bands(bands) => set_parameter(PARAMETER_BANDS, bands as f32, settings, registry, &reply),
+        ControlCommand::Parameter(name, value) => set_parameter(&name, value, settings, registry, &reply),
+        ControlCommand::Subscribe { port, subscription } => {
+            match subscribers.subscribe(SocketAddr::new(reply.addr.ip(), port.unwrap_or(reply.addr.port())), subscription) {
+                Ok(()) => reply.ok(),
+                Err(reason) => reply.error(&reason),
+            }
+        }
+        ControlCommand::Unsubscribe { port } => {
+            match subscribers.unsubscribe(SocketAddr::new(reply.addr.ip(), port.unwrap_or(reply.addr.port()))) {
+                true => reply.ok(),
+                false => reply.error("Not subscribed"),
+            }
+        }
+        command => {
+            if let Err(e) = tx.send(ControlRequest { command, reply }) {
+                e.0.reply.error("Server is not accepting commands");
+            }
+        }
+    }
+}
+
+/// Sets a parameter if an extractor of the registry uses it
+fn set_parameter(name: &str, value: f32, settings: &AnalyzerSettings, registry: &mut ExtractorRegistry, reply: &ControlReply) {
+    if registry.iter_mut().any(|extractor| extractor.set_parameter(name, value)) {
+        settings.set_parameter(name, value);
+        reply.ok();
+    } else {
+        reply.error(&format!("Unknown parameter {}", name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subscription::StreamFormat;
+
+    fn message(addr: &str, args: Vec<OscType>) -> OscMessage {
+        OscMessage { addr: addr.to_owned(), args }
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(ControlCommand::from_message(&message(OSC_ADDR_CTL_GAIN, vec![OscType::Float(2.)])), Ok(ControlCommand::Gain(2.)));
+        assert_eq!(ControlCommand::from_message(&message(OSC_ADDR_CTL_GAIN, vec![OscType::Int(3)])), Ok(ControlCommand::Gain(3.)));
+        assert_eq!(ControlCommand::from_message(&message(OSC_ADDR_CTL_BANDS, vec![OscType::Int(32)])), Ok(ControlCommand::Bands(32)));
+        assert_eq!(ControlCommand::from_message(&message("/lt/ctl/threshold", vec![OscType::Double(0.5)])), Ok(ControlCommand::Parameter("threshold".to_owned(), 0.5)));
+        assert_eq!(ControlCommand::from_message(&message(OSC_ADDR_CTL_DEVICE, vec![OscType::String("Line In".to_owned())])), Ok(ControlCommand::Device("Line In".to_owned())));
+        assert_eq!(ControlCommand::from_message(&message(OSC_ADDR_CTL_START, vec![])), Ok(ControlCommand::Start));
+        assert_eq!(ControlCommand::from_message(&message(OSC_ADDR_CTL_STOP, vec![])), Ok(ControlCommand::Stop));
+        assert_eq!(ControlCommand::from_message(&message(OSC_ADDR_CTL_UNSUBSCRIBE, vec![OscType::Int(9000)])), Ok(ControlCommand::Unsubscribe { port: Some(9000) }));
+        assert_eq!(ControlCommand::from_message(&message(OSC_ADDR_CTL_UNSUBSCRIBE, vec![])), Ok(ControlCommand::Unsubscribe { port: None }));
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        let rejected = [
+            message(OSC_ADDR_CTL_GAIN, vec![OscType::Float(-1.)]),
+            message(OSC_ADDR_CTL_GAIN, vec![OscType::Float(f32::NAN)]),
+            message(OSC_ADDR_CTL_GAIN, vec![OscType::Double(f64::INFINITY)]),
+            message(OSC_ADDR_CTL_GAIN, vec![OscType::String("loud".to_owned())]),
+            message(OSC_ADDR_CTL_GAIN, vec![]),
+            message(OSC_ADDR_CTL_BANDS, vec![OscType::Int(0)]),
+            message(OSC_ADDR_CTL_BANDS, vec![OscType::Int(65)]),
+            message(OSC_ADDR_CTL_BANDS, vec![OscType::Float(f32::NAN)]),
+            message(OSC_ADDR_CTL_BANDS, vec![OscType::Float(f32::INFINITY)]),
+            message("/lt/ctl/threshold", vec![OscType::Float(f32::NAN)]),
+            message(OSC_ADDR_CTL_DEVICE, vec![OscType::Int(1)]),
+            message(OSC_ADDR_CTL_UNSUBSCRIBE, vec![OscType::Int(70000)]),
+            message("/lt/ctl/", vec![OscType::Float(1.)]),
+            message("/lt/ctl/nested/name", vec![OscType::Float(1.)]),
+            message("/lt/other", vec![]),
+        ];
+        for msg in rejected {
+            assert!(ControlCommand::from_message(&msg).is_err(), "{:?}", msg);
+        }
+    }
+
+    #[test]
+    fn parses_subscribe_arguments_in_any_order() {
+        let msg = message(OSC_ADDR_CTL_SUBSCRIBE, vec![
+            OscType::String("/lt/*RMS".to_owned()),
+            OscType::Float(30.),
+            OscType::String("json".to_owned()),
+            OscType::Int(9001),
+            OscType::String("/lt/Flux".to_owned()),
+            OscType::String("messages".to_owned()),
+        ]);
+        let Ok(ControlCommand::Subscribe { port, subscription }) = ControlCommand::from_message(&msg) else {
+            panic!("Expected a subscribe command");
+        };
+        assert_eq!(port, Some(9001));
+        assert_eq!(subscription.addresses, Some(vec!["/lt/*RMS".to_owned(), "/lt/Flux".to_owned()]));
+        assert_eq!(subscription.rate, Some(30.));
+        assert_eq!(subscription.format, StreamFormat::Json);
+        assert_eq!(subscription.layout, MessageLayout::Messages);
+
+        assert!(ControlCommand::from_message(&message(OSC_ADDR_CTL_SUBSCRIBE, vec![OscType::Float(0.)])).is_err());
+        assert!(ControlCommand::from_message(&message(OSC_ADDR_CTL_SUBSCRIBE, vec![OscType::Float(1e-39)])).is_err());
+        assert!(ControlCommand::from_message(&message(OSC_ADDR_CTL_SUBSCRIBE, vec![OscType::Double(1e-30)])).is_err());
+        assert!(ControlCommand::from_message(&message(OSC_ADDR_CTL_SUBSCRIBE, vec![OscType::String("/lt/[".to_owned())])).is_err());
+        assert!(ControlCommand::from_message(&message(OSC_ADDR_CTL_SUBSCRIBE, vec![OscType::String("xml".to_owned())])).is_err());
+    }
+
+    #[test]
+    fn replies_name_the_command() {
+        assert_eq!(ack_message(OSC_ADDR_CTL_GAIN).args, vec![OscType::String(OSC_ADDR_CTL_GAIN.to_owned())]);
+        let error = error_message(OSC_ADDR_CTL_BANDS, "Band count must be 1 to 64");
+        assert_eq!(error.addr, OSC_ADDR_ERROR);
+        assert_eq!(error.args[1], OscType::String("Band count must be 1 to 64".to_owned()));
+    }
+
+    fn request(socket: &UdpSocket, addr: &str, args: Vec<OscType>) -> OscMessage {
+        let buf = encoder::encode(&OscPacket::Message(message(addr, args))).unwrap();
+        socket.send(&buf).unwrap();
+        let mut reply = [0u8; rosc::decoder::MTU];
+        let size = socket.recv(&mut reply).unwrap();
+        match rosc::decoder::decode_udp(&reply[..size]).unwrap().1 {
+            OscPacket::Message(msg) => msg,
+            packet => panic!("Expected a reply message, got {:?}", packet),
+        }
+    }
+
+    #[test]
+    fn applies_known_parameters_only() {
+        let server = ControlServer::new(0).unwrap();
+        let settings = Arc::new(AnalyzerSettings::default());
+        let _requests = server.start(settings.clone(), crate::extractors::default_registry());
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        socket.connect((Ipv4Addr::LOCALHOST, server.local_addr().unwrap().port())).unwrap();
+
+        assert_eq!(request(&socket, OSC_ADDR_CTL_BANDS, vec![OscType::Int(8)]), ack_message(OSC_ADDR_CTL_BANDS));
+        assert_eq!(settings.parameters().get(PARAMETER_BANDS), Some(&8.));
+
+        assert_eq!(request(&socket, "/lt/ctl/treshold", vec![OscType::Float(0.5)]), error_message("/lt/ctl/treshold", "Unknown parameter treshold"));
+        assert!(!settings.parameters().contains_key("treshold"));
+
+        assert_eq!(request(&socket, OSC_ADDR_CTL_GAIN, vec![OscType::Float(0.)]), ack_message(OSC_ADDR_CTL_GAIN));
+        assert_eq!(settings.gain(), 0.);
+        assert_eq!(request(&socket, OSC_ADDR_CTL_GAIN, vec![OscType::Float(-1.)]), error_message(OSC_ADDR_CTL_GAIN, "Gain must be zero or a positive number"));
+    }
+}
@@ -0,0 +1,292 @@
+use std::{fmt, io, mem, net::SocketAddr, sync::{atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicU64, Ordering}, Arc, Mutex}, thread, time::{Duration, Instant}};
+use colored::Colorize;
+use crossbeam::channel::Sender;
+use socket2::Socket;
+
+use crate::output_rate::OutputRate;
+
+/// Time between printed send errors of one sender, a failing target sent to 60 times a second would flood the console
+pub const SEND_ERROR_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Prints send errors at most once per interval, with the number of errors left out since the last one
+#[derive(Debug)]
+pub struct SendErrorLog {
+    interval: Duration,
+    last_printed: Option<Instant>,
+    skipped: u64,
+}
+
+impl Default for SendErrorLog {
+    fn default() -> Self {
+        Self::new(SEND_ERROR_INTERVAL)
+    }
+}
+
+impl SendErrorLog {
+    pub fn new(interval: Duration) -> Self {
+        Self { interval, last_printed: None, skipped: 0 }
+    }
+
+    /// Counts an error, returns the number of errors left out before it if it should be printed
+    pub fn record(&mut self) -> Option<u64> {
+        if self.last_printed.is_some_and(|last_printed| last_printed.elapsed() < self.interval) {
+            self.skipped += 1;
+            return None;
+        }
+        self.last_printed = Some(Instant::now());
+        Some(mem::take(&mut self.skipped))
+    }
+
+    /// Prints an error sending `what` to `target`, unless one was printed within the interval
+    pub fn print(&mut self, what: &str, target: impl fmt::Display, e: &io::Error) {
+        match self.record() {
+            Some(0) => println!("Error sending {} to {}: {}", what, target, e.to_string().bold().red()),
+            Some(skipped) => println!("Error sending {} to {}: {} ({} more errors since the last one shown)", what, target, e.to_string().bold().red(), skipped),
+            None => {}
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DestinationKind {
+    Broadcast,
+    Unicast,
+    Multicast,
+}
+
+/// Send statistics of a single destination
+#[derive(Debug, Default)]
+pub struct DestinationStats {
+    packets_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    send_errors: AtomicU64,
+    last_error: Mutex<Option<String>>,
+    error_log: Mutex<SendErrorLog>,
+}
+
+impl DestinationStats {
+    pub fn packets_sent(&self) -> u64 {
+        self.packets_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn send_errors(&self) -> u64 {
+        self.send_errors.load(Ordering::Relaxed)
+    }
+
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.lock().ok()?.clone()
+    }
+
+    fn record_sent(&self, bytes: usize) {
+        self.packets_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn record_error(&self, e: &io::Error) {
+        self.send_errors.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut last_error) = self.last_error.lock() {
+            *last_error = Some(e.to_string());
+        }
+    }
+}
+
+/// Packet waiting to be sent at a later time
+type HeldPacket = (Instant, Vec<u8>);
+
+/// An address the server sends feature packets to
+pub struct Destination {
+    pub addr: SocketAddr,
+    pub kind: DestinationKind,
+    pub stats: Arc<DestinationStats>,
+    socket: Arc<Socket>,
+    offset_ms: AtomicI64,
+    hold: AtomicBool,
+    sequence: AtomicU32,
+    rate: Mutex<Option<OutputRate>>,
+    /// Queue of the thread sending held packets, started by the first held packet
+    held: Mutex<Option<Sender<HeldPacket>>>,
+}
+
+impl Destination {
+    pub fn new(addr: SocketAddr, kind: DestinationKind, socket: Arc<Socket>) -> Self {
+        Self {
+            addr,
+            kind,
+            stats: Arc::new(DestinationStats::default()),
+            socket,
+            offset_ms: AtomicI64::new(0),
+            hold: AtomicBool::new(false),
+            sequence: AtomicU32::new(0),
+            rate: Mutex::new(None),
+            held: Mutex::new(None),
+        }
+    }
+
+    /// Milliseconds added to the timetags of feature bundles, negative values look ahead
+    pub fn offset_ms(&self) -> i64 {
+        self.offset_ms.load(Ordering::Relaxed)
+    }
+
+    pub fn set_offset_ms(&self, offset_ms: i64) {
+        self.offset_ms.store(offset_ms, Ordering::Relaxed);
+    }
+
+    pub fn hold(&self) -> bool {
+        self.hold.load(Ordering::Relaxed)
+    }
+
+    /// Also holds feature bundles back by a positive offset, for receivers that ignore timetags
+    pub fn set_hold(&self, hold: bool) {
+        self.hold.store(hold, Ordering::Relaxed);
+    }
+
+    /// Fixed rate the destination is sent to, `None` sends once per audio buffer
+    pub fn rate(&self) -> Option<OutputRate> {
+        *self.rate.lock().ok()?
+    }
+
+    /// Takes effect when the server is started
+    pub fn set_rate(&self, rate: Option<OutputRate>) {
+        if let Ok(mut current) = self.rate.lock() {
+            *current = rate;
+        }
+    }
+
+    /// Sequence number of the next feature bundle, counting up from 0 and wrapping around
+    pub fn next_sequence(&self) -> u32 {
+        self.sequence.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn send(&self, buf: &[u8]) {
+        send_to(&self.socket, self.addr, &self.stats, buf);
+    }
+
+    /// Sends a feature bundle, held back by the offset if holding is enabled
+    pub fn send_features(&self, buf: &[u8]) {
+        let offset_ms = self.offset_ms();
+        if !self.hold() || offset_ms <= 0 {
+            self.send(buf);
+            return;
+        }
+
+        let send_at = Instant::now() + Duration::from_millis(offset_ms as u64);
+        if let Ok(mut held) = self.held.lock() {
+            let sender = held.get_or_insert_with(|| self.start_held_thread());
+            let _ = sender.send((send_at, buf.to_vec()));
+        }
+    }
+
+    fn start_held_thread(&self) -> Sender<HeldPacket> {
+        let (tx, rx) = crossbeam::channel::unbounded::<HeldPacket>();
+        let (socket, addr, stats) = (self.socket.clone(), self.addr, self.stats.clone());
+        // Ends when the destination is dropped
+        thread::spawn(move || {
+            for (send_at, buf) in rx {
+                thread::sleep(send_at.saturating_duration_since(Instant::now()));
+                send_to(&socket, addr, &stats, &buf);
+            }
+        });
+        tx
+    }
+}
+
+fn send_to(socket: &Socket, addr: SocketAddr, stats: &DestinationStats, buf: &[u8]) {
+    match socket.send_to(buf, &addr.into()) {
+        Ok(bytes) => stats.record_sent(bytes),
+        Err(e) => {
+            stats.record_error(&e);
+            if let Ok(mut error_log) = stats.error_log.lock() {
+                error_log.print("audio features", addr, &e);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            DestinationKind::Broadcast => write!(f, "{} (broadcast)", self.addr)?,
+            DestinationKind::Unicast => write!(f, "{}", self.addr)?,
+            DestinationKind::Multicast => write!(f, "{} (multicast)", self.addr)?,
+        }
+        if let Some(rate) = self.rate() {
+            write!(f, " at {} Hz ({})", rate.hz, rate.aggregation)?;
+        }
+        match self.offset_ms() {
+            0 => Ok(()),
+            offset_ms => write!(f, " {:+} ms", offset_ms),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use socket2::{Domain, Protocol, Type};
+
+    use super::*;
+
+    #[test]
+    fn counts_errors_of_each_destination() {
+        let socket = Arc::new(Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap());
+        let reachable = Destination::new("127.0.0.1:9".parse().unwrap(), DestinationKind::Unicast, socket.clone());
+        // An IPv4 socket can't send to an IPv6 address
+        let unreachable = Destination::new("[::1]:9".parse().unwrap(), DestinationKind::Unicast, socket);
+        reachable.send(&[1, 2, 3]);
+        unreachable.send(&[1, 2, 3]);
+        unreachable.send(&[1, 2, 3]);
+
+        assert_eq!((reachable.stats.packets_sent(), reachable.stats.bytes_sent(), reachable.stats.send_errors()), (1, 3, 0));
+        assert_eq!((unreachable.stats.packets_sent(), unreachable.stats.send_errors()), (0, 2));
+        assert!(unreachable.stats.last_error().is_some());
+        assert_eq!(reachable.stats.last_error(), None);
+    }
+
+    #[test]
+    fn prints_errors_once_per_interval() {
+        let mut errors = SendErrorLog::new(Duration::from_millis(100));
+        assert_eq!(errors.record(), Some(0));
+        assert_eq!(errors.record(), None);
+        assert_eq!(errors.record(), None);
+        thread::sleep(Duration::from_millis(120));
+        assert_eq!(errors.record(), Some(2));
+        assert_eq!(errors.record(), None);
+    }
+
+    #[test]
+    fn describes_the_destination() {
+        let socket = Arc::new(Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap());
+        let destination = Destination::new("10.0.0.2:3000".parse().unwrap(), DestinationKind::Unicast, socket.clone());
+        assert_eq!(destination.to_string(), "10.0.0.2:3000");
+        destination.set_offset_ms(-20);
+        assert_eq!(destination.to_string(), "10.0.0.2:3000 -20 ms");
+        let broadcast = Destination::new("255.255.255.255:3000".parse().unwrap(), DestinationKind::Broadcast, socket);
+        assert_eq!(broadcast.to_string(), "255.255.255.255:3000 (broadcast)");
+    }
+
+    #[test]
+    fn holds_packets_back_by_the_offset() {
+        let receiver = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket = Arc::new(Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap());
+        let destination = Destination::new(receiver.local_addr().unwrap(), DestinationKind::Unicast, socket);
+        destination.set_offset_ms(150);
+        destination.set_hold(true);
+
+        let sent_at = Instant::now();
+        destination.send_features(&[1]);
+        receiver.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut buf = [0; 8];
+        assert_eq!(receiver.recv(&mut buf).unwrap(), 1);
+        assert!(sent_at.elapsed() >= Duration::from_millis(150));
+
+        // Lookahead can't be held back
+        destination.set_offset_ms(-150);
+        let sent_at = Instant::now();
+        destination.send_features(&[2]);
+        receiver.recv(&mut buf).unwrap();
+        assert!(sent_at.elapsed() < Duration::from_millis(150));
+    }
+}
@@ -3,9 +3,13 @@ use colored::Colorize;
 use crossbeam::channel::Sender;
 use cpal::{traits::{DeviceTrait, StreamTrait}, SampleRate, StreamConfig};
 
-use crate::analyzer::Analyzer;
+use crate::analyzer::{Analyzer, AnalyzerSettings};
 
-use lt_utilities::audio_features::Features;
+use lt_utilities::audio_features::FeatureFrame;
+use lt_utilities::extractor::ExtractorRegistry;
+
+/// Builds the extractors used for every stream created by a device monitor
+pub type RegistryFactory = Arc<dyn Fn() -> ExtractorRegistry + Send + Sync>;
 
 #[derive(Default)]
 pub struct DeviceMonitor {
@@ -14,7 +18,9 @@ pub struct DeviceMonitor {
     device_name: Option<String>, 
     /// The data stream of the device
     stream: Option<cpal::Stream>,
-    tx: Option<Arc<Sender<Features>>>,
+    tx: Option<Arc<Sender<FeatureFrame>>>,
+    registry_factory: Option<RegistryFactory>,
+    settings: Arc<AnalyzerSettings>,
     error_msg: Option<String>,
 }
 
@@ -27,14 +33,38 @@ impl DeviceMonitor {
             device_name: None,
             stream: None,
             tx: None,
+            registry_factory: None,
+            settings: Arc::new(AnalyzerSettings::default()),
             error_msg: None,
         }
     }
 
-    pub fn set_thread_sender(&mut self, tx: crossbeam::channel::Sender<Features>) {
+    pub fn set_thread_sender(&mut self, tx: crossbeam::channel::Sender<FeatureFrame>) {
         self.tx = Some(tx.into());
     }
 
+    /// Replaces the built in extractors with the registries created by `factory`
+    pub fn set_registry_factory(&mut self, factory: RegistryFactory) {
+        self.registry_factory = Some(factory);
+    }
+
+    pub fn settings(&self) -> Arc<AnalyzerSettings> {
+        self.settings.clone()
+    }
+
+    /// Shares settings with the analyzers of streams built after this call
+    pub fn set_settings(&mut self, settings: Arc<AnalyzerSettings>) {
+        self.settings = settings;
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn device_name(&self) -> Option<&str> {
+        self.device_name.as_deref()
+    }
+
     pub fn start_device_monitor(&self) {
         if let (Some(stream), Some(device_name)) = (&self.stream, &self.device_name) {
             match stream.play() {
@@ -84,7 +114,11 @@ impl DeviceMonitor {
     }
 
     fn try_building_stream(&self, device: &cpal::Device, config: &StreamConfig) -> Result<cpal::Stream, Box<dyn Error>>  {
-        let mut analyzer = Analyzer::new(config.channels, config.sample_rate.0);
+        let mut analyzer = match &self.registry_factory {
+            Some(factory) => Analyzer::with_registry(config.channels, config.sample_rate.0, factory()),
+            None => Analyzer::new(config.channels, config.sample_rate.0),
+        };
+        analyzer.set_settings(self.settings.clone());
         
         let sender = match &self.tx {
             Some(sender) => sender,
@@ -94,17 +128,8 @@ impl DeviceMonitor {
         let shared_sender = sender.clone();
 
         let data_callback = move |sample_data: &[f32], _: &cpal::InputCallbackInfo| {            
-            analyzer.feed_data(sample_data);
-            let tx = shared_sender.clone();
-            let _ = tx.send((
-                analyzer.audio_features.broad_range_rms.get(),
-                analyzer.audio_features.low_range_rms.get(),
-                analyzer.audio_features.mid_range_rms.get(),
-                analyzer.audio_features.high_range_rms.get(),
-                analyzer.audio_features.zcr.get(),
-                analyzer.audio_features.spectral_centroid.get(),
-                analyzer.audio_features.flux.get(),
-            ));
+            let frame = analyzer.feed_data(sample_data);
+            let _ = shared_sender.send(frame);
         };
 
         let error_callback = move |e: cpal::StreamError| {
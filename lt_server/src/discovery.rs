@@ -0,0 +1,257 @@
+use std::{collections::HashMap, net::IpAddr, time::{Duration, Instant}};
+use mdns_sd::{IfKind, ServiceDaemon, ServiceEvent, ServiceInfo};
+
+use lt_utilities::atomic_float::FeatureDescriptor;
+use lt_utilities::namespace::{Namespace, DEFAULT_PREFIX};
+
+use crate::layout::MessageLayout;
+
+pub use mdns_sd::Error as DiscoveryError;
+
+/// Generic OSC service type understood by most OSC tools
+pub const OSC_SERVICE_TYPE: &str = "_osc._udp.local.";
+/// Service type only LunaTech servers advertise
+pub const LUNATECH_SERVICE_TYPE: &str = "_lunatech._udp.local.";
+/// Service type of OSCQuery HTTP servers
+pub const OSCQUERY_SERVICE_TYPE: &str = "_oscjson._tcp.local.";
+
+pub const TXT_VERSION: &str = "version";
+/// Comma separated feature names
+pub const TXT_FEATURES: &str = "features";
+pub const TXT_MULTICAST: &str = "multicast";
+pub const TXT_OSCQUERY_PORT: &str = "oscquery";
+/// TCP port of the OSC stream transport
+pub const TXT_STREAM_PORT: &str = "stream";
+/// Namespace prefix, `/lt` if missing
+pub const TXT_PREFIX: &str = "prefix";
+pub const TXT_INSTANCE: &str = "instance";
+/// Comma separated `feature=address` overrides
+pub const TXT_ADDRESSES: &str = "addresses";
+pub const TXT_LAYOUT: &str = "layout";
+
+/// What a server advertises about itself
+#[derive(Clone, Debug)]
+pub struct Advertisement {
+    pub instance_name: String,
+    /// Port the features are sent to
+    pub port: u16,
+    pub features: Vec<String>,
+    /// Group the features are also sent to
+    pub multicast: Option<IpAddr>,
+    pub oscquery_port: Option<u16>,
+    pub stream_port: Option<u16>,
+    /// Addresses the features are sent on
+    pub namespace: Namespace,
+    pub layout: MessageLayout,
+}
+
+impl Advertisement {
+    pub fn new(instance_name: &str, port: u16, features: &[FeatureDescriptor]) -> Self {
+        Self {
+            instance_name: instance_name.to_owned(),
+            port,
+            features: features.iter().map(|feature| feature.name.to_string()).collect(),
+            multicast: None,
+            oscquery_port: None,
+            stream_port: None,
+            namespace: Namespace::default(),
+            layout: MessageLayout::default(),
+        }
+    }
+
+    fn properties(&self) -> HashMap<String, String> {
+        let mut properties = HashMap::new();
+        properties.insert(TXT_VERSION.to_owned(), env!("CARGO_PKG_VERSION").to_owned());
+        properties.insert(TXT_FEATURES.to_owned(), self.features.join(","));
+        if let Some(group) = self.multicast {
+            properties.insert(TXT_MULTICAST.to_owned(), group.to_string());
+        }
+        if let Some(port) = self.oscquery_port {
+            properties.insert(TXT_OSCQUERY_PORT.to_owned(), port.to_string());
+        }
+        if let Some(port) = self.stream_port {
+            properties.insert(TXT_STREAM_PORT.to_owned(), port.to_string());
+        }
+        properties.insert(TXT_PREFIX.to_owned(), self.namespace.prefix().to_owned());
+        if let Some(instance) = self.namespace.instance() {
+            properties.insert(TXT_INSTANCE.to_owned(), instance.to_owned());
+        }
+        let overrides = self.namespace.overrides().map(|(name, addr)| format!("{}={}", name, addr)).collect::<Vec<String>>();
+        if !overrides.is_empty() {
+            properties.insert(TXT_ADDRESSES.to_owned(), overrides.join(","));
+        }
+        properties.insert(TXT_LAYOUT.to_owned(), self.layout.to_string());
+        properties
+    }
+
+    fn host_name(&self) -> String {
+        let name = self.instance_name.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' }).collect::<String>();
+        format!("{}.local.", name)
+    }
+}
+
+/// Advertises a server over mDNS until dropped
+pub struct ServiceAdvertiser {
+    daemon: ServiceDaemon,
+    fullnames: Vec<String>,
+}
+
+impl ServiceAdvertiser {
+    pub fn new(advertisement: &Advertisement) -> Result<Self, DiscoveryError> {
+        let daemon = ServiceDaemon::new()?;
+        // Lets servers and clients on the same machine find each other without a network
+        daemon.enable_interface(IfKind::LoopbackV4)?;
+
+        let host_name = advertisement.host_name();
+        let properties = advertisement.properties();
+        let mut services = vec![
+            (LUNATECH_SERVICE_TYPE, advertisement.port),
+            (OSC_SERVICE_TYPE, advertisement.port),
+        ];
+        if let Some(port) = advertisement.oscquery_port {
+            services.push((OSCQUERY_SERVICE_TYPE, port));
+        }
+
+        let mut fullnames = Vec::new();
+        for (service_type, port) in services {
+            let service = ServiceInfo::new(service_type, &advertisement.instance_name, &host_name, "", port, properties.clone())?
+                .enable_addr_auto();
+            fullnames.push(service.get_fullname().to_owned());
+            daemon.register(service)?;
+        }
+
+        Ok(Self { daemon, fullnames })
+    }
+}
+
+impl Drop for ServiceAdvertiser {
+    fn drop(&mut self) {
+        self.fullnames.iter().for_each(|fullname| {
+            let _ = self.daemon.unregister(fullname);
+        });
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// A server found by `browse`
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiscoveredServer {
+    pub instance_name: String,
+    pub addrs: Vec<IpAddr>,
+    pub port: u16,
+    pub version: Option<String>,
+    pub features: Vec<String>,
+    pub multicast: Option<IpAddr>,
+    pub oscquery_port: Option<u16>,
+    pub stream_port: Option<u16>,
+    /// Addresses the features are sent on, the default namespace for servers that don't advertise one
+    pub namespace: Namespace,
+    pub layout: MessageLayout,
+}
+
+impl DiscoveredServer {
+    fn from_service(service: &ServiceInfo) -> Self {
+        let fullname = service.get_fullname();
+        let instance_name = fullname.strip_suffix(LUNATECH_SERVICE_TYPE).unwrap_or(fullname).trim_end_matches('.');
+        let mut addrs = service.get_addresses().iter().cloned().collect::<Vec<IpAddr>>();
+        // Loopback addresses are also advertised by remote servers, so they go last
+        addrs.sort_by_key(|addr| (addr.is_loopback(), addr.is_ipv6()));
+
+        let mut namespace = Namespace::new(service.get_property_val_str(TXT_PREFIX).unwrap_or(DEFAULT_PREFIX));
+        namespace.set_instance(service.get_property_val_str(TXT_INSTANCE));
+        service.get_property_val_str(TXT_ADDRESSES).unwrap_or_default().split(',')
+            .filter_map(|address| address.split_once('='))
+            .for_each(|(name, addr)| namespace.set_override(name, addr));
+
+        Self {
+            instance_name: instance_name.to_owned(),
+            addrs,
+            port: service.get_port(),
+            version: service.get_property_val_str(TXT_VERSION).map(str::to_owned),
+            features: service.get_property_val_str(TXT_FEATURES)
+                .map(|features| features.split(',').filter(|name| !name.is_empty()).map(str::to_owned).collect())
+                .unwrap_or_default(),
+            multicast: service.get_property_val_str(TXT_MULTICAST).and_then(|group| group.parse().ok()),
+            oscquery_port: service.get_property_val_str(TXT_OSCQUERY_PORT).and_then(|port| port.parse().ok()),
+            stream_port: service.get_property_val_str(TXT_STREAM_PORT).and_then(|port| port.parse().ok()),
+            namespace,
+            layout: service.get_property_val_str(TXT_LAYOUT).and_then(|layout| layout.parse().ok()).unwrap_or_default(),
+        }
+    }
+}
+
+/// Browses for LunaTech servers for `timeout` and returns every one that was resolved
+pub fn browse(timeout: Duration) -> Result<Vec<DiscoveredServer>, DiscoveryError> {
+    let daemon = ServiceDaemon::new()?;
+    daemon.enable_interface(IfKind::LoopbackV4)?;
+    let events = daemon.browse(LUNATECH_SERVICE_TYPE)?;
+
+    let mut servers: Vec<DiscoveredServer> = Vec::new();
+    let deadline = Instant::now() + timeout;
+    while let Ok(event) = events.recv_deadline(deadline) {
+        if let ServiceEvent::ServiceResolved(service) = event {
+            let server = DiscoveredServer::from_service(&service);
+            servers.retain(|known| known.instance_name != server.instance_name);
+            servers.push(server);
+        }
+    }
+
+    let _ = daemon.shutdown();
+    Ok(servers)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use lt_utilities::audio_features::FEATURES;
+
+    fn discovered(advertisement: &Advertisement) -> DiscoveredServer {
+        let service = ServiceInfo::new(LUNATECH_SERVICE_TYPE, &advertisement.instance_name, &advertisement.host_name(), "10.0.0.2", advertisement.port, advertisement.properties()).unwrap();
+        DiscoveredServer::from_service(&service)
+    }
+
+    #[test]
+    fn round_trips_advertisements() {
+        let mut namespace = Namespace::new("/venue/audio");
+        namespace.set_instance(Some("stageA"));
+        namespace.set_override("Flux", "/show/flux");
+        namespace.set_override("ZCR", "/show/zcr");
+        let advertisement = Advertisement {
+            multicast: Some(Ipv4Addr::new(239, 1, 2, 3).into()),
+            oscquery_port: Some(5678),
+            stream_port: Some(9000),
+            namespace: namespace.clone(),
+            layout: MessageLayout::Messages,
+            ..Advertisement::new("Stage A", 8000, FEATURES)
+        };
+
+        assert_eq!(discovered(&advertisement), DiscoveredServer {
+            instance_name: "Stage A".to_owned(),
+            addrs: vec![Ipv4Addr::new(10, 0, 0, 2).into()],
+            port: 8000,
+            version: Some(env!("CARGO_PKG_VERSION").to_owned()),
+            features: FEATURES.iter().map(|feature| feature.name.to_string()).collect(),
+            multicast: Some(Ipv4Addr::new(239, 1, 2, 3).into()),
+            oscquery_port: Some(5678),
+            stream_port: Some(9000),
+            namespace,
+            layout: MessageLayout::Messages,
+        });
+    }
+
+    #[test]
+    fn defaults_missing_records() {
+        let server = discovered(&Advertisement::new("lt", 8000, &[]));
+        assert!(server.features.is_empty());
+        assert_eq!((server.multicast, server.oscquery_port, server.stream_port), (None, None, None));
+        assert_eq!(server.namespace, Namespace::default());
+        assert_eq!(server.layout, MessageLayout::Bundle);
+    }
+
+    #[test]
+    fn host_names_are_dns_labels() {
+        assert_eq!(Advertisement::new("Stage A #2", 8000, &[]).host_name(), "stage-a--2.local.");
+    }
+}
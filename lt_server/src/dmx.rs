@@ -0,0 +1,413 @@
+use std::{collections::{BTreeMap, BTreeSet}, fs, net::{SocketAddr, ToSocketAddrs}, str::FromStr};
+use serde_json::{Map, Value};
+
+use lt_utilities::atomic_float::{FeatureDescriptor, FeatureKind};
+use lt_utilities::audio_features::FeatureFrame;
+
+use crate::mapping::find_feature;
+
+/// Channels in a DMX universe
+pub const DMX_CHANNELS: usize = 512;
+/// Slowest refresh rate of the outputs, the period of slower rates can't be waited for
+pub const MIN_REFRESH_RATE: f32 = 0.01;
+
+/// Shapes feature values between the input and output range
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Curve {
+    #[default]
+    Linear,
+    /// Stays low longer, closer to how brightness is perceived
+    Square,
+    /// Rises quickly, for subtle features
+    Sqrt,
+    /// Eases in and out of both ends
+    Smooth,
+}
+
+impl FromStr for Curve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "linear" => Ok(Curve::Linear),
+            "square" => Ok(Curve::Square),
+            "sqrt" => Ok(Curve::Sqrt),
+            "smooth" => Ok(Curve::Smooth),
+            _ => Err(format!("Unknown curve {}, expected linear, square, sqrt or smooth", s)),
+        }
+    }
+}
+
+impl Curve {
+    /// Applies the curve to a position between 0 and 1
+    pub fn apply(&self, position: f32) -> f32 {
+        match self {
+            Curve::Linear => position,
+            Curve::Square => position * position,
+            Curve::Sqrt => position.sqrt(),
+            Curve::Smooth => position * position * (3. - 2. * position),
+        }
+    }
+}
+
+/// Where the value of a DMX channel comes from
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChannelSource {
+    Fixed(u8),
+    Feature {
+        feature: String,
+        /// Element of an array feature
+        index: Option<usize>,
+        /// Feature values mapped to the start and end of the output range, the expected range of the feature by default
+        input: (f32, f32),
+        /// DMX values, 0 to 255 by default, reversed to invert
+        output: (f32, f32),
+        curve: Curve,
+    },
+}
+
+impl ChannelSource {
+    /// Parses a fixed value such as `255` or a feature such as
+    /// `{"feature": "LowRangeRMS", "input": [0, 0.8], "output": [0, 255], "curve": "square"}`
+    pub fn from_json(value: &Value, features: &[FeatureDescriptor]) -> Result<Self, String> {
+        if let Some(fixed) = value.as_u64() {
+            return u8::try_from(fixed).map(ChannelSource::Fixed).map_err(|_| format!("Channel value {} is above 255", fixed));
+        }
+        let range = |field: &str| -> Result<Option<(f32, f32)>, String> {
+            match value.get(field) {
+                None => Ok(None),
+                Some(range) => match range.as_array().map(|range| range.iter().filter_map(Value::as_f64).collect::<Vec<f64>>()).as_deref() {
+                    Some([start, end]) => Ok(Some((*start as f32, *end as f32))),
+                    _ => Err(format!("{} must be a list of two numbers", field)),
+                },
+            }
+        };
+
+        let name = value.get("feature").and_then(Value::as_str).ok_or_else(|| "Expected a value or a feature".to_owned())?;
+        let descriptor = find_feature(features, name).ok_or_else(|| format!("Unknown feature {}", name))?;
+        let index = match value.get("index") {
+            None => None,
+            Some(index) => Some(index.as_u64().ok_or_else(|| "Index must be a positive integer".to_owned())? as usize),
+        };
+        match (&descriptor.kind, index) {
+            (FeatureKind::Scalar, Some(_)) => return Err(format!("{} has no elements", descriptor.name)),
+            (FeatureKind::Array(_), None) => return Err(format!("{} needs an index", descriptor.name)),
+            (FeatureKind::Array(count), Some(index)) if index >= *count => return Err(format!("{} has only {} elements", descriptor.name, count)),
+            _ => {}
+        }
+        let input = range("input")?.unwrap_or((descriptor.min, descriptor.max));
+        let output = range("output")?.unwrap_or((0., 255.));
+        if input.0 == input.1 || ![input.0, input.1].iter().all(|value| value.is_finite()) {
+            return Err(format!("Invalid input range for {}", descriptor.name));
+        }
+        if ![output.0, output.1].iter().all(|value| (0. ..=255.).contains(value)) {
+            return Err(format!("Output range of {} must be within 0 and 255", descriptor.name));
+        }
+        let curve = match value.get("curve") {
+            None => Curve::Linear,
+            Some(curve) => curve.as_str().ok_or_else(|| "Curve must be a string".to_owned())?.parse()?,
+        };
+
+        Ok(ChannelSource::Feature { feature: descriptor.name.to_string(), index, input, output, curve })
+    }
+
+    /// DMX value for a frame, the start of the output range before the first frame
+    pub fn value(&self, frame: Option<&FeatureFrame>) -> u8 {
+        match self {
+            ChannelSource::Fixed(value) => *value,
+            ChannelSource::Feature { feature, index, input, output, curve } => {
+                let value = frame.and_then(|frame| match index {
+                    Some(index) => frame.get_array(feature)?.get(*index).cloned(),
+                    None => frame.get(feature),
+                });
+                let position = value.map(|value| ((value - input.0) / (input.1 - input.0)).clamp(0., 1.)).unwrap_or(0.);
+                (output.0 + curve.apply(position) * (output.1 - output.0)).round() as u8
+            }
+        }
+    }
+}
+
+/// A channel of a universe, numbered from 1 as on consoles
+#[derive(Clone, Debug, PartialEq)]
+pub struct DmxChannel {
+    pub universe: u16,
+    pub channel: u16,
+    pub source: ChannelSource,
+}
+
+/// Channel layout of a fixture type, channels without a source are set to their default
+#[derive(Clone, Debug, PartialEq)]
+pub struct FixtureProfile {
+    pub name: String,
+    /// Name and default value of every channel, in order
+    pub channels: Vec<(String, u8)>,
+}
+
+impl FixtureProfile {
+    pub fn new(name: &str, channels: &[&str]) -> Self {
+        Self { name: name.to_owned(), channels: channels.iter().map(|channel| (channel.to_string(), 0)).collect() }
+    }
+
+    /// Parses a list of channels given as names, or as `{"name": "shutter", "value": 255}` to set a default
+    pub fn from_json(name: &str, value: &Value) -> Result<Self, String> {
+        let channels = value.as_array().ok_or_else(|| format!("Profile {} must be a list of channels", name))?;
+        let channels = channels.iter().map(|channel| match channel {
+            Value::String(channel) => Ok((channel.clone(), 0)),
+            channel => {
+                let channel_name = channel.get("name").and_then(Value::as_str).ok_or_else(|| format!("Channel of profile {} is missing a name", name))?;
+                let default = channel.get("value").map(|value| value.as_u64().and_then(|value| u8::try_from(value).ok()))
+                    .unwrap_or(Some(0))
+                    .ok_or_else(|| format!("Default of {} in profile {} must be 0 to 255", channel_name, name))?;
+                Ok((channel_name.to_owned(), default))
+            }
+        }).collect::<Result<Vec<(String, u8)>, String>>()?;
+        if channels.is_empty() || channels.len() > DMX_CHANNELS {
+            return Err(format!("Profile {} must have 1 to {} channels", name, DMX_CHANNELS));
+        }
+        Ok(Self { name: name.to_owned(), channels })
+    }
+}
+
+/// Profiles that can be used without defining them
+pub fn default_profiles() -> Vec<FixtureProfile> {
+    vec![
+        FixtureProfile::new("dimmer", &["dimmer"]),
+        FixtureProfile::new("rgb", &["red", "green", "blue"]),
+        FixtureProfile::new("rgbw", &["red", "green", "blue", "white"]),
+        FixtureProfile::new("dimmer_rgb", &["dimmer", "red", "green", "blue"]),
+        FixtureProfile::new("dimmer_rgbw", &["dimmer", "red", "green", "blue", "white"]),
+    ]
+}
+
+/// Feature driven values of every used DMX channel
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DmxMap {
+    pub channels: Vec<DmxChannel>,
+}
+
+impl DmxMap {
+    /// Parses the `profiles`, `fixtures` and `channels` of a DMX output config.
+    /// Fixtures such as `{"profile": "rgb", "universe": 1, "address": 1, "channels": {"red": {"feature": "LowRangeRMS"}}}`
+    /// take consecutive channels from their address, single channels are given as `{"universe": 1, "channel": 100, "feature": "Flux"}`
+    pub fn from_json(config: &Value, features: &[FeatureDescriptor]) -> Result<Self, String> {
+        let mut profiles = default_profiles();
+        for (name, profile) in config.get("profiles").and_then(Value::as_object).unwrap_or(&Map::new()) {
+            let profile = FixtureProfile::from_json(name, profile)?;
+            profiles.retain(|existing| existing.name != profile.name);
+            profiles.push(profile);
+        }
+
+        let list = |field: &str| match config.get(field) {
+            None => Ok(Vec::new()),
+            Some(Value::Array(values)) => Ok(values.clone()),
+            Some(_) => Err(format!("{} must be a list", field)),
+        };
+        let number = |value: &Value, field: &str| value.get(field).and_then(Value::as_u64).and_then(|number| u16::try_from(number).ok());
+
+        let mut map = DmxMap::default();
+        for (position, fixture) in list("fixtures")?.iter().enumerate() {
+            let error = |e: String| format!("Fixture {}: {}", position + 1, e);
+            let name = fixture.get("profile").and_then(Value::as_str).ok_or_else(|| error("Missing profile".to_owned()))?;
+            let profile = profiles.iter().find(|profile| profile.name == name).ok_or_else(|| error(format!("Unknown profile {}", name)))?;
+            let universe = number(fixture, "universe").ok_or_else(|| error("Missing universe".to_owned()))?;
+            let address = number(fixture, "address").ok_or_else(|| error("Missing address".to_owned()))?;
+            let sources = fixture.get("channels").and_then(Value::as_object).cloned().unwrap_or_default();
+            if let Some(unknown) = sources.keys().find(|channel| !profile.channels.iter().any(|(name, _)| name == *channel)) {
+                return Err(error(format!("Profile {} has no channel {}", profile.name, unknown)));
+            }
+            for (offset, (channel_name, default)) in profile.channels.iter().enumerate() {
+                let source = match sources.get(channel_name) {
+                    Some(source) => ChannelSource::from_json(source, features).map_err(|e| error(format!("{}: {}", channel_name, e)))?,
+                    None => ChannelSource::Fixed(*default),
+                };
+                map.channels.push(DmxChannel { universe, channel: address.saturating_add(offset as u16), source });
+            }
+        }
+        for (position, channel) in list("channels")?.iter().enumerate() {
+            let error = |e: String| format!("Channel {}: {}", position + 1, e);
+            let universe = number(channel, "universe").ok_or_else(|| error("Missing universe".to_owned()))?;
+            let number = number(channel, "channel").ok_or_else(|| error("Missing channel".to_owned()))?;
+            let source = match channel.get("value") {
+                Some(value) => ChannelSource::from_json(value, features),
+                None => ChannelSource::from_json(channel, features),
+            }.map_err(error)?;
+            map.channels.push(DmxChannel { universe, channel: number, source });
+        }
+
+        map.validate()?;
+        Ok(map)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.channels.is_empty() {
+            return Err("No fixtures or channels are mapped".to_owned());
+        }
+        let mut used = BTreeSet::new();
+        for channel in &self.channels {
+            if channel.channel == 0 || channel.channel as usize > DMX_CHANNELS {
+                return Err(format!("Channel {} of universe {} is outside 1 to {}", channel.channel, channel.universe, DMX_CHANNELS));
+            }
+            if !used.insert((channel.universe, channel.channel)) {
+                return Err(format!("Channel {} of universe {} is mapped more than once", channel.channel, channel.universe));
+            }
+        }
+        Ok(())
+    }
+
+    /// Universes with at least one mapped channel, in order
+    pub fn universes(&self) -> Vec<u16> {
+        let mut universes = self.channels.iter().map(|channel| channel.universe).collect::<Vec<u16>>();
+        universes.sort_unstable();
+        universes.dedup();
+        universes
+    }
+
+    /// The 512 channel values of every universe, unmapped channels are 0
+    pub fn render(&self, frame: Option<&FeatureFrame>) -> BTreeMap<u16, Vec<u8>> {
+        let mut universes = self.universes().into_iter().map(|universe| (universe, vec![0u8; DMX_CHANNELS])).collect::<BTreeMap<u16, Vec<u8>>>();
+        for channel in &self.channels {
+            if let Some(data) = universes.get_mut(&channel.universe) {
+                data[channel.channel as usize - 1] = channel.source.value(frame);
+            }
+        }
+        universes
+    }
+}
+
+/// Optional `rate` of an output config in packets per second, at least `MIN_REFRESH_RATE`
+pub fn parse_rate(config: &Value, default: f32) -> Result<f32, String> {
+    match config.get("rate") {
+        None => Ok(default),
+        Some(rate) => rate.as_f64().map(|rate| rate as f32).filter(|rate| rate.is_finite() && *rate >= MIN_REFRESH_RATE)
+            .ok_or_else(|| format!("Rate must be a number of at least {}", MIN_REFRESH_RATE)),
+    }
+}
+
+/// Reads a JSON output config, see `DmxMap::from_json`
+pub fn load_config(path: &str) -> Result<Value, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    serde_json::from_str(&text).map_err(|e| format!("Invalid config {}: {}", path, e))
+}
+
+/// IPv4 address of a target given as `host` or `host:port`, DMX protocols are IPv4 only
+pub(crate) fn resolve_ipv4(target: &str, default_port: u16) -> Option<SocketAddr> {
+    let addrs = match target.to_socket_addrs() {
+        Ok(addrs) => addrs.collect::<Vec<SocketAddr>>(),
+        Err(_) => (target, default_port).to_socket_addrs().ok()?.collect::<Vec<SocketAddr>>(),
+    };
+    addrs.into_iter().find(SocketAddr::is_ipv4)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use lt_utilities::audio_features::FEATURES;
+
+    use super::*;
+
+    fn map(config: Value) -> Result<DmxMap, String> {
+        DmxMap::from_json(&config, FEATURES)
+    }
+
+    #[test]
+    fn curves_keep_their_ends() {
+        for curve in [Curve::Linear, Curve::Square, Curve::Sqrt, Curve::Smooth] {
+            assert_eq!(curve.apply(0.), 0., "{:?}", curve);
+            assert_eq!(curve.apply(1.), 1., "{:?}", curve);
+        }
+        assert_eq!(Curve::Square.apply(0.5), 0.25);
+        assert_eq!(Curve::Sqrt.apply(0.25), 0.5);
+        assert_eq!(Curve::Smooth.apply(0.5), 0.5);
+        assert!(Curve::Smooth.apply(0.1) < 0.1);
+    }
+
+    #[test]
+    fn lays_out_fixtures_from_their_address() {
+        let map = map(json!({"fixtures": [
+            {"profile": "dimmer_rgb", "universe": 2, "address": 10, "channels": {"red": {"feature": "LowRangeRMS"}, "dimmer": 255}},
+        ]})).unwrap();
+        let channels = map.channels.iter().map(|channel| (channel.universe, channel.channel)).collect::<Vec<(u16, u16)>>();
+        assert_eq!(channels, vec![(2, 10), (2, 11), (2, 12), (2, 13)]);
+        assert_eq!(map.channels[0].source, ChannelSource::Fixed(255));
+        assert_eq!(map.channels[2].source, ChannelSource::Fixed(0));
+        assert!(matches!(&map.channels[1].source, ChannelSource::Feature { feature, .. } if feature == "LowRangeRMS"));
+    }
+
+    #[test]
+    fn renders_universes() {
+        let map = map(json!({
+            "profiles": {"par": ["dimmer", {"name": "shutter", "value": 200}]},
+            "fixtures": [{"profile": "par", "universe": 1, "address": 1, "channels": {"dimmer": {"feature": "Flux", "input": [0, 10]}}}],
+            "channels": [
+                {"universe": 3, "channel": 512, "feature": "SpectrumBands", "index": 1, "output": [255, 0]},
+                {"universe": 3, "channel": 5, "value": 42},
+            ],
+        })).unwrap();
+        assert_eq!(map.universes(), vec![1, 3]);
+
+        let frame = FeatureFrame { flux: 5., spectrum_bands: vec![0., 0.25], ..Default::default() };
+        let universes = map.render(Some(&frame));
+        assert_eq!(universes[&1].len(), DMX_CHANNELS);
+        assert_eq!(&universes[&1][..3], &[128, 200, 0]);
+        assert_eq!(universes[&3][4], 42);
+        assert_eq!(universes[&3][511], 191);
+
+        // Before the first frame features are at the start of their output range
+        let universes = map.render(None);
+        assert_eq!(universes[&1][0], 0);
+        assert_eq!(universes[&3][511], 255);
+    }
+
+    #[test]
+    fn clamps_and_shapes_feature_values() {
+        let source = ChannelSource::from_json(&json!({"feature": "flux", "input": [0, 10], "curve": "square"}), FEATURES).unwrap();
+        let value = |flux: f32| source.value(Some(&FeatureFrame { flux, ..Default::default() }));
+        assert_eq!(value(-5.), 0);
+        assert_eq!(value(5.), 64);
+        assert_eq!(value(50.), 255);
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        let invalid = [
+            json!({}),
+            json!({"fixtures": {}}),
+            json!({"fixtures": [{"profile": "moving_head", "universe": 1, "address": 1}]}),
+            json!({"fixtures": [{"profile": "rgb", "address": 1}]}),
+            json!({"fixtures": [{"profile": "rgb", "universe": 1}]}),
+            json!({"fixtures": [{"profile": "rgb", "universe": 1, "address": 511}]}),
+            json!({"fixtures": [{"profile": "rgb", "universe": 1, "address": 1, "channels": {"amber": 255}}]}),
+            json!({"fixtures": [{"profile": "rgb", "universe": 1, "address": 1}, {"profile": "dimmer", "universe": 1, "address": 3}]}),
+            json!({"channels": [{"universe": 1, "channel": 0, "value": 1}]}),
+            json!({"channels": [{"universe": 1, "channel": 1, "value": 256}]}),
+            json!({"channels": [{"universe": 1, "channel": 1, "feature": "Loudness"}]}),
+            json!({"channels": [{"universe": 1, "channel": 1, "feature": "SpectrumBands"}]}),
+            json!({"channels": [{"universe": 1, "channel": 1, "feature": "Flux", "index": 0}]}),
+            json!({"channels": [{"universe": 1, "channel": 1, "feature": "Flux", "input": [1, 1]}]}),
+            json!({"channels": [{"universe": 1, "channel": 1, "feature": "Flux", "output": [0, 300]}]}),
+            json!({"channels": [{"universe": 1, "channel": 1, "feature": "Flux", "curve": "cubic"}]}),
+            json!({"profiles": {"empty": []}, "channels": [{"universe": 1, "channel": 1, "value": 1}]}),
+        ];
+        for config in invalid {
+            assert!(map(config.clone()).is_err(), "{}", config);
+        }
+    }
+
+    #[test]
+    fn parses_rates() {
+        assert_eq!(parse_rate(&json!({}), 44.), Ok(44.));
+        assert_eq!(parse_rate(&json!({"rate": 30}), 44.), Ok(30.));
+        assert_eq!(parse_rate(&json!({"rate": MIN_REFRESH_RATE}), 44.), Ok(MIN_REFRESH_RATE));
+        for rate in [json!(0), json!(-1), json!(1e-40), json!(0.001), json!(1e300), json!("fast")] {
+            assert!(parse_rate(&json!({"rate": rate}), 44.).is_err(), "{}", rate);
+        }
+    }
+
+    #[test]
+    fn resolves_ipv4_targets() {
+        assert_eq!(resolve_ipv4("127.0.0.1", 6454), Some("127.0.0.1:6454".parse().unwrap()));
+        assert_eq!(resolve_ipv4("127.0.0.1:7000", 6454), Some("127.0.0.1:7000".parse().unwrap()));
+        assert_eq!(resolve_ipv4("[::1]:7000", 6454), None);
+    }
+}
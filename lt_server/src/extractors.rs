@@ -0,0 +1,284 @@
+use std::f32::consts::PI;
+use std::ops::Range;
+
+use lt_utilities::atomic_float::FeatureDescriptor;
+use lt_utilities::audio_features::{FEATURE_BROADRANGERMS, FEATURE_CHROMA, FEATURE_FLUX, FEATURE_HIGHRANGERMS, FEATURE_LOWRANGERMS, FEATURE_MFCC, FEATURE_MIDRANGERMS, FEATURE_SPECTRALCENTROID, FEATURE_SPECTRUMBANDS, FEATURE_ZCR};
+use lt_utilities::extractor::{AnalysisFrame, Extractor, ExtractorRegistry};
+
+use crate::analyzer::{compute_rms, compute_spectral_centroid, compute_zcr, get_filtered_by_range};
+
+pub const LOW_RANGE: Range<f32> = 0.0..250.; // Hz
+pub const MID_RANGE: Range<f32> = 250.0..4000.; // Hz
+pub const HIGH_RANGE: Range<f32> = 4000.0..20000.; // Hz
+pub const SPECTRUM_RANGE: Range<f32> = 20.0..20000.; // Hz
+pub const CHROMA_RANGE: Range<f32> = 55.0..5000.; // Hz
+pub const MFCC_RANGE: Range<f32> = 20.0..8000.; // Hz
+/// Number of mel bands the cepstral coefficients are computed from
+pub const MEL_BAND_COUNT: usize = 26;
+pub const DEFAULT_SPECTRUM_BANDS: usize = 16;
+/// Parameter setting the number of spectrum bands
+pub const PARAMETER_BANDS: &str = "bands";
+
+/// Registry containing the built in LunaTech features
+pub fn default_registry() -> ExtractorRegistry {
+    let mut registry = ExtractorRegistry::new();
+    registry.register(BandRMS::new(FEATURE_BROADRANGERMS, None));
+    registry.register(BandRMS::new(FEATURE_LOWRANGERMS, Some(LOW_RANGE)));
+    registry.register(BandRMS::new(FEATURE_MIDRANGERMS, Some(MID_RANGE)));
+    registry.register(BandRMS::new(FEATURE_HIGHRANGERMS, Some(HIGH_RANGE)));
+    registry.register(ZeroCrossingRate);
+    registry.register(SpectralCentroid);
+    registry.register(SpectralFlux::default());
+    registry.register(SpectrumBands::new(DEFAULT_SPECTRUM_BANDS));
+    registry.register(Chroma);
+    registry.register(Mfcc);
+    registry
+}
+
+/// RMS of the log compressed spectrum, optionally limited to a frequency range
+pub struct BandRMS {
+    feature: FeatureDescriptor,
+    range: Option<Range<f32>>,
+}
+
+impl BandRMS {
+    pub fn new(feature: FeatureDescriptor, range: Option<Range<f32>>) -> Self {
+        Self { feature, range }
+    }
+}
+
+impl Extractor for BandRMS {
+    fn features(&self) -> Vec<FeatureDescriptor> {
+        vec![self.feature.clone()]
+    }
+
+    fn extract(&mut self, frame: &AnalysisFrame) -> Vec<f32> {
+        let rms = match &self.range {
+            Some(range) => compute_rms(&get_filtered_by_range(frame.log_magnitudes, frame.freqs, range.clone())),
+            None => compute_rms(frame.log_magnitudes),
+        };
+        vec![rms / 2.0]
+    }
+
+    fn combine(&self, channel_values: &[f32]) -> f32 {
+        (channel_values.iter().sum::<f32>() / channel_values.len().max(1) as f32).clamp(0., 1.)
+    }
+}
+
+/// RMS of logarithmically spaced bands of the log compressed spectrum
+pub struct SpectrumBands {
+    band_count: usize,
+}
+
+impl SpectrumBands {
+    pub fn new(band_count: usize) -> Self {
+        Self { band_count: band_count.clamp(1, FEATURE_SPECTRUMBANDS.len()) }
+    }
+}
+
+impl Extractor for SpectrumBands {
+    fn features(&self) -> Vec<FeatureDescriptor> {
+        vec![FEATURE_SPECTRUMBANDS.with_len(self.band_count)]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) -> bool {
+        if name != PARAMETER_BANDS {
+            return false;
+        }
+        self.band_count = (value as usize).clamp(1, FEATURE_SPECTRUMBANDS.len());
+        true
+    }
+
+    fn extract(&mut self, frame: &AnalysisFrame) -> Vec<f32> {
+        let ratio = SPECTRUM_RANGE.end / SPECTRUM_RANGE.start;
+        let edge = |band: usize| SPECTRUM_RANGE.start * ratio.powf(band as f32 / self.band_count as f32);
+        (0..self.band_count).map(|band| {
+            let magnitudes = get_filtered_by_range(frame.log_magnitudes, frame.freqs, edge(band)..edge(band + 1));
+            if magnitudes.is_empty() {
+                return 0.;
+            }
+            compute_rms(&magnitudes) / 2.0
+        }).collect()
+    }
+
+    fn combine(&self, channel_values: &[f32]) -> f32 {
+        (channel_values.iter().sum::<f32>() / channel_values.len().max(1) as f32).clamp(0., 1.)
+    }
+}
+
+pub struct ZeroCrossingRate;
+
+impl Extractor for ZeroCrossingRate {
+    fn features(&self) -> Vec<FeatureDescriptor> {
+        vec![FEATURE_ZCR]
+    }
+
+    fn extract(&mut self, frame: &AnalysisFrame) -> Vec<f32> {
+        vec![compute_zcr(frame.samples)]
+    }
+}
+
+pub struct SpectralCentroid;
+
+impl Extractor for SpectralCentroid {
+    fn features(&self) -> Vec<FeatureDescriptor> {
+        vec![FEATURE_SPECTRALCENTROID]
+    }
+
+    fn extract(&mut self, frame: &AnalysisFrame) -> Vec<f32> {
+        vec![compute_spectral_centroid(frame.magnitudes, frame.freqs)]
+    }
+}
+
+#[derive(Default)]
+pub struct SpectralFlux {
+    last_frame_buffer: Vec<Vec<f32>>,
+}
+
+impl Extractor for SpectralFlux {
+    fn features(&self) -> Vec<FeatureDescriptor> {
+        vec![FEATURE_FLUX]
+    }
+
+    fn extract(&mut self, frame: &AnalysisFrame) -> Vec<f32> {
+        if self.last_frame_buffer.len() <= frame.channel_index {
+            self.last_frame_buffer.resize(frame.channel_index + 1, Vec::new());
+        }
+
+        let last_frame = std::mem::replace(&mut self.last_frame_buffer[frame.channel_index], frame.samples.to_vec());
+
+        // check which vec is longer and cut it down to the length of the shorter one
+        let length = std::cmp::min(frame.magnitudes.len(), last_frame.len());
+        let flux = frame.magnitudes[..length].iter().zip(&last_frame[..length]).map(|(x, last)| {
+            (x - last).powf(2.)
+        }).sum::<f32>().sqrt();
+        vec![flux]
+    }
+}
+
+/// Energy of each pitch class from C to B, relative to the strongest
+pub struct Chroma;
+
+impl Extractor for Chroma {
+    fn features(&self) -> Vec<FeatureDescriptor> {
+        vec![FEATURE_CHROMA]
+    }
+
+    fn extract(&mut self, frame: &AnalysisFrame) -> Vec<f32> {
+        let mut chroma = vec![0.; FEATURE_CHROMA.len()];
+        frame.magnitudes.iter().zip(frame.freqs).filter(|(_, freq)| CHROMA_RANGE.contains(freq)).for_each(|(magnitude, freq)| {
+            // MIDI note 69 is A4 at 440 Hz, C is pitch class 0
+            let note = (12. * (freq / 440.).log2()).round() as i32 + 69;
+            chroma[note.rem_euclid(12) as usize] += magnitude * magnitude;
+        });
+        let strongest = chroma.iter().cloned().fold(0., f32::max);
+        if strongest > 0. {
+            chroma.iter_mut().for_each(|energy| *energy /= strongest);
+        }
+        chroma
+    }
+
+    fn combine(&self, channel_values: &[f32]) -> f32 {
+        (channel_values.iter().sum::<f32>() / channel_values.len().max(1) as f32).clamp(0., 1.)
+    }
+}
+
+/// Mel frequency cepstral coefficients, the DCT of the log energies of triangular mel bands
+pub struct Mfcc;
+
+impl Extractor for Mfcc {
+    fn features(&self) -> Vec<FeatureDescriptor> {
+        vec![FEATURE_MFCC]
+    }
+
+    fn extract(&mut self, frame: &AnalysisFrame) -> Vec<f32> {
+        let to_mel = |hz: f32| 2595. * (1. + hz / 700.).log10();
+        let to_hz = |mel: f32| 700. * (10f32.powf(mel / 2595.) - 1.);
+        let (low, high) = (to_mel(MFCC_RANGE.start), to_mel(MFCC_RANGE.end));
+        let edges = (0..MEL_BAND_COUNT + 2).map(|edge| {
+            to_hz(low + (high - low) * edge as f32 / (MEL_BAND_COUNT + 1) as f32)
+        }).collect::<Vec<f32>>();
+
+        let log_energies = edges.windows(3).map(|edge| {
+            let energy = frame.magnitudes.iter().zip(frame.freqs).map(|(magnitude, &freq)| {
+                let weight = if freq > edge[0] && freq <= edge[1] {
+                    (freq - edge[0]) / (edge[1] - edge[0])
+                } else if freq > edge[1] && freq < edge[2] {
+                    (edge[2] - freq) / (edge[2] - edge[1])
+                } else {
+                    0.
+                };
+                weight * magnitude * magnitude
+            }).sum::<f32>();
+            (energy + 1e-10).ln()
+        }).collect::<Vec<f32>>();
+
+        // Orthonormal DCT-II
+        let count = MEL_BAND_COUNT as f32;
+        (0..FEATURE_MFCC.len()).map(|coefficient| {
+            let scale = if coefficient == 0 { (1. / count).sqrt() } else { (2. / count).sqrt() };
+            scale * log_energies.iter().enumerate().map(|(band, energy)| {
+                energy * (PI * coefficient as f32 * (band as f32 + 0.5) / count).cos()
+            }).sum::<f32>()
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lt_utilities::audio_features::FEATURES;
+
+    use super::*;
+
+    fn extract(extractor: &mut impl Extractor, magnitudes: &[f32], freqs: &[f32]) -> Vec<f32> {
+        extractor.extract(&AnalysisFrame { channel_index: 0, sample_rate: 48000, samples: &[], magnitudes, log_magnitudes: magnitudes, freqs })
+    }
+
+    #[test]
+    fn default_registry_has_every_built_in_feature() {
+        let registry = default_registry();
+        let names = registry.names();
+        assert_eq!(names, FEATURES.iter().map(|feature| feature.name.to_string()).collect::<Vec<String>>());
+        let bands = registry.features().into_iter().find(|feature| feature.name == FEATURE_SPECTRUMBANDS.name).unwrap();
+        assert_eq!(bands.len(), DEFAULT_SPECTRUM_BANDS);
+    }
+
+    #[test]
+    fn spectrum_bands_follow_the_bands_parameter() {
+        let mut bands = SpectrumBands::new(DEFAULT_SPECTRUM_BANDS);
+        assert!(bands.set_parameter(PARAMETER_BANDS, 4.));
+        assert!(!bands.set_parameter("gain", 4.));
+        assert_eq!(bands.features()[0].len(), 4);
+        let freqs = (0..512).map(|bin| bin as f32 * 46.875).collect::<Vec<f32>>();
+        assert_eq!(extract(&mut bands, &[0.; 512], &freqs), vec![0.; 4]);
+
+        bands.set_parameter(PARAMETER_BANDS, 1000.);
+        assert_eq!(bands.features()[0].len(), FEATURE_SPECTRUMBANDS.len());
+        bands.set_parameter(PARAMETER_BANDS, 0.);
+        assert_eq!(bands.features()[0].len(), 1);
+    }
+
+    #[test]
+    fn chroma_follows_the_pitch_class() {
+        let freqs = [110., 261.63, 440., 10000.];
+        let chroma = extract(&mut Chroma, &[0., 0.5, 1., 1.], &freqs);
+        assert_eq!(chroma.len(), 12);
+        // A is the strongest, C has a quarter of its energy and 10 kHz is out of range
+        assert_eq!(chroma[9], 1.);
+        assert_eq!(chroma[0], 0.25);
+        assert_eq!(chroma.iter().sum::<f32>(), 1.25);
+        assert_eq!(extract(&mut Chroma, &[0.; 4], &freqs), vec![0.; 12]);
+    }
+
+    #[test]
+    fn mfcc_of_silence_is_flat() {
+        let freqs = (0..512).map(|bin| bin as f32 * 46.875).collect::<Vec<f32>>();
+        let mfcc = extract(&mut Mfcc, &[0.; 512], &freqs);
+        assert_eq!(mfcc.len(), 13);
+        assert!((mfcc[0] - (MEL_BAND_COUNT as f32).sqrt() * 1e-10f32.ln()).abs() < 1e-3);
+        assert!(mfcc[1..].iter().all(|coefficient| coefficient.abs() < 1e-3));
+
+        let tone = freqs.iter().map(|&freq| if (1000.0..1050.).contains(&freq) { 1. } else { 0. }).collect::<Vec<f32>>();
+        assert!(extract(&mut Mfcc, &tone, &freqs)[0] > mfcc[0]);
+    }
+}
@@ -0,0 +1,77 @@
+use std::{fmt, time::Duration};
+use rosc::{OscMessage, OscType};
+
+/// Arguments: server id, uptime in seconds, sample rate, device name, state
+pub const OSC_ADDR_HEARTBEAT: &str = "/lt/heartbeat";
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ServerState {
+    Running,
+    Stopped,
+}
+
+impl ServerState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServerState::Running => "running",
+            ServerState::Stopped => "stopped",
+        }
+    }
+
+    pub fn parse(state: &str) -> Option<Self> {
+        match state {
+            "running" => Some(ServerState::Running),
+            "stopped" => Some(ServerState::Stopped),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ServerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Presence information a server sends with every heartbeat
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerInfo {
+    /// Unique for every server process
+    pub server_id: String,
+    pub uptime: Duration,
+    pub sample_rate: u32,
+    pub device_name: String,
+    pub state: ServerState,
+}
+
+impl ServerInfo {
+    pub fn to_message(&self) -> OscMessage {
+        OscMessage {
+            addr: OSC_ADDR_HEARTBEAT.to_owned(),
+            args: vec![
+                OscType::String(self.server_id.clone()),
+                OscType::Float(self.uptime.as_secs_f32()),
+                OscType::Int(self.sample_rate as i32),
+                OscType::String(self.device_name.clone()),
+                OscType::String(self.state.as_str().to_owned()),
+            ],
+        }
+    }
+
+    pub fn from_message(msg: &OscMessage) -> Option<Self> {
+        if msg.addr != OSC_ADDR_HEARTBEAT {
+            return None;
+        }
+        match msg.args.as_slice() {
+            [OscType::String(server_id), OscType::Float(uptime), OscType::Int(sample_rate), OscType::String(device_name), OscType::String(state)] => Some(Self {
+                server_id: server_id.clone(),
+                uptime: Duration::from_secs_f32(uptime.max(0.)),
+                sample_rate: *sample_rate as u32,
+                device_name: device_name.clone(),
+                state: ServerState::parse(state)?,
+            }),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::{fmt, str::FromStr};
+use rosc::{OscBundle, OscMessage, OscPacket, OscTime, OscType};
+
+use lt_utilities::audio_features::FeatureFrame;
+use lt_utilities::namespace::Namespace;
+
+use crate::server::OSC_ADDR_SEQUENCE;
+use crate::subscription::Subscription;
+
+/// How the values of a frame are put into packets
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MessageLayout {
+    /// One timetagged bundle per frame, starting with the sequence number
+    #[default]
+    Bundle,
+    /// One message per feature, for receivers that don't understand bundles
+    Messages,
+    /// One `<prefix>/frame` message with every value as an argument, arrays last
+    Frame,
+}
+
+impl FromStr for MessageLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "bundle" => Ok(MessageLayout::Bundle),
+            "messages" => Ok(MessageLayout::Messages),
+            "frame" => Ok(MessageLayout::Frame),
+            _ => Err(format!("Unknown layout {}, expected bundle, messages or frame", s)),
+        }
+    }
+}
+
+impl fmt::Display for MessageLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageLayout::Bundle => f.write_str("bundle"),
+            MessageLayout::Messages => f.write_str("messages"),
+            MessageLayout::Frame => f.write_str("frame"),
+        }
+    }
+}
+
+/// Packets holding the values the subscription selects.
+/// Only bundles carry the timetag and sequence number
+pub fn frame_packets(frame: &FeatureFrame, timetag: OscTime, sequence: u32, subscription: &Subscription, namespace: &Namespace) -> Vec<OscPacket> {
+    match subscription.layout {
+        MessageLayout::Bundle => vec![features_bundle(frame, timetag, sequence, subscription, namespace)],
+        MessageLayout::Messages => subscription.select(frame, namespace).into_iter().map(|(addr, values)| value_message(addr, &values)).collect(),
+        MessageLayout::Frame => {
+            let values = subscription.select(frame, namespace).into_iter().flat_map(|(_, values)| values).collect::<Vec<f32>>();
+            vec![value_message(namespace.frame_address(), &values)]
+        }
+    }
+}
+
+/// Bundle of the sequence number and the values the subscription selects
+pub fn features_bundle(frame: &FeatureFrame, timetag: OscTime, sequence: u32, subscription: &Subscription, namespace: &Namespace) -> OscPacket {
+    let sequence = OscPacket::Message(OscMessage {
+        addr: OSC_ADDR_SEQUENCE.to_owned(),
+        args: vec![OscType::Int(sequence as i32)],
+    });
+    OscPacket::Bundle(OscBundle {
+        timetag,
+        content: std::iter::once(sequence).chain(subscription.select(frame, namespace).into_iter().map(|(addr, values)| {
+            value_message(addr, &values)
+        })).collect(),
+    })
+}
+
+fn value_message(addr: String, values: &[f32]) -> OscPacket {
+    OscPacket::Message(OscMessage {
+        addr,
+        args: values.iter().map(|value| OscType::Float(*value)).collect(),
+    })
+}
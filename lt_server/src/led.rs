@@ -0,0 +1,521 @@
+use std::{fmt, io, net::{Ipv4Addr, SocketAddr, UdpSocket}, str::FromStr, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}, thread, time::{Duration, Instant}};
+use serde_json::Value;
+
+use lt_utilities::audio_features::FeatureFrame;
+
+use crate::destination::SendErrorLog;
+use crate::dmx::{load_config, resolve_ipv4, MIN_REFRESH_RATE};
+
+/// UDP port of the WLED realtime protocols
+pub const WLED_PORT: u16 = 21324;
+/// UDP port of DDP receivers
+pub const DDP_PORT: u16 = 4048;
+pub const DEFAULT_REFRESH_RATE: f32 = 60.;
+/// Seconds WLED waits after the last packet before returning to its own effects
+pub const DEFAULT_WLED_TIMEOUT: u8 = 2;
+
+/// LEDs per DNRGB packet
+const DNRGB_LEDS: usize = 489;
+/// Data bytes per DDP packet, 480 RGB LEDs
+const DDP_DATA_SIZE: usize = 1440;
+/// Most LEDs of a DDP strip, as many as DNRGB addresses. Larger displays are split over several controllers
+const DDP_MAX_LEDS: usize = 65536;
+const DDP_VERSION_1: u8 = 0x40;
+const DDP_PUSH: u8 = 0x01;
+/// 8 bit RGB pixels
+const DDP_TYPE_RGB8: u8 = 0x0B;
+/// Default output device of the receiver
+const DDP_DESTINATION_DISPLAY: u8 = 0x01;
+
+/// Frames with a flux this many times above its running average count as beats
+const BEAT_THRESHOLD: f32 = 1.6;
+/// Flux below this is never a beat, so silence doesn't flash
+const BEAT_MIN_FLUX: f32 = 1.;
+const BEAT_HOLDOFF: Duration = Duration::from_millis(150);
+/// Time a beat flash takes to fade to about a third
+const FLASH_DECAY: Duration = Duration::from_millis(150);
+
+pub type Rgb = [u8; 3];
+
+/// Realtime protocol a strip is sent with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LedProtocol {
+    /// WLED, an index and color per LED, at most 255 LEDs
+    Warls,
+    /// WLED, colors from the first LED, at most 490 LEDs
+    Drgb,
+    /// WLED, colors from a start index, split over several packets for long strips
+    #[default]
+    Dnrgb,
+    /// Distributed Display Protocol, supported by WLED and most pixel controllers
+    Ddp,
+}
+
+impl FromStr for LedProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "warls" => Ok(LedProtocol::Warls),
+            "drgb" => Ok(LedProtocol::Drgb),
+            "dnrgb" => Ok(LedProtocol::Dnrgb),
+            "ddp" => Ok(LedProtocol::Ddp),
+            _ => Err(format!("Unknown protocol {}, expected warls, drgb, dnrgb or ddp", s)),
+        }
+    }
+}
+
+impl fmt::Display for LedProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedProtocol::Warls => f.write_str("WARLS"),
+            LedProtocol::Drgb => f.write_str("DRGB"),
+            LedProtocol::Dnrgb => f.write_str("DNRGB"),
+            LedProtocol::Ddp => f.write_str("DDP"),
+        }
+    }
+}
+
+impl LedProtocol {
+    pub fn default_port(&self) -> u16 {
+        match self {
+            LedProtocol::Ddp => DDP_PORT,
+            _ => WLED_PORT,
+        }
+    }
+
+    /// Most LEDs the protocol can address
+    pub fn max_leds(&self) -> usize {
+        match self {
+            LedProtocol::Warls => 255,
+            LedProtocol::Drgb => 490,
+            LedProtocol::Dnrgb => u16::MAX as usize + 1,
+            LedProtocol::Ddp => DDP_MAX_LEDS,
+        }
+    }
+
+    /// Packets setting every LED. `timeout` is used by the WLED protocols,
+    /// `sequence` by DDP where 1 to 15 let receivers drop duplicates
+    pub fn encode(&self, pixels: &[Rgb], timeout: u8, sequence: u8) -> Vec<Vec<u8>> {
+        match self {
+            LedProtocol::Warls => vec![
+                [1, timeout].into_iter().chain(pixels.iter().take(255).enumerate().flat_map(|(index, [r, g, b])| [index as u8, *r, *g, *b])).collect(),
+            ],
+            LedProtocol::Drgb => vec![
+                [2, timeout].into_iter().chain(pixels.iter().take(490).flatten().cloned()).collect(),
+            ],
+            LedProtocol::Dnrgb => pixels.chunks(DNRGB_LEDS).enumerate().map(|(chunk, pixels)| {
+                let [start_high, start_low] = ((chunk * DNRGB_LEDS) as u16).to_be_bytes();
+                [4, timeout, start_high, start_low].into_iter().chain(pixels.iter().flatten().cloned()).collect()
+            }).collect(),
+            LedProtocol::Ddp => {
+                let data = pixels.iter().flatten().cloned().collect::<Vec<u8>>();
+                let chunks = data.chunks(DDP_DATA_SIZE).count();
+                data.chunks(DDP_DATA_SIZE).enumerate().map(|(chunk, data)| {
+                    // The last packet tells the receiver to show the frame
+                    let flags = if chunk + 1 == chunks { DDP_VERSION_1 | DDP_PUSH } else { DDP_VERSION_1 };
+                    let mut packet = vec![flags, sequence & 0x0F, DDP_TYPE_RGB8, DDP_DESTINATION_DISPLAY];
+                    packet.extend_from_slice(&((chunk * DDP_DATA_SIZE) as u32).to_be_bytes());
+                    packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+                    packet.extend_from_slice(data);
+                    packet
+                }).collect()
+            }
+        }
+    }
+}
+
+/// Built in audio reactive patterns
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Pattern {
+    /// Low, mid and high range RMS as three bars fading from green to red
+    #[default]
+    Vu,
+    /// The spectrum bands spread over the strip in rainbow colors
+    Spectrum,
+    /// The whole strip flashes on beats and fades out
+    Beat,
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "vu" => Ok(Pattern::Vu),
+            "spectrum" => Ok(Pattern::Spectrum),
+            "beat" => Ok(Pattern::Beat),
+            _ => Err(format!("Unknown pattern {}, expected vu, spectrum or beat", s)),
+        }
+    }
+}
+
+/// A strip and what is shown on it
+#[derive(Clone, Debug, PartialEq)]
+pub struct LedStrip {
+    pub target: SocketAddr,
+    pub protocol: LedProtocol,
+    pub leds: usize,
+    pub pattern: Pattern,
+    /// Frames sent per second
+    pub rate: f32,
+    /// Scales every color, from 0 to 1
+    pub brightness: f32,
+    /// Color of beat flashes
+    pub color: Rgb,
+    /// Shows the pattern from the other end of the strip
+    pub reverse: bool,
+    /// Seconds WLED keeps showing the last frame
+    pub timeout: u8,
+}
+
+impl LedStrip {
+    /// Parses a strip such as `{"target": "10.0.0.80", "leds": 150, "pattern": "spectrum"}`.
+    /// `protocol`, `rate`, `brightness`, `color`, `reverse` and `timeout` are optional
+    pub fn from_json(value: &Value) -> Result<Self, String> {
+        let protocol = match value.get("protocol") {
+            None => LedProtocol::default(),
+            Some(protocol) => protocol.as_str().ok_or_else(|| "Protocol must be a string".to_owned())?.parse()?,
+        };
+        let target = value.get("target").and_then(Value::as_str).ok_or_else(|| "Strip is missing target".to_owned())?;
+        let target = resolve_ipv4(target, protocol.default_port()).ok_or_else(|| format!("No IPv4 address found for {}", target))?;
+        let leds = value.get("leds").and_then(Value::as_u64).ok_or_else(|| "Strip is missing leds".to_owned())? as usize;
+        let pattern = match value.get("pattern") {
+            None => Pattern::default(),
+            Some(pattern) => pattern.as_str().ok_or_else(|| "Pattern must be a string".to_owned())?.parse()?,
+        };
+        let number = |field: &str, default: f32| match value.get(field) {
+            None => Ok(default),
+            Some(number) => number.as_f64().map(|number| number as f32).ok_or_else(|| format!("{} must be a number", field)),
+        };
+        let color = match value.get("color") {
+            None => [255, 255, 255],
+            Some(color) => match color.as_array().map(|color| color.iter().filter_map(|value| value.as_u64().and_then(|value| u8::try_from(value).ok())).collect::<Vec<u8>>()).as_deref() {
+                Some([r, g, b]) => [*r, *g, *b],
+                _ => return Err("Color must be a list of three values from 0 to 255".to_owned()),
+            },
+        };
+        let timeout = match value.get("timeout") {
+            None => DEFAULT_WLED_TIMEOUT,
+            Some(timeout) => timeout.as_u64().and_then(|timeout| u8::try_from(timeout).ok()).ok_or_else(|| "Timeout must be 0 to 255 seconds".to_owned())?,
+        };
+
+        let strip = Self {
+            target,
+            protocol,
+            leds,
+            pattern,
+            rate: number("rate", DEFAULT_REFRESH_RATE)?,
+            brightness: number("brightness", 1.)?,
+            color,
+            reverse: value.get("reverse").and_then(Value::as_bool).unwrap_or(false),
+            timeout,
+        };
+        strip.validate()?;
+        Ok(strip)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.leds == 0 || self.leds > self.protocol.max_leds() {
+            return Err(format!("{} supports 1 to {} LEDs", self.protocol, self.protocol.max_leds()));
+        }
+        if !self.rate.is_finite() || self.rate < MIN_REFRESH_RATE {
+            return Err(format!("Rate must be a number of at least {}", MIN_REFRESH_RATE));
+        }
+        if !(0. ..=1.).contains(&self.brightness) {
+            return Err("Brightness must be from 0 to 1".to_owned());
+        }
+        Ok(())
+    }
+}
+
+/// Reads strips from a config such as `{"strips": [...]}`, see `LedStrip::from_json`
+pub fn parse_strips(config: &Value) -> Result<Vec<LedStrip>, String> {
+    let strips = config.get("strips").and_then(Value::as_array).ok_or_else(|| "Expected a list of strips".to_owned())?;
+    strips.iter().enumerate().map(|(index, strip)| {
+        LedStrip::from_json(strip).map_err(|e| format!("Strip {}: {}", index + 1, e))
+    }).collect()
+}
+
+pub fn load_strips(path: &str) -> Result<Vec<LedStrip>, String> {
+    parse_strips(&load_config(path)?)
+}
+
+/// Finds beats as jumps of the flux above its running average
+#[derive(Debug, Default)]
+pub struct BeatDetector {
+    /// `None` until the first frame, which is never a beat
+    average_flux: Option<f32>,
+    last_beat: Option<Instant>,
+}
+
+impl BeatDetector {
+    /// Returns true if the frame is a beat, expects every analyzed frame
+    pub fn update(&mut self, frame: &FeatureFrame) -> bool {
+        let average_flux = self.average_flux.unwrap_or(frame.flux);
+        let beat = frame.flux > BEAT_MIN_FLUX
+            && frame.flux > average_flux * BEAT_THRESHOLD
+            && self.last_beat.map(|last_beat| last_beat.elapsed() >= BEAT_HOLDOFF).unwrap_or(true);
+        self.average_flux = Some(average_flux + (frame.flux - average_flux) * 0.1);
+        if beat {
+            self.last_beat = Some(Instant::now());
+        }
+        beat
+    }
+
+    pub fn last_beat(&self) -> Option<Instant> {
+        self.last_beat
+    }
+}
+
+/// Color of a hue from 0 to 1 at full saturation and value
+fn hue_to_rgb(hue: f32) -> [f32; 3] {
+    let channel = |offset: f32| (((hue + offset).fract() * 6. - 3.).abs() - 1.).clamp(0., 1.);
+    [channel(0.), channel(2. / 3.), channel(1. / 3.)]
+}
+
+/// Renders the pattern of a strip from the latest frame
+pub fn render(strip: &LedStrip, frame: Option<&FeatureFrame>, last_beat: Option<Instant>) -> Vec<Rgb> {
+    let mut pixels = vec![[0f32; 3]; strip.leds];
+    match (strip.pattern, frame) {
+        (Pattern::Vu, Some(frame)) => {
+            let bars = [frame.low_range_rms, frame.mid_range_rms, frame.high_range_rms];
+            let length = (strip.leds / bars.len()).max(1);
+            for (bar, level) in bars.iter().enumerate() {
+                let lit = (level.clamp(0., 1.) * length as f32).round() as usize;
+                for (position, pixel) in pixels.iter_mut().skip(bar * length).take(lit.min(length)).enumerate() {
+                    let position = position as f32 / length as f32;
+                    *pixel = [(position * 2.).min(1.), ((1. - position) * 2.).min(1.), 0.];
+                }
+            }
+        }
+        (Pattern::Spectrum, Some(frame)) if !frame.spectrum_bands.is_empty() => {
+            let bands = &frame.spectrum_bands;
+            for (index, pixel) in pixels.iter_mut().enumerate() {
+                let level = bands[index * bands.len() / strip.leds].clamp(0., 1.);
+                *pixel = hue_to_rgb(index as f32 / strip.leds as f32 * 0.8).map(|channel| channel * level);
+            }
+        }
+        (Pattern::Beat, _) => {
+            let flash = last_beat.map(|last_beat| (-last_beat.elapsed().as_secs_f32() / FLASH_DECAY.as_secs_f32()).exp()).unwrap_or(0.);
+            pixels.iter_mut().for_each(|pixel| *pixel = strip.color.map(|channel| channel as f32 / 255. * flash));
+        }
+        _ => {}
+    }
+    if strip.reverse {
+        pixels.reverse();
+    }
+    pixels.iter().map(|pixel| pixel.map(|channel| (channel * strip.brightness * 255.).round() as u8)).collect()
+}
+
+/// Renders patterns to LED strips and sends them over the WLED realtime protocols or DDP
+pub struct LedSender {
+    socket: Arc<UdpSocket>,
+    strips: Vec<LedStrip>,
+}
+
+impl LedSender {
+    pub fn new(strips: Vec<LedStrip>) -> io::Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.set_broadcast(true)?;
+        Ok(Self { socket: Arc::new(socket), strips })
+    }
+
+    /// Sends every strip at its own rate and returns the sender frames are read from,
+    /// see `LunaTechServer::add_frame_listener`
+    pub fn start(self) -> crossbeam::channel::Sender<FeatureFrame> {
+        let (tx, rx) = crossbeam::channel::unbounded::<FeatureFrame>();
+        let latest = Arc::new(Mutex::new(None::<FeatureFrame>));
+        let beats = Arc::new(Mutex::new(BeatDetector::default()));
+        // Cleared once every server sending frames is gone
+        let alive = Arc::new(AtomicBool::new(true));
+
+        let (receiving, receiving_beats, receiving_alive) = (latest.clone(), beats.clone(), alive.clone());
+        thread::spawn(move || {
+            // Beats are found on every frame, strips may be sent less often
+            for frame in rx {
+                if let Ok(mut beats) = receiving_beats.lock() {
+                    beats.update(&frame);
+                }
+                if let Ok(mut latest) = receiving.lock() {
+                    *latest = Some(frame);
+                }
+            }
+            receiving_alive.store(false, Ordering::Relaxed);
+        });
+
+        for strip in self.strips {
+            let (socket, latest, beats, alive) = (self.socket.clone(), latest.clone(), beats.clone(), alive.clone());
+            thread::spawn(move || {
+                let period = Duration::from_secs_f32(1. / strip.rate);
+                let mut sequence = 0u8;
+                let mut next_send = Instant::now();
+                let mut errors = SendErrorLog::default();
+                while alive.load(Ordering::Relaxed) {
+                    let frame = latest.lock().ok().and_then(|latest| latest.clone());
+                    let last_beat = beats.lock().ok().and_then(|beats| beats.last_beat());
+                    // DDP sequence numbers run from 1 to 15
+                    sequence = sequence % 15 + 1;
+                    for packet in strip.protocol.encode(&render(&strip, frame.as_ref(), last_beat), strip.timeout, sequence) {
+                        if let Err(e) = socket.send_to(&packet, strip.target) {
+                            errors.print("LEDs", strip.target, &e);
+                        }
+                    }
+
+                    next_send += period;
+                    let now = Instant::now();
+                    if next_send > now {
+                        thread::sleep(next_send - now);
+                    } else {
+                        next_send = now;
+                    }
+                }
+            });
+        }
+
+        tx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixels(count: usize) -> Vec<Rgb> {
+        (0..count).map(|index| [index as u8, (index >> 8) as u8, 7]).collect()
+    }
+
+    fn strip(pattern: Pattern, leds: usize) -> LedStrip {
+        LedStrip::from_json(&serde_json::json!({"target": "127.0.0.1", "leds": leds, "pattern": format!("{:?}", pattern)})).unwrap()
+    }
+
+    #[test]
+    fn warls_layout() {
+        let packets = LedProtocol::Warls.encode(&pixels(300), 5, 0);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(&packets[0][..10], &[1, 5, 0, 0, 0, 7, 1, 1, 0, 7]);
+        assert_eq!(packets[0].len(), 2 + 255 * 4);
+        assert_eq!(&packets[0][packets[0].len() - 4..], &[254, 254, 0, 7]);
+    }
+
+    #[test]
+    fn drgb_layout() {
+        let packets = LedProtocol::Drgb.encode(&pixels(600), 2, 0);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(&packets[0][..8], &[2, 2, 0, 0, 7, 1, 0, 7]);
+        assert_eq!(packets[0].len(), 2 + 490 * 3);
+    }
+
+    #[test]
+    fn dnrgb_layout() {
+        let packets = LedProtocol::Dnrgb.encode(&pixels(1000), 255, 0);
+        assert_eq!(packets.iter().map(Vec::len).collect::<Vec<_>>(), vec![4 + 489 * 3, 4 + 489 * 3, 4 + 22 * 3]);
+        assert_eq!(&packets[0][..7], &[4, 255, 0, 0, 0, 0, 7]);
+        // Start index 489 and 978, high byte first
+        assert_eq!(&packets[1][..7], &[4, 255, 0x01, 0xE9, 0xE9, 0x01, 7]);
+        assert_eq!(&packets[2][..4], &[4, 255, 0x03, 0xD2]);
+    }
+
+    #[test]
+    fn ddp_layout() {
+        let packets = LedProtocol::Ddp.encode(&pixels(500), 0, 0x13);
+        assert_eq!(packets.len(), 2);
+        // Version 1, sequence, RGB 8 bit, display, offset and length high byte first
+        assert_eq!(&packets[0][..10], &[0x40, 0x03, 0x0B, 0x01, 0, 0, 0, 0, 0x05, 0xA0]);
+        assert_eq!(&packets[0][10..13], &[0, 0, 7]);
+        assert_eq!(packets[0].len(), 10 + 1440);
+        // Only the last packet pushes the frame
+        assert_eq!(&packets[1][..10], &[0x41, 0x03, 0x0B, 0x01, 0, 0, 0x05, 0xA0, 0, 60]);
+        assert_eq!(&packets[1][10..13], &[224, 1, 7]);
+        assert_eq!(packets[1].len(), 10 + 60);
+    }
+
+    #[test]
+    fn parses_names() {
+        assert_eq!("DDP".parse::<LedProtocol>(), Ok(LedProtocol::Ddp));
+        assert_eq!("warls".parse::<LedProtocol>(), Ok(LedProtocol::Warls));
+        assert!("e131".parse::<LedProtocol>().is_err());
+        assert_eq!("Spectrum".parse::<Pattern>(), Ok(Pattern::Spectrum));
+        assert!("strobe".parse::<Pattern>().is_err());
+    }
+
+    #[test]
+    fn detects_beats() {
+        let frame = |flux: f32| FeatureFrame { flux, ..Default::default() };
+        let mut detector = BeatDetector::default();
+        assert!(!detector.update(&frame(10.)));
+        assert!(!detector.update(&frame(10.)));
+        assert!(detector.update(&frame(30.)));
+        assert!(detector.last_beat().is_some());
+        // Too soon after the last beat
+        assert!(!detector.update(&frame(100.)));
+
+        let mut detector = BeatDetector::default();
+        assert!(!detector.update(&frame(0.01)));
+        assert!(!detector.update(&frame(0.9)));
+    }
+
+    #[test]
+    fn renders_patterns() {
+        let frame = FeatureFrame { low_range_rms: 1., mid_range_rms: 0.5, spectrum_bands: vec![1., 0.], ..Default::default() };
+        assert_eq!(render(&strip(Pattern::Vu, 6), Some(&frame), None), vec![[0, 255, 0], [255, 255, 0], [0, 255, 0], [0, 0, 0], [0, 0, 0], [0, 0, 0]]);
+        assert_eq!(render(&strip(Pattern::Spectrum, 4), Some(&frame), None)[..2], [[255, 0, 0], [204, 255, 0]]);
+        assert_eq!(render(&strip(Pattern::Spectrum, 4), Some(&frame), None)[2..], [[0, 0, 0], [0, 0, 0]]);
+        assert_eq!(render(&strip(Pattern::Vu, 3), None, None), vec![[0, 0, 0]; 3]);
+
+        let beat = render(&strip(Pattern::Beat, 2), None, Some(Instant::now()));
+        assert!(beat.iter().all(|[r, g, b]| *r > 200 && r == g && g == b));
+        assert_eq!(render(&strip(Pattern::Beat, 2), None, Some(Instant::now() - Duration::from_secs(5))), vec![[0, 0, 0]; 2]);
+    }
+
+    #[test]
+    fn applies_brightness_and_reverse() {
+        let frame = FeatureFrame { low_range_rms: 1., ..Default::default() };
+        let mut strip = strip(Pattern::Vu, 3);
+        strip.brightness = 0.5;
+        strip.reverse = true;
+        assert_eq!(render(&strip, Some(&frame), None), vec![[0, 0, 0], [0, 0, 0], [0, 128, 0]]);
+    }
+
+    #[test]
+    fn parses_strips() {
+        let strip = LedStrip::from_json(&serde_json::json!({"target": "127.0.0.1", "protocol": "ddp", "leds": 150, "pattern": "beat", "rate": 30, "brightness": 0.5, "color": [255, 0, 0], "reverse": true, "timeout": 10})).unwrap();
+        assert_eq!(strip, LedStrip {
+            target: "127.0.0.1:4048".parse().unwrap(),
+            protocol: LedProtocol::Ddp,
+            leds: 150,
+            pattern: Pattern::Beat,
+            rate: 30.,
+            brightness: 0.5,
+            color: [255, 0, 0],
+            reverse: true,
+            timeout: 10,
+        });
+
+        let strip = LedStrip::from_json(&serde_json::json!({"target": "127.0.0.1:5000", "leds": 10})).unwrap();
+        assert_eq!((strip.target, strip.protocol, strip.pattern, strip.rate, strip.timeout), ("127.0.0.1:5000".parse().unwrap(), LedProtocol::Dnrgb, Pattern::Vu, DEFAULT_REFRESH_RATE, DEFAULT_WLED_TIMEOUT));
+
+        let strips = parse_strips(&serde_json::json!({"strips": [{"target": "127.0.0.1", "leds": 1}, {"target": "127.0.0.1", "leds": 0}]}));
+        assert_eq!(strips, Err("Strip 2: DNRGB supports 1 to 65536 LEDs".to_owned()));
+        assert!(parse_strips(&serde_json::json!({})).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_strips() {
+        for invalid in [
+            serde_json::json!({"leds": 10}),
+            serde_json::json!({"target": "127.0.0.1"}),
+            serde_json::json!({"target": "127.0.0.1", "protocol": "warls", "leds": 256}),
+            serde_json::json!({"target": "127.0.0.1", "protocol": "ddp", "leds": 65537}),
+            serde_json::json!({"target": "127.0.0.1", "leds": 10, "rate": 0}),
+            serde_json::json!({"target": "127.0.0.1", "leds": 10, "rate": 0.001}),
+            serde_json::json!({"target": "127.0.0.1", "leds": 10, "brightness": 1.5}),
+            serde_json::json!({"target": "127.0.0.1", "leds": 10, "color": [255, 0]}),
+            serde_json::json!({"target": "127.0.0.1", "leds": 10, "color": [256, 0, 0]}),
+            serde_json::json!({"target": "127.0.0.1", "leds": 10, "timeout": 300}),
+            serde_json::json!({"target": "127.0.0.1", "leds": 10, "pattern": 1}),
+        ] {
+            assert!(LedStrip::from_json(&invalid).is_err(), "{}", invalid);
+        }
+    }
+}
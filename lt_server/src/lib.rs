@@ -1,4 +1,22 @@
 pub mod analyzer;
+pub mod artnet;
+pub mod control;
+pub mod destination;
+pub mod discovery;
+pub mod dmx;
+pub mod extractors;
+pub mod heartbeat;
+pub mod layout;
+pub mod led;
+pub mod mapping;
 pub mod prompts;
+pub mod sacn;
 pub mod device_monitor;
-pub mod server;
\ No newline at end of file
+pub mod network;
+pub mod oscquery;
+pub mod output_rate;
+pub mod server;
+pub mod stream;
+pub mod subscription;
+pub mod timetag;
+pub mod websocket;
\ No newline at end of file
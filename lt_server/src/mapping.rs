@@ -0,0 +1,268 @@
+use std::{fs, sync::Arc};
+use rosc::{encoder, OscMessage, OscPacket, OscType};
+use serde_json::Value;
+
+use lt_utilities::atomic_float::{FeatureDescriptor, FeatureKind};
+use lt_utilities::audio_features::FeatureFrame;
+use lt_utilities::namespace::RESERVED_CHARS;
+
+use crate::destination::Destination;
+
+/// Sends a feature to another program on an address of its choosing,
+/// such as the opacity of a Resolume layer
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mapping {
+    pub feature: String,
+    /// Element of an array feature
+    pub index: Option<usize>,
+    pub address: String,
+    /// `host:port` the value is sent to
+    pub target: String,
+    /// Feature values mapped to the start and end of the output range, the expected range of the feature by default
+    pub input: (f32, f32),
+    pub output: (f32, f32),
+    /// Keeps values within the output range
+    pub clamp: bool,
+}
+
+impl Mapping {
+    /// Mapping of a feature to `address` on `target`, passing the expected range of the feature through unchanged
+    pub fn new(descriptor: &FeatureDescriptor, address: &str, target: &str) -> Self {
+        Self {
+            feature: descriptor.name.to_string(),
+            index: None,
+            address: address.to_owned(),
+            target: target.to_owned(),
+            input: (descriptor.min, descriptor.max),
+            output: (descriptor.min, descriptor.max),
+            clamp: true,
+        }
+    }
+
+    /// Parses a mapping such as
+    /// `{"feature": "LowRangeRMS", "address": "/composition/layers/1/video/opacity", "target": "127.0.0.1:7000", "output": [0.2, 1.0]}`.
+    /// `index`, `input` and `clamp` are optional, feature names may also be written as `low_range_rms`
+    pub fn from_json(value: &Value, features: &[FeatureDescriptor]) -> Result<Self, String> {
+        let string = |field: &str| value.get(field).and_then(Value::as_str).ok_or_else(|| format!("Mapping is missing {}", field));
+        let range = |field: &str| -> Result<Option<(f32, f32)>, String> {
+            match value.get(field) {
+                None => Ok(None),
+                Some(range) => match range.as_array().and_then(|range| range.iter().map(Value::as_f64).collect::<Option<Vec<f64>>>()).as_deref() {
+                    Some([start, end]) => Ok(Some((*start as f32, *end as f32))),
+                    _ => Err(format!("{} must be a list of two numbers", field)),
+                },
+            }
+        };
+
+        let name = string("feature")?;
+        let descriptor = find_feature(features, name).ok_or_else(|| format!("Unknown feature {}", name))?;
+        let mut mapping = Mapping::new(descriptor, string("address")?, string("target")?);
+        mapping.index = match value.get("index") {
+            None => None,
+            Some(index) => Some(index.as_u64().ok_or_else(|| "Index must be a positive integer".to_owned())? as usize),
+        };
+        if let Some(input) = range("input")? {
+            mapping.input = input;
+        }
+        if let Some(output) = range("output")? {
+            mapping.output = output;
+        }
+        if let Some(clamp) = value.get("clamp") {
+            mapping.clamp = clamp.as_bool().ok_or_else(|| "Clamp must be true or false".to_owned())?;
+        }
+
+        match (&descriptor.kind, mapping.index) {
+            (FeatureKind::Scalar, Some(_)) => return Err(format!("{} has no elements", mapping.feature)),
+            (FeatureKind::Array(_), None) => return Err(format!("{} needs an index", mapping.feature)),
+            (FeatureKind::Array(count), Some(index)) if index >= *count => return Err(format!("{} has only {} elements", mapping.feature, count)),
+            _ => {}
+        }
+        mapping.validate()?;
+        Ok(mapping)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.address.starts_with('/') || self.address.ends_with('/') || self.address.contains(RESERVED_CHARS) {
+            return Err(format!("Invalid address {}", self.address));
+        }
+        if self.input.0 == self.input.1 || ![self.input.0, self.input.1, self.output.0, self.output.1].iter().all(|value| value.is_finite()) {
+            return Err(format!("Invalid range for {}", self.address));
+        }
+        Ok(())
+    }
+
+    /// Maps a feature value from the input to the output range, ranges may be reversed to invert
+    pub fn scale(&self, value: f32) -> f32 {
+        let mut position = (value - self.input.0) / (self.input.1 - self.input.0);
+        if self.clamp {
+            position = position.clamp(0., 1.);
+        }
+        self.output.0 + position * (self.output.1 - self.output.0)
+    }
+
+    /// Scaled value of the mapped feature, `None` if the frame doesn't have it
+    pub fn value(&self, frame: &FeatureFrame) -> Option<f32> {
+        let value = match self.index {
+            Some(index) => *frame.get_array(&self.feature)?.get(index)?,
+            None => frame.get(&self.feature)?,
+        };
+        Some(self.scale(value))
+    }
+
+    pub fn message(&self, frame: &FeatureFrame) -> Option<OscMessage> {
+        Some(OscMessage {
+            addr: self.address.clone(),
+            args: vec![OscType::Float(self.value(frame)?)],
+        })
+    }
+}
+
+/// Feature by its name or by its name in snake case, as in `low_range_rms`
+pub(crate) fn find_feature<'a>(features: &'a [FeatureDescriptor], name: &str) -> Option<&'a FeatureDescriptor> {
+    let normalize = |name: &str| name.replace('_', "").to_ascii_lowercase();
+    features.iter().find(|feature| feature.name == name)
+        .or_else(|| features.iter().find(|feature| normalize(&feature.name) == normalize(name)))
+}
+
+/// Parses a mapping file: `{"mappings": [...]}`, see `Mapping::from_json`
+pub fn parse_mappings(text: &str, features: &[FeatureDescriptor]) -> Result<Vec<Mapping>, String> {
+    let config = serde_json::from_str::<Value>(text).map_err(|e| e.to_string())?;
+    let mappings = config.get("mappings").and_then(Value::as_array).ok_or_else(|| "Expected a list of mappings".to_owned())?;
+    mappings.iter().enumerate().map(|(index, mapping)| {
+        Mapping::from_json(mapping, features).map_err(|e| format!("Mapping {}: {}", index + 1, e))
+    }).collect()
+}
+
+pub fn load_mappings(path: &str, features: &[FeatureDescriptor]) -> Result<Vec<Mapping>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    parse_mappings(&text, features)
+}
+
+/// Mappings sharing a target, sent as one message each for receivers that don't understand bundles
+pub struct MappedTarget {
+    pub destination: Arc<Destination>,
+    pub mappings: Vec<Mapping>,
+}
+
+impl MappedTarget {
+    pub fn send_frame(&self, frame: &FeatureFrame) {
+        self.mappings.iter().filter_map(|mapping| mapping.message(frame)).for_each(|message| {
+            if let Ok(buf) = encoder::encode(&OscPacket::Message(message)) {
+                self.destination.send_features(&buf);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use lt_utilities::audio_features::{FEATURES, FEATURE_FLUX};
+
+    use super::*;
+
+    fn mapping(input: (f32, f32), output: (f32, f32), clamp: bool) -> Mapping {
+        Mapping { input, output, clamp, ..Mapping::new(&FEATURE_FLUX, "/layer/opacity", "127.0.0.1:7000") }
+    }
+
+    #[test]
+    fn scales_between_ranges() {
+        let mapping = mapping((0., 10.), (0.2, 1.), true);
+        assert_eq!(mapping.scale(0.), 0.2);
+        assert_eq!(mapping.scale(5.), 0.6);
+        assert_eq!(mapping.scale(10.), 1.);
+    }
+
+    #[test]
+    fn clamps_to_the_output_range() {
+        let clamped = mapping((0., 10.), (0., 1.), true);
+        assert_eq!(clamped.scale(-5.), 0.);
+        assert_eq!(clamped.scale(20.), 1.);
+        let unclamped = mapping((0., 10.), (0., 1.), false);
+        assert_eq!(unclamped.scale(-5.), -0.5);
+        assert_eq!(unclamped.scale(20.), 2.);
+    }
+
+    #[test]
+    fn reversed_ranges_invert() {
+        let output_reversed = mapping((0., 10.), (1., 0.), true);
+        assert_eq!(output_reversed.scale(0.), 1.);
+        assert_eq!(output_reversed.scale(2.5), 0.75);
+        assert_eq!(output_reversed.scale(20.), 0.);
+        let input_reversed = mapping((10., 0.), (0., 1.), true);
+        assert_eq!(input_reversed.scale(10.), 0.);
+        assert_eq!(input_reversed.scale(-1.), 1.);
+    }
+
+    #[test]
+    fn reads_mapped_values_from_frames() {
+        let frame = FeatureFrame { flux: 5., spectrum_bands: vec![0., 0.25], ..Default::default() };
+        let message = mapping((0., 10.), (0., 100.), true).message(&frame).unwrap();
+        assert_eq!(message.addr, "/layer/opacity");
+        assert_eq!(message.args, vec![OscType::Float(50.)]);
+
+        let band = Mapping::from_json(&json!({"feature": "spectrum_bands", "index": 1, "address": "/a", "target": "127.0.0.1:7000"}), FEATURES).unwrap();
+        assert_eq!(band.value(&frame), Some(0.25));
+        let missing = Mapping { index: Some(5), ..band };
+        assert_eq!(missing.value(&frame), None);
+    }
+
+    #[test]
+    fn parses_mappings() {
+        let parsed = Mapping::from_json(&json!({
+            "feature": "low_range_rms",
+            "address": "/composition/layers/1/video/opacity",
+            "target": "127.0.0.1:7000",
+            "input": [0, 0.5],
+            "output": [0.2, 1.0],
+            "clamp": false,
+        }), FEATURES).unwrap();
+        assert_eq!(parsed.feature, "LowRangeRMS");
+        assert_eq!(parsed.index, None);
+        assert_eq!(parsed.input, (0., 0.5));
+        assert_eq!(parsed.output, (0.2, 1.));
+        assert!(!parsed.clamp);
+
+        let defaults = Mapping::from_json(&json!({"feature": "Flux", "address": "/flux", "target": "127.0.0.1:7000"}), FEATURES).unwrap();
+        assert_eq!(defaults, Mapping::new(&FEATURE_FLUX, "/flux", "127.0.0.1:7000"));
+    }
+
+    #[test]
+    fn rejects_invalid_mappings() {
+        let invalid = [
+            json!({"address": "/a", "target": "127.0.0.1:7000"}),
+            json!({"feature": "Loudness", "address": "/a", "target": "127.0.0.1:7000"}),
+            json!({"feature": "Flux", "target": "127.0.0.1:7000"}),
+            json!({"feature": "Flux", "address": "a", "target": "127.0.0.1:7000"}),
+            json!({"feature": "Flux", "address": "/a"}),
+            json!({"feature": "Flux", "address": "/a", "target": "127.0.0.1:7000", "index": 0}),
+            json!({"feature": "SpectrumBands", "address": "/a", "target": "127.0.0.1:7000"}),
+            json!({"feature": "SpectrumBands", "address": "/a", "target": "127.0.0.1:7000", "index": 64}),
+            json!({"feature": "SpectrumBands", "address": "/a", "target": "127.0.0.1:7000", "index": -1}),
+            json!({"feature": "Flux", "address": "/a", "target": "127.0.0.1:7000", "input": [1, 1]}),
+            json!({"feature": "Flux", "address": "/a", "target": "127.0.0.1:7000", "output": [0, 1, 2]}),
+            json!({"feature": "Flux", "address": "/a", "target": "127.0.0.1:7000", "output": ["low", "high"]}),
+            json!({"feature": "Flux", "address": "/a", "target": "127.0.0.1:7000", "output": [0, "x", 1]}),
+            json!({"feature": "Flux", "address": "/a", "target": "127.0.0.1:7000", "input": [0, null, 1]}),
+            json!({"feature": "Flux", "address": "/layer opacity", "target": "127.0.0.1:7000"}),
+            json!({"feature": "Flux", "address": "/layers/*/opacity", "target": "127.0.0.1:7000"}),
+            json!({"feature": "Flux", "address": "/layers/{1,2}", "target": "127.0.0.1:7000"}),
+            json!({"feature": "Flux", "address": "/layers/[1]", "target": "127.0.0.1:7000"}),
+            json!({"feature": "Flux", "address": "/layer#1,a?", "target": "127.0.0.1:7000"}),
+            json!({"feature": "Flux", "address": "/layer/", "target": "127.0.0.1:7000"}),
+            json!({"feature": "Flux", "address": "/a", "target": "127.0.0.1:7000", "clamp": "yes"}),
+        ];
+        for value in invalid {
+            assert!(Mapping::from_json(&value, FEATURES).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn numbers_errors_by_mapping() {
+        let text = r#"{"mappings": [{"feature": "Flux", "address": "/a", "target": "127.0.0.1:7000"}, {"feature": "Nope", "address": "/b", "target": "127.0.0.1:7000"}]}"#;
+        assert_eq!(parse_mappings(text, FEATURES), Err("Mapping 2: Unknown feature Nope".to_owned()));
+        assert!(parse_mappings(r#"{"mapping": []}"#, FEATURES).is_err());
+        assert!(parse_mappings("not json", FEATURES).is_err());
+    }
+}
@@ -0,0 +1,267 @@
+use std::{io, net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr}, sync::Arc};
+use socket2::{Domain, Protocol, Socket, Type};
+
+pub const DEFAULT_MULTICAST_TTL: u32 = 1;
+
+/// Multicast group settings shared by the server and client
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MulticastConfig {
+    /// IPv4 group, or IPv6 group such as ff02::/16 (link-local) or ff05::/16 (site-local)
+    pub group: IpAddr,
+    /// Number of routers packets may cross, 1 keeps them on the local network
+    pub ttl: u32,
+    /// Local address of the IPv4 interface to send or join on, unspecified lets the OS choose
+    pub interface: Ipv4Addr,
+    /// Index of the IPv6 interface to send or join on, 0 lets the OS choose
+    pub interface_index: u32,
+    /// Whether packets are also delivered to receivers on the sending machine
+    pub loopback: bool,
+}
+
+impl MulticastConfig {
+    pub fn new(group: IpAddr) -> Self {
+        Self {
+            group,
+            ttl: DEFAULT_MULTICAST_TTL,
+            interface: Ipv4Addr::UNSPECIFIED,
+            interface_index: 0,
+            loopback: true,
+        }
+    }
+
+    pub fn validate(&self) -> io::Result<()> {
+        if !self.group.is_multicast() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a multicast address", self.group)));
+        }
+        Ok(())
+    }
+
+    /// Applies the sending options to a socket of the group's address family
+    pub fn apply_to_sender(&self, socket: &Socket) -> io::Result<()> {
+        match self.group {
+            IpAddr::V4(_) => {
+                socket.set_multicast_ttl_v4(self.ttl)?;
+                socket.set_multicast_if_v4(&self.interface)?;
+                socket.set_multicast_loop_v4(self.loopback)
+            }
+            IpAddr::V6(_) => {
+                socket.set_multicast_hops_v6(self.ttl)?;
+                socket.set_multicast_if_v6(self.interface_index)?;
+                socket.set_multicast_loop_v6(self.loopback)
+            }
+        }
+    }
+
+    /// Joins the group on a receiving socket of the group's address family
+    pub fn join(&self, socket: &Socket) -> io::Result<()> {
+        match self.group {
+            IpAddr::V4(group) => {
+                socket.join_multicast_v4(&group, &self.interface)?;
+                socket.set_multicast_loop_v4(self.loopback)
+            }
+            IpAddr::V6(group) => {
+                socket.join_multicast_v6(&group, self.interface_index)?;
+                socket.set_multicast_loop_v6(self.loopback)
+            }
+        }
+    }
+}
+
+/// Address of a local network interface
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetworkInterface {
+    pub name: String,
+    pub index: Option<u32>,
+    pub addr: IpAddr,
+    pub netmask: IpAddr,
+    /// Directed broadcast address of the subnet, IPv4 only
+    pub broadcast: Option<Ipv4Addr>,
+    pub is_loopback: bool,
+}
+
+impl std::fmt::Display for NetworkInterface {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}/{}", self.name, self.addr, self.netmask)?;
+        if let Some(broadcast) = self.broadcast {
+            write!(f, " broadcast {}", broadcast)?;
+        }
+        Ok(())
+    }
+}
+
+/// Every address of every local network interface
+pub fn interfaces() -> io::Result<Vec<NetworkInterface>> {
+    Ok(if_addrs::get_if_addrs()?.into_iter().map(|interface| {
+        let is_loopback = interface.is_loopback();
+        let (addr, netmask, broadcast) = match interface.addr {
+            if_addrs::IfAddr::V4(v4) => (IpAddr::V4(v4.ip), IpAddr::V4(v4.netmask), v4.broadcast),
+            if_addrs::IfAddr::V6(v6) => (IpAddr::V6(v6.ip), IpAddr::V6(v6.netmask), None),
+        };
+        NetworkInterface { name: interface.name, index: interface.index, addr, netmask, broadcast, is_loopback }
+    }).collect())
+}
+
+/// Finds an interface by name, local address or directed broadcast address.
+/// Names prefer the IPv4 address of the interface
+pub fn find_interface(selector: &str) -> io::Result<NetworkInterface> {
+    let mut interfaces = interfaces()?;
+    interfaces.sort_by_key(|interface| interface.addr.is_ipv6());
+
+    let ip = selector.parse::<IpAddr>().ok();
+    interfaces.into_iter().find(|interface| {
+        interface.name == selector || ip.is_some_and(|ip| interface.addr == ip || interface.broadcast.map(IpAddr::V4) == Some(ip))
+    }).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("No interface matches {}", selector)))
+}
+
+/// Restricts a socket to an interface so packets can't leave through another NIC
+pub fn bind_to_interface(socket: &Socket, interface: &NetworkInterface) -> io::Result<()> {
+    #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+    {
+        socket.bind_device(Some(interface.name.as_bytes()))
+    }
+    #[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+    {
+        let _ = (socket, interface);
+        Err(io::Error::new(io::ErrorKind::Unsupported, "Binding to a device is not supported on this platform"))
+    }
+}
+
+pub fn domain_of(addr: &IpAddr) -> Domain {
+    match addr {
+        IpAddr::V4(_) => Domain::IPV4,
+        IpAddr::V6(_) => Domain::IPV6,
+    }
+}
+
+/// Unspecified address of the same family as `addr`
+pub fn unspecified_like(addr: &IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    }
+}
+
+/// Sending sockets, one per address family, created when first needed
+#[derive(Clone, Default)]
+pub struct SenderSockets {
+    v4: Option<Arc<Socket>>,
+    v6: Option<Arc<Socket>>,
+    interface: Option<NetworkInterface>,
+}
+
+impl SenderSockets {
+    /// Sockets sending through `interface`, or wherever the OS routes packets if none is given
+    pub fn new(interface: Option<NetworkInterface>) -> Self {
+        Self { v4: None, v6: None, interface }
+    }
+
+    pub fn socket_for(&mut self, addr: &SocketAddr) -> io::Result<Arc<Socket>> {
+        let slot = if addr.is_ipv4() { &mut self.v4 } else { &mut self.v6 };
+        if let Some(socket) = slot {
+            return Ok(socket.clone());
+        }
+
+        let socket = Socket::new(domain_of(&addr.ip()), Type::DGRAM, Some(Protocol::UDP))?;
+        if addr.is_ipv4() {
+            socket.set_broadcast(true)?;
+        }
+        socket.set_reuse_address(true)?;
+
+        if let Some(interface) = &self.interface {
+            if let Err(e) = bind_to_interface(&socket, interface) {
+                println!("Could not bind to device {}, only the source address is pinned: {}", interface.name, e);
+            }
+            if interface.addr.is_ipv4() == addr.is_ipv4() {
+                socket.bind(&SocketAddr::new(interface.addr, 0).into())?;
+            }
+        }
+
+        let socket = Arc::new(socket);
+        *slot = Some(socket.clone());
+        Ok(socket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_multicast_groups() {
+        assert!(MulticastConfig::new(Ipv4Addr::new(239, 1, 2, 3).into()).validate().is_ok());
+        assert!(MulticastConfig::new("ff02::1".parse().unwrap()).validate().is_ok());
+        assert!(MulticastConfig::new(Ipv4Addr::new(10, 0, 0, 1).into()).validate().is_err());
+        assert!(MulticastConfig::new(Ipv6Addr::LOCALHOST.into()).validate().is_err());
+    }
+
+    #[test]
+    fn applies_sending_options() {
+        let config = MulticastConfig { ttl: 4, loopback: false, ..MulticastConfig::new(Ipv4Addr::new(239, 1, 2, 3).into()) };
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
+        config.apply_to_sender(&socket).unwrap();
+        assert_eq!(socket.multicast_ttl_v4().unwrap(), 4);
+        assert!(!socket.multicast_loop_v4().unwrap());
+        assert_eq!(socket.multicast_if_v4().unwrap(), Ipv4Addr::UNSPECIFIED);
+    }
+
+    #[test]
+    fn follows_the_address_family() {
+        let v6 = IpAddr::from(Ipv6Addr::LOCALHOST);
+        assert_eq!(domain_of(&v6), Domain::IPV6);
+        assert_eq!(domain_of(&Ipv4Addr::LOCALHOST.into()), Domain::IPV4);
+        assert_eq!(unspecified_like(&v6), IpAddr::from(Ipv6Addr::UNSPECIFIED));
+        assert_eq!(unspecified_like(&Ipv4Addr::LOCALHOST.into()), IpAddr::from(Ipv4Addr::UNSPECIFIED));
+    }
+
+    #[test]
+    fn keeps_a_socket_per_address_family() {
+        let mut sockets = SenderSockets::default();
+        let v4 = sockets.socket_for(&"127.0.0.1:3000".parse().unwrap()).unwrap();
+        let v6 = sockets.socket_for(&"[::1]:3000".parse().unwrap()).unwrap();
+        assert!(Arc::ptr_eq(&v4, &sockets.socket_for(&"10.0.0.2:4000".parse().unwrap()).unwrap()));
+        assert!(Arc::ptr_eq(&v6, &sockets.socket_for(&"[::1]:4000".parse().unwrap()).unwrap()));
+        assert!(!Arc::ptr_eq(&v4, &v6));
+        assert_eq!(v6.domain().unwrap(), Domain::IPV6);
+        assert!(v4.broadcast().unwrap());
+    }
+
+    fn loopback() -> NetworkInterface {
+        NetworkInterface {
+            name: "lo".to_owned(),
+            index: Some(1),
+            addr: Ipv4Addr::LOCALHOST.into(),
+            netmask: Ipv4Addr::new(255, 0, 0, 0).into(),
+            broadcast: Some(Ipv4Addr::new(127, 255, 255, 255)),
+            is_loopback: true,
+        }
+    }
+
+    #[test]
+    fn finds_interfaces_by_name_and_address() {
+        let interfaces = interfaces().unwrap();
+        let local = interfaces.iter().find(|interface| interface.addr == IpAddr::from(Ipv4Addr::LOCALHOST)).unwrap();
+        assert!(local.is_loopback);
+        assert_eq!(find_interface("127.0.0.1").unwrap(), *local);
+        assert!(find_interface(&local.name).unwrap().addr.is_ipv4());
+        if let Some(broadcast) = interfaces.iter().find_map(|interface| interface.broadcast) {
+            assert_eq!(find_interface(&broadcast.to_string()).unwrap().broadcast, Some(broadcast));
+        }
+        assert_eq!(find_interface("no-such-interface").unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn describes_interfaces() {
+        assert_eq!(loopback().to_string(), "lo 127.0.0.1/255.0.0.0 broadcast 127.255.255.255");
+    }
+
+    #[test]
+    fn pins_sockets_to_the_interface_address() {
+        let mut sockets = SenderSockets::new(Some(loopback()));
+        let v4 = sockets.socket_for(&"127.0.0.1:3000".parse().unwrap()).unwrap();
+        let local = v4.local_addr().unwrap().as_socket().unwrap();
+        assert_eq!(local.ip(), IpAddr::from(Ipv4Addr::LOCALHOST));
+        // The interface has no IPv6 address to pin to
+        let v6 = sockets.socket_for(&"[::1]:3000".parse().unwrap()).unwrap();
+        assert!(v6.local_addr().unwrap().as_socket().is_none_or(|local| local.ip().is_unspecified()));
+    }
+}
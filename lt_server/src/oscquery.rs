@@ -0,0 +1,322 @@
+use std::{io::{self, BufRead, BufReader, Write}, net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream}, sync::{Arc, Mutex}, thread, time::Duration};
+use colored::Colorize;
+use serde_json::{json, Map, Value};
+
+use lt_utilities::atomic_float::FeatureDescriptor;
+use lt_utilities::extractor::ExtractorRegistry;
+use lt_utilities::namespace::Namespace;
+
+use crate::analyzer::AnalyzerSettings;
+
+/// Value of the `ACCESS` attribute for addresses the server only sends
+const ACCESS_READ: u8 = 1;
+
+/// Serves the OSCQuery namespace of the sent features over HTTP
+pub struct OscQueryServer {
+    listener: TcpListener,
+    addresses: Namespace,
+    namespace: Mutex<Arc<Value>>,
+    /// Extractors whose features are described, see `set_settings`
+    described: Mutex<Option<DescribedRegistry>>,
+    host_info: Arc<Value>,
+}
+
+/// Registry kept up to date with the analyzer parameters
+struct DescribedRegistry {
+    registry: ExtractorRegistry,
+    settings: Arc<AnalyzerSettings>,
+    applied_generation: Option<u64>,
+}
+
+impl OscQueryServer {
+    /// Describes `features` sent on `osc_port` under the instance `name`, at their addresses in `addresses`
+    pub fn new(port: u16, name: &str, osc_port: u16, features: &[FeatureDescriptor], addresses: &Namespace) -> io::Result<Self> {
+        let listener = TcpListener::bind(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port))?;
+        Ok(Self {
+            listener,
+            addresses: addresses.clone(),
+            namespace: Mutex::new(Arc::new(namespace(features, addresses).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?)),
+            described: Mutex::new(None),
+            host_info: Arc::new(host_info(name, osc_port)),
+        })
+    }
+
+    /// Describes the features of `registry` with the parameters in `settings`,
+    /// rebuilding the namespace when a parameter such as the band count changes
+    pub fn set_settings(&mut self, settings: Arc<AnalyzerSettings>, registry: ExtractorRegistry) {
+        self.described = Mutex::new(Some(DescribedRegistry { registry, settings, applied_generation: None }));
+    }
+
+    /// Namespace of the current parameters
+    fn current_namespace(&self) -> Arc<Value> {
+        if let Some(described) = self.described.lock().ok().as_mut().and_then(|described| described.as_mut()) {
+            let generation = described.settings.generation();
+            if described.applied_generation != Some(generation) {
+                let parameters = described.settings.parameters();
+                described.registry.iter_mut().for_each(|extractor| {
+                    parameters.iter().for_each(|(name, value)| {
+                        extractor.set_parameter(name, *value);
+                    });
+                });
+                described.applied_generation = Some(generation);
+                match (namespace(&described.registry.features(), &self.addresses), self.namespace.lock()) {
+                    (Ok(namespace), Ok(mut current)) => *current = Arc::new(namespace),
+                    (Err(e), _) => println!("Failed to describe features: {}", e.bold().red()),
+                    _ => {}
+                }
+            }
+        }
+        self.namespace.lock().map(|current| current.clone()).unwrap_or_else(|current| current.into_inner().clone())
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn start(self) {
+        let server = Arc::new(self);
+        thread::spawn(move || {
+            for stream in server.listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let namespace = server.current_namespace();
+                        let host_info = server.host_info.clone();
+                        thread::spawn(move || {
+                            if let Err(e) = handle_connection(stream, &namespace, &host_info) {
+                                println!("OSCQuery request failed: {}", e.to_string().bold().red());
+                            }
+                        });
+                    }
+                    Err(e) => println!("OSCQuery connection failed: {}", e.to_string().bold().red()),
+                }
+            }
+        });
+    }
+}
+
+/// OSCQuery node tree containing every feature address.
+/// Fails if an address is used twice or contains another, see `Namespace::validate`
+pub fn namespace(features: &[FeatureDescriptor], addresses: &Namespace) -> Result<Value, String> {
+    let mut root = container_node("/");
+    for feature in features {
+        let addr = addresses.address(&feature.name);
+        let mut node = &mut root;
+        let mut path = String::new();
+        let segments = addr.split('/').filter(|segment| !segment.is_empty()).collect::<Vec<&str>>();
+        for (index, segment) in segments.iter().enumerate() {
+            path.push('/');
+            path.push_str(segment);
+            let last = index + 1 == segments.len();
+            let contents = node.get_mut("CONTENTS").and_then(Value::as_object_mut)
+                .ok_or_else(|| format!("Address {} of {} is inside the address of another feature", addr, feature.name))?;
+            match contents.get(*segment) {
+                Some(existing) if last && existing.get("CONTENTS").is_some() => return Err(format!("Address {} of {} contains the address of another feature", addr, feature.name)),
+                Some(_) if last => return Err(format!("Address {} of {} is used by another feature", addr, feature.name)),
+                _ => {}
+            }
+            node = contents.entry(segment.to_string()).or_insert_with(|| {
+                if last {
+                    feature_node(&path, feature)
+                } else {
+                    container_node(&path)
+                }
+            });
+        }
+    }
+    Ok(root)
+}
+
+fn container_node(path: &str) -> Value {
+    json!({
+        "FULL_PATH": path,
+        "ACCESS": 0,
+        "CONTENTS": Map::new(),
+    })
+}
+
+fn feature_node(path: &str, feature: &FeatureDescriptor) -> Value {
+    let len = feature.len();
+    let mut node = json!({
+        "FULL_PATH": path,
+        "TYPE": "f".repeat(len),
+        "ACCESS": ACCESS_READ,
+        "RANGE": vec![json!({ "MIN": feature.min, "MAX": feature.max }); len],
+    });
+    if !feature.units.is_empty() {
+        node["UNIT"] = json!(vec![feature.units.as_ref(); len]);
+    }
+    if !feature.description.is_empty() {
+        node["DESCRIPTION"] = json!(feature.description);
+    }
+    node
+}
+
+fn host_info(name: &str, osc_port: u16) -> Value {
+    json!({
+        "NAME": name,
+        "OSC_PORT": osc_port,
+        "OSC_TRANSPORT": "UDP",
+        "EXTENSIONS": {
+            "ACCESS": true,
+            "VALUE": false,
+            "RANGE": true,
+            "DESCRIPTION": true,
+            "UNIT": true,
+            "TAGS": false,
+            "CLIPMODE": false,
+            "LISTEN": false,
+            "PATH_CHANGED": false,
+        },
+    })
+}
+
+/// Node at an OSC path such as `/lt/Flux`
+pub fn find_node<'a>(namespace: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('/').filter(|segment| !segment.is_empty()).try_fold(namespace, |node, segment| {
+        node.get("CONTENTS")?.get(segment)
+    })
+}
+
+fn handle_connection(mut stream: TcpStream, namespace: &Value, host_info: &Value) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Headers are not used
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or("/"));
+    if method != "GET" {
+        return write_response(&mut stream, "405 Method Not Allowed", None);
+    }
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    if query == "HOST_INFO" {
+        return write_response(&mut stream, "200 OK", Some(host_info));
+    }
+
+    match find_node(namespace, path) {
+        Some(node) if query.is_empty() => write_response(&mut stream, "200 OK", Some(node)),
+        Some(node) => match node.get(query) {
+            Some(value) => write_response(&mut stream, "200 OK", Some(&json!({ query: value }))),
+            None => write_response(&mut stream, "204 No Content", None),
+        },
+        None => write_response(&mut stream, "404 Not Found", None),
+    }
+}
+
+fn write_response(stream: &mut TcpStream, status: &str, body: Option<&Value>) -> io::Result<()> {
+    let body = body.map(|body| body.to_string()).unwrap_or_default();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body,
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lt_utilities::audio_features::FEATURES;
+
+    #[test]
+    fn rejects_clashing_addresses() {
+        let mut addresses = Namespace::default();
+        addresses.set_override("BroadRangeRMS", "/lt");
+        assert_eq!(namespace(FEATURES, &addresses), Err("Address /lt/LowRangeRMS of LowRangeRMS is inside the address of another feature".to_owned()));
+
+        let mut addresses = Namespace::default();
+        addresses.set_override("ZCR", "/lt/Flux/zcr");
+        let features = [FeatureDescriptor::scalar("Flux"), FeatureDescriptor::scalar("ZCR")];
+        assert_eq!(namespace(&features, &addresses), Err("Address /lt/Flux/zcr of ZCR is inside the address of another feature".to_owned()));
+
+        let mut addresses = Namespace::default();
+        addresses.set_override("ZCR", "/lt/Flux");
+        assert_eq!(namespace(&features, &addresses), Err("Address /lt/Flux of ZCR is used by another feature".to_owned()));
+
+        assert!(OscQueryServer::new(0, "test", 7000, &features, &addresses).is_err());
+    }
+
+    #[test]
+    fn builds_the_default_tree() {
+        let features = crate::extractors::default_registry().features();
+        let tree = namespace(&features, &Namespace::default()).unwrap();
+        assert_eq!(tree["FULL_PATH"], "/");
+        let lt = find_node(&tree, "/lt").unwrap();
+        assert_eq!((&lt["FULL_PATH"], &lt["ACCESS"]), (&json!("/lt"), &json!(0)));
+        assert_eq!(lt["CONTENTS"].as_object().unwrap().len(), features.len());
+
+        let flux = find_node(&tree, "/lt/Flux").unwrap();
+        assert_eq!(flux["FULL_PATH"], "/lt/Flux");
+        assert_eq!(flux["TYPE"], "f");
+        assert_eq!(flux["ACCESS"], ACCESS_READ);
+        assert_eq!(flux["RANGE"].as_array().unwrap().len(), 1);
+        assert!(flux.get("CONTENTS").is_none());
+
+        let bands = find_node(&tree, "/lt/SpectrumBands").unwrap();
+        assert_eq!(bands["TYPE"], "f".repeat(16));
+        assert_eq!(bands["RANGE"].as_array().unwrap().len(), 16);
+        assert!(find_node(&tree, "/lt/Unknown").is_none());
+    }
+
+    #[test]
+    fn builds_the_tree_of_overridden_addresses() {
+        let mut addresses = Namespace::new("/venue");
+        addresses.set_instance(Some("stageA"));
+        addresses.set_override("Flux", "/show/flux");
+        let tree = namespace(FEATURES, &addresses).unwrap();
+
+        assert_eq!(find_node(&tree, "/show/flux").unwrap()["FULL_PATH"], "/show/flux");
+        assert_eq!(find_node(&tree, "/show")
+            .unwrap()["CONTENTS"].as_object().unwrap().keys().collect::<Vec<&String>>(), vec!["flux"]);
+        assert_eq!(find_node(&tree, "/venue/stageA/ZCR").unwrap()["FULL_PATH"], "/venue/stageA/ZCR");
+        assert!(find_node(&tree, "/venue/stageA/Flux").is_none());
+        assert!(find_node(&tree, "/lt").is_none());
+    }
+
+    #[test]
+    fn rebuilds_when_parameters_change() {
+        let features = crate::extractors::default_registry().features();
+        let mut server = OscQueryServer::new(0, "test", 7000, &features, &Namespace::default()).unwrap();
+        let settings = Arc::new(AnalyzerSettings::default());
+        server.set_settings(settings.clone(), crate::extractors::default_registry());
+        let bands_type = |server: &OscQueryServer| find_node(&server.current_namespace(), "/lt/SpectrumBands").unwrap()["TYPE"].clone();
+
+        assert_eq!(bands_type(&server), "f".repeat(16));
+        settings.set_parameter(crate::extractors::PARAMETER_BANDS, 4.);
+        assert_eq!(bands_type(&server), "ffff");
+    }
+
+    #[test]
+    fn serves_nodes_over_http() {
+        let server = OscQueryServer::new(0, "test", 7000, FEATURES, &Namespace::default()).unwrap();
+        let port = server.local_addr().unwrap().port();
+        server.start();
+
+        let get = |target: &str| {
+            let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", target).unwrap();
+            let mut response = String::new();
+            io::Read::read_to_string(&mut stream, &mut response).unwrap();
+            let (head, body) = response.split_once("\r\n\r\n").unwrap();
+            (head.lines().next().unwrap().to_owned(), body.to_owned())
+        };
+
+        let (status, body) = get("/lt/Flux?TYPE");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap(), json!({"TYPE": "f"}));
+        let (status, body) = get("/?HOST_INFO");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap()["OSC_PORT"], 7000);
+        assert_eq!(get("/lt/Unknown").0, "HTTP/1.1 404 Not Found");
+        assert_eq!(get("/lt/Flux?VALUE").0, "HTTP/1.1 204 No Content");
+    }
+}
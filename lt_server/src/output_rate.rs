@@ -0,0 +1,175 @@
+use std::{fmt, str::FromStr, time::Duration};
+
+use lt_utilities::audio_features::FeatureFrame;
+
+use crate::dmx::MIN_REFRESH_RATE;
+
+/// How the frames analyzed between two sends are combined
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Aggregation {
+    /// The most recent frame
+    #[default]
+    Latest,
+    /// The largest value of every feature
+    Max,
+    /// The mean value of every feature
+    Mean,
+}
+
+impl FromStr for Aggregation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "latest" => Ok(Aggregation::Latest),
+            "max" => Ok(Aggregation::Max),
+            "mean" => Ok(Aggregation::Mean),
+            _ => Err(format!("Unknown aggregation {}, expected latest, max or mean", s)),
+        }
+    }
+}
+
+impl fmt::Display for Aggregation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Aggregation::Latest => f.write_str("latest"),
+            Aggregation::Max => f.write_str("max"),
+            Aggregation::Mean => f.write_str("mean"),
+        }
+    }
+}
+
+/// Fixed rate a destination is sent to, independent of the audio buffer size
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputRate {
+    pub hz: f32,
+    pub aggregation: Aggregation,
+}
+
+impl OutputRate {
+    pub fn new(hz: f32, aggregation: Aggregation) -> Self {
+        Self { hz, aggregation }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.hz.is_finite() || self.hz < MIN_REFRESH_RATE {
+            return Err(format!("Invalid output rate {} Hz, expected at least {} Hz", self.hz, MIN_REFRESH_RATE));
+        }
+        Ok(())
+    }
+
+    /// Time between two sends, that of the minimum rate if the rate is lower
+    pub fn period(&self) -> Duration {
+        Duration::try_from_secs_f32(1. / self.hz.max(MIN_REFRESH_RATE)).unwrap_or(Duration::from_secs(100))
+    }
+}
+
+/// Collects frames between two sends
+pub struct FrameAggregator {
+    aggregation: Aggregation,
+    pending: Vec<FeatureFrame>,
+    last: Option<FeatureFrame>,
+}
+
+impl FrameAggregator {
+    pub fn new(aggregation: Aggregation) -> Self {
+        Self { aggregation, pending: Vec::new(), last: None }
+    }
+
+    pub fn push(&mut self, frame: FeatureFrame) {
+        self.pending.push(frame);
+    }
+
+    /// Combines the frames pushed since the last call.
+    /// Repeats the previous result if none were pushed, `None` before the first frame
+    pub fn take(&mut self) -> Option<FeatureFrame> {
+        let pending = std::mem::take(&mut self.pending);
+        let Some(latest) = pending.last() else {
+            return self.last.clone();
+        };
+
+        let mut frame = latest.clone();
+        if self.aggregation != Aggregation::Latest && pending.len() > 1 {
+            let combine = |values: &mut dyn Iterator<Item = f32>| match self.aggregation {
+                Aggregation::Max => values.fold(f32::MIN, f32::max),
+                _ => values.sum::<f32>() / pending.len() as f32,
+            };
+            latest.values().into_iter().for_each(|(name, _)| {
+                let value = combine(&mut pending.iter().map(|frame| frame.get(name).unwrap_or(0.)));
+                frame.set(name, value);
+            });
+            latest.arrays().into_iter().for_each(|(name, values)| {
+                let values = (0..values.len()).map(|index| {
+                    combine(&mut pending.iter().map(|frame| frame.get_array(name).and_then(|values| values.get(index)).cloned().unwrap_or(0.)))
+                }).collect::<Vec<f32>>();
+                frame.set_array(name, values);
+            });
+        }
+
+        self.last = Some(frame.clone());
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(flux: f32, bands: Vec<f32>) -> FeatureFrame {
+        FeatureFrame { flux, spectrum_bands: bands, ..Default::default() }
+    }
+
+    #[test]
+    fn nothing_before_the_first_frame() {
+        assert_eq!(FrameAggregator::new(Aggregation::Latest).take(), None);
+    }
+
+    #[test]
+    fn latest_keeps_the_last_frame() {
+        let mut aggregator = FrameAggregator::new(Aggregation::Latest);
+        aggregator.push(frame(1., vec![1., 0.]));
+        aggregator.push(frame(3., vec![0., 1.]));
+        assert_eq!(aggregator.take(), Some(frame(3., vec![0., 1.])));
+    }
+
+    #[test]
+    fn max_and_mean_combine_every_value() {
+        let mut max = FrameAggregator::new(Aggregation::Max);
+        let mut mean = FrameAggregator::new(Aggregation::Mean);
+        for aggregator in [&mut max, &mut mean] {
+            aggregator.push(frame(1., vec![0.2, 0.8]));
+            aggregator.push(frame(3., vec![0.6, 0.4]));
+        }
+        assert_eq!(max.take(), Some(frame(3., vec![0.6, 0.8])));
+        let mean = mean.take().unwrap();
+        assert_eq!(mean.flux, 2.);
+        assert!((mean.spectrum_bands[0] - 0.4).abs() < 1e-6 && (mean.spectrum_bands[1] - 0.6).abs() < 1e-6);
+    }
+
+    #[test]
+    fn repeats_the_previous_result_without_new_frames() {
+        let mut aggregator = FrameAggregator::new(Aggregation::Max);
+        aggregator.push(frame(1., vec![]));
+        aggregator.push(frame(2., vec![]));
+        let first = aggregator.take();
+        assert_eq!(aggregator.take(), first);
+        aggregator.push(frame(0.5, vec![]));
+        assert_eq!(aggregator.take().map(|frame| frame.flux), Some(0.5));
+    }
+
+    #[test]
+    fn validates_rates() {
+        assert!(OutputRate::new(44., Aggregation::Latest).validate().is_ok());
+        assert!(OutputRate::new(MIN_REFRESH_RATE, Aggregation::Latest).validate().is_ok());
+        assert!(OutputRate::new(0., Aggregation::Latest).validate().is_err());
+        assert!(OutputRate::new(1e-30, Aggregation::Latest).validate().is_err());
+        assert!(OutputRate::new(0.001, Aggregation::Latest).validate().is_err());
+        assert!(OutputRate::new(f32::NAN, Aggregation::Latest).validate().is_err());
+        assert_eq!(OutputRate::new(50., Aggregation::Latest).period(), Duration::from_millis(20));
+        // Unvalidated rates never panic
+        assert_eq!(OutputRate::new(1e-30, Aggregation::Latest).period(), OutputRate::new(MIN_REFRESH_RATE, Aggregation::Latest).period());
+        assert_eq!(OutputRate::new(f32::NAN, Aggregation::Latest).period(), OutputRate::new(MIN_REFRESH_RATE, Aggregation::Latest).period());
+        assert_eq!("MEAN".parse(), Ok(Aggregation::Mean));
+        assert!("median".parse::<Aggregation>().is_err());
+    }
+}
@@ -65,4 +65,10 @@ pub fn select_device_by_prompt(host: &cpal::Host) -> cpal::Device{
     println!();
     
     devices[device_index.parse::<usize>().unwrap()].clone()
-}
\ No newline at end of file
+}
+
+/// Finds an input or output device whose name matches exactly
+pub fn find_device_by_name(host: &cpal::Host, name: &str) -> Option<cpal::Device> {
+    let _print_gag = Gag::stderr();
+    host.devices().ok()?.find(|device| device.name().map(|device_name| device_name == name).unwrap_or(false))
+}
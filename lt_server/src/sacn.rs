@@ -0,0 +1,371 @@
+use std::{collections::{hash_map::RandomState, BTreeMap}, hash::{BuildHasher, Hasher}, io, net::{Ipv4Addr, SocketAddr, UdpSocket}, sync::{atomic::{AtomicBool, Ordering}, Arc}, thread::{self, JoinHandle}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+use crossbeam::channel::{Sender, TryRecvError};
+use serde_json::Value;
+use socket2::{Domain, Protocol, Socket, Type};
+
+use lt_utilities::atomic_float::FeatureDescriptor;
+use lt_utilities::audio_features::FeatureFrame;
+
+use crate::destination::SendErrorLog;
+use crate::dmx::{load_config, parse_rate, resolve_ipv4, DmxMap, DMX_CHANNELS};
+
+/// UDP port sACN receivers listen on
+pub const SACN_PORT: u16 = 5568;
+pub const DEFAULT_PRIORITY: u8 = 100;
+pub const MAX_PRIORITY: u8 = 200;
+/// Universes are numbered from 1 to 63999
+pub const MAX_UNIVERSE: u16 = 63999;
+/// Packets per second of DMX512
+pub const DEFAULT_REFRESH_RATE: f32 = 44.;
+/// Sent with the stream terminated option when a source stops, as the standard asks
+const TERMINATION_PACKETS: usize = 3;
+
+const ACN_PACKET_IDENTIFIER: &[u8; 12] = b"ASC-E1.17\0\0\0";
+const VECTOR_ROOT_E131_DATA: u32 = 0x0000_0004;
+const VECTOR_E131_DATA_PACKET: u32 = 0x0000_0002;
+const VECTOR_DMP_SET_PROPERTY: u8 = 0x02;
+const OPTION_STREAM_TERMINATED: u8 = 0x40;
+const SOURCE_NAME_LENGTH: usize = 64;
+/// Offsets of the root, framing and DMP layers, whose lengths run to the end of the packet
+const ROOT_LAYER: usize = 16;
+const FRAMING_LAYER: usize = 38;
+const DMP_LAYER: usize = 115;
+const DATA_START: usize = 126;
+
+/// Identifies a source to receivers
+pub type Cid = [u8; 16];
+
+/// Random version 4 UUID
+pub fn new_cid() -> Cid {
+    let mut cid = [0u8; 16];
+    for half in cid.chunks_mut(8) {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_nanos()).unwrap_or(0));
+        half.copy_from_slice(&hasher.finish().to_be_bytes());
+    }
+    cid[6] = (cid[6] & 0x0F) | 0x40;
+    cid[8] = (cid[8] & 0x3F) | 0x80;
+    cid
+}
+
+/// Multicast group of a universe, 239.255.<high byte>.<low byte>
+pub fn universe_group(universe: u16) -> Ipv4Addr {
+    let [high, low] = universe.to_be_bytes();
+    Ipv4Addr::new(239, 255, high, low)
+}
+
+/// Fields of an E1.31 data packet besides the universe values
+#[derive(Clone, Debug, PartialEq)]
+pub struct SacnSource {
+    pub cid: Cid,
+    /// Shown by consoles, at most 63 bytes are sent
+    pub name: String,
+    /// Receivers use the values of the source with the highest priority
+    pub priority: u8,
+}
+
+impl SacnSource {
+    pub fn new(name: &str) -> Self {
+        Self { cid: new_cid(), name: name.to_owned(), priority: DEFAULT_PRIORITY }
+    }
+
+    /// E1.31 data packet with the values of one universe
+    pub fn data_packet(&self, universe: u16, sequence: u8, data: &[u8], terminated: bool) -> Vec<u8> {
+        let slots = data.len().min(DMX_CHANNELS);
+        let mut packet = vec![0u8; DATA_START + slots];
+        let flags_and_length = |start: usize| (0x7000 | (DATA_START + slots - start) as u16).to_be_bytes();
+
+        packet[0..2].copy_from_slice(&(ROOT_LAYER as u16).to_be_bytes());
+        packet[4..16].copy_from_slice(ACN_PACKET_IDENTIFIER);
+        packet[16..18].copy_from_slice(&flags_and_length(ROOT_LAYER));
+        packet[18..22].copy_from_slice(&VECTOR_ROOT_E131_DATA.to_be_bytes());
+        packet[22..38].copy_from_slice(&self.cid);
+
+        packet[38..40].copy_from_slice(&flags_and_length(FRAMING_LAYER));
+        packet[40..44].copy_from_slice(&VECTOR_E131_DATA_PACKET.to_be_bytes());
+        // Null terminated, cut on a character boundary
+        let mut name_length = self.name.len().min(SOURCE_NAME_LENGTH - 1);
+        while !self.name.is_char_boundary(name_length) {
+            name_length -= 1;
+        }
+        packet[44..44 + name_length].copy_from_slice(&self.name.as_bytes()[..name_length]);
+        packet[108] = self.priority;
+        // Synchronization address 0, packets are not synchronized
+        packet[111] = sequence;
+        packet[112] = if terminated { OPTION_STREAM_TERMINATED } else { 0 };
+        packet[113..115].copy_from_slice(&universe.to_be_bytes());
+
+        packet[115..117].copy_from_slice(&flags_and_length(DMP_LAYER));
+        packet[117] = VECTOR_DMP_SET_PROPERTY;
+        // Relative addresses of single bytes
+        packet[118] = 0xA1;
+        packet[121..123].copy_from_slice(&1u16.to_be_bytes());
+        // The start code counts as a property
+        packet[123..125].copy_from_slice(&(slots as u16 + 1).to_be_bytes());
+        // Start code 0 is dimmer data
+        packet[DATA_START..].copy_from_slice(&data[..slots]);
+        packet
+    }
+}
+
+/// Universes, source and targets of an sACN output
+#[derive(Clone, Debug, PartialEq)]
+pub struct SacnConfig {
+    pub map: DmxMap,
+    pub source: SacnSource,
+    /// Data packets sent per second and universe
+    pub rate: f32,
+    /// Sends every universe to its multicast group
+    pub multicast: bool,
+    /// Receivers sent every universe over unicast
+    pub targets: Vec<SocketAddr>,
+    /// Local address of the interface to send multicast on
+    pub interface: Ipv4Addr,
+}
+
+impl SacnConfig {
+    /// Parses a DMX config, see `DmxMap::from_json`, with optional source settings such as
+    /// `{"source_name": "FOH audio", "priority": 120, "rate": 30, "multicast": true, "targets": ["10.0.0.60"], "interface": "10.0.0.2", "fixtures": [...]}`
+    pub fn from_json(config: &Value, features: &[FeatureDescriptor], default_name: &str) -> Result<Self, String> {
+        let map = DmxMap::from_json(config, features)?;
+        if let Some(universe) = map.universes().into_iter().find(|universe| *universe == 0 || *universe > MAX_UNIVERSE) {
+            return Err(format!("Universe {} is outside the sACN universes 1 to {}", universe, MAX_UNIVERSE));
+        }
+
+        let mut source = SacnSource::new(config.get("source_name").and_then(Value::as_str).unwrap_or(default_name));
+        if let Some(priority) = config.get("priority") {
+            source.priority = priority.as_u64().filter(|priority| *priority <= MAX_PRIORITY as u64)
+                .ok_or_else(|| format!("Priority must be 0 to {}", MAX_PRIORITY))? as u8;
+        }
+        let rate = parse_rate(config, DEFAULT_REFRESH_RATE)?;
+        let multicast = match config.get("multicast") {
+            None => true,
+            Some(multicast) => multicast.as_bool().ok_or_else(|| "Multicast must be true or false".to_owned())?,
+        };
+        let targets = match config.get("targets").and_then(Value::as_array) {
+            None => Vec::new(),
+            Some(targets) => targets.iter().map(|target| {
+                let target = target.as_str().ok_or_else(|| "Targets must be strings".to_owned())?;
+                resolve_ipv4(target, SACN_PORT).ok_or_else(|| format!("No IPv4 address found for {}", target))
+            }).collect::<Result<Vec<SocketAddr>, String>>()?,
+        };
+        let interface = match config.get("interface") {
+            None => Ipv4Addr::UNSPECIFIED,
+            Some(interface) => interface.as_str().and_then(|interface| interface.parse().ok())
+                .ok_or_else(|| "Interface must be an IPv4 address".to_owned())?,
+        };
+        if !multicast && targets.is_empty() {
+            return Err("Nothing to send to without multicast or targets".to_owned());
+        }
+
+        Ok(Self { map, source, rate, multicast, targets, interface })
+    }
+
+    pub fn load(path: &str, features: &[FeatureDescriptor], default_name: &str) -> Result<Self, String> {
+        Self::from_json(&load_config(path)?, features, default_name)
+    }
+
+    /// Multicast groups and unicast targets a universe is sent to
+    fn destinations(&self, universe: u16, port: u16) -> Vec<SocketAddr> {
+        let group = self.multicast.then(|| SocketAddr::new(universe_group(universe).into(), port));
+        group.into_iter().chain(self.targets.iter().cloned()).collect()
+    }
+}
+
+/// Streams features as DMX over sACN, terminating the streams when dropped
+pub struct SacnSender {
+    socket: Arc<UdpSocket>,
+    config: Arc<SacnConfig>,
+    /// Port the multicast groups are sent to
+    port: u16,
+    alive: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SacnSender {
+    pub fn new(config: SacnConfig) -> io::Result<Self> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_multicast_ttl_v4(16)?;
+        socket.set_multicast_if_v4(&config.interface)?;
+        socket.bind(&SocketAddr::new(config.interface.into(), 0).into())?;
+        Ok(Self { socket: Arc::new(socket.into()), config: Arc::new(config), port: SACN_PORT, alive: Arc::new(AtomicBool::new(true)), thread: None })
+    }
+
+    /// Sends the multicast groups to another port, for receivers that can't share the sACN port
+    pub fn set_port(&mut self, port: u16) {
+        self.port = port;
+    }
+
+    /// Sends the latest frame at the configured rate and returns the sender frames are read from,
+    /// see `LunaTechServer::add_frame_listener`
+    pub fn start(&mut self) -> Sender<FeatureFrame> {
+        let (tx, rx) = crossbeam::channel::unbounded::<FeatureFrame>();
+
+        let (socket, config, port, alive) = (self.socket.clone(), self.config.clone(), self.port, self.alive.clone());
+        self.thread = Some(thread::spawn(move || {
+            let period = Duration::from_secs_f32(1. / config.rate);
+            let mut sequences = BTreeMap::<u16, u8>::new();
+            let mut latest = None;
+            let mut next_send = Instant::now();
+            let mut errors = SendErrorLog::default();
+            let mut send = |latest: Option<&FeatureFrame>, terminated: bool| {
+                for (universe, data) in config.map.render(latest) {
+                    let sequence = sequences.entry(universe).or_insert(0);
+                    let packet = config.source.data_packet(universe, *sequence, &data, terminated);
+                    *sequence = sequence.wrapping_add(1);
+                    for destination in config.destinations(universe, port) {
+                        if let Err(e) = socket.send_to(&packet, destination) {
+                            errors.print("sACN", destination, &e);
+                        }
+                    }
+                }
+            };
+
+            'sending: while alive.load(Ordering::Relaxed) {
+                loop {
+                    match rx.try_recv() {
+                        Ok(frame) => latest = Some(frame),
+                        Err(TryRecvError::Empty) => break,
+                        // Every server sending frames is gone
+                        Err(TryRecvError::Disconnected) => break 'sending,
+                    }
+                }
+                send(latest.as_ref(), false);
+
+                next_send += period;
+                let now = Instant::now();
+                if next_send > now {
+                    thread::sleep(next_send - now);
+                } else {
+                    next_send = now;
+                }
+            }
+
+            // Lets receivers release the universes right away instead of after the data loss timeout
+            for _ in 0..TERMINATION_PACKETS {
+                send(latest.as_ref(), true);
+            }
+        }));
+
+        tx
+    }
+}
+
+impl Drop for SacnSender {
+    fn drop(&mut self) {
+        self.alive.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source() -> SacnSource {
+        SacnSource { cid: *b"0123456789abcdef", name: "FOH audio".to_owned(), priority: 120 }
+    }
+
+    fn config(config: Value) -> Result<SacnConfig, String> {
+        SacnConfig::from_json(&config, lt_utilities::audio_features::FEATURES, "lt_server")
+    }
+
+    #[test]
+    fn data_packet_layout() {
+        let packet = source().data_packet(0x1234, 9, &[255; DMX_CHANNELS], false);
+        assert_eq!(packet.len(), 638);
+        // Root layer
+        assert_eq!(&packet[0..4], &[0x00, 0x10, 0x00, 0x00]);
+        assert_eq!(&packet[4..16], b"ASC-E1.17\0\0\0");
+        assert_eq!(&packet[16..18], &[0x72, 0x6E]);
+        assert_eq!(&packet[18..22], &[0, 0, 0, 4]);
+        assert_eq!(&packet[22..38], b"0123456789abcdef");
+        // Framing layer
+        assert_eq!(&packet[38..40], &[0x72, 0x58]);
+        assert_eq!(&packet[40..44], &[0, 0, 0, 2]);
+        assert!(packet[44..108].starts_with(b"FOH audio\0"));
+        assert_eq!(packet[108], 120);
+        assert_eq!(&packet[109..111], &[0, 0]);
+        assert_eq!((packet[111], packet[112]), (9, 0));
+        assert_eq!(&packet[113..115], &[0x12, 0x34]);
+        // DMP layer
+        assert_eq!(&packet[115..117], &[0x72, 0x0B]);
+        assert_eq!((packet[117], packet[118]), (0x02, 0xA1));
+        assert_eq!(&packet[119..121], &[0, 0]);
+        assert_eq!(&packet[121..123], &[0, 1]);
+        assert_eq!(&packet[123..125], &[0x02, 0x01]);
+        assert_eq!(packet[125], 0);
+        assert!(packet[126..].iter().all(|value| *value == 255));
+    }
+
+    #[test]
+    fn data_packets_carry_at_most_a_universe() {
+        let short = source().data_packet(1, 0, &[1, 2, 3], true);
+        assert_eq!(short.len(), DATA_START + 3);
+        assert_eq!(&short[16..18], &[0x70, 0x71]);
+        assert_eq!(&short[123..125], &[0, 4]);
+        assert_eq!(short[112], 0x40);
+        assert_eq!(&short[126..], &[1, 2, 3]);
+
+        assert_eq!(source().data_packet(1, 0, &[0; 600], false).len(), DATA_START + DMX_CHANNELS);
+    }
+
+    #[test]
+    fn cuts_long_source_names() {
+        let name = format!("{}é", "a".repeat(62));
+        let packet = SacnSource { name, ..source() }.data_packet(1, 0, &[], false);
+        // The two byte character doesn't fit before the null terminator
+        assert_eq!(&packet[44..106], "a".repeat(62).as_bytes());
+        assert!(packet[106..108].iter().all(|byte| *byte == 0));
+
+        let packet = SacnSource { name: "b".repeat(80), ..source() }.data_packet(1, 0, &[], false);
+        assert_eq!(&packet[44..107], "b".repeat(63).as_bytes());
+        assert_eq!(packet[107], 0);
+    }
+
+    #[test]
+    fn universe_groups() {
+        assert_eq!(universe_group(1), Ipv4Addr::new(239, 255, 0, 1));
+        assert_eq!(universe_group(0x1234), Ipv4Addr::new(239, 255, 0x12, 0x34));
+        assert_eq!(universe_group(MAX_UNIVERSE), Ipv4Addr::new(239, 255, 249, 255));
+    }
+
+    #[test]
+    fn cids_are_version_4_uuids() {
+        let cid = new_cid();
+        assert_eq!(cid[6] >> 4, 4);
+        assert_eq!(cid[8] >> 6, 0b10);
+        assert_ne!(cid, new_cid());
+    }
+
+    #[test]
+    fn parses_configs() {
+        let parsed = config(serde_json::json!({"source_name": "FOH", "priority": 200, "rate": 30, "multicast": false, "targets": ["127.0.0.1"], "interface": "127.0.0.1", "channels": [{"universe": 1, "channel": 1, "value": 255}]})).unwrap();
+        assert_eq!((parsed.source.name.as_str(), parsed.source.priority, parsed.rate), ("FOH", 200, 30.));
+        assert!(!parsed.multicast);
+        assert_eq!(parsed.targets, vec!["127.0.0.1:5568".parse().unwrap()]);
+        assert_eq!(parsed.interface, Ipv4Addr::LOCALHOST);
+        assert_eq!(parsed.destinations(1, SACN_PORT), vec!["127.0.0.1:5568".parse().unwrap()]);
+
+        let defaults = config(serde_json::json!({"channels": [{"universe": 2, "channel": 1, "value": 255}]})).unwrap();
+        assert_eq!((defaults.source.name.as_str(), defaults.source.priority, defaults.rate), ("lt_server", DEFAULT_PRIORITY, DEFAULT_REFRESH_RATE));
+        assert_eq!(defaults.destinations(2, 6000), vec!["239.255.0.2:6000".parse().unwrap()]);
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        for invalid in [
+            serde_json::json!({"channels": [{"universe": 0, "channel": 1, "value": 255}]}),
+            serde_json::json!({"channels": [{"universe": 64000, "channel": 1, "value": 255}]}),
+            serde_json::json!({"priority": 201, "channels": [{"universe": 1, "channel": 1, "value": 255}]}),
+            serde_json::json!({"rate": 0, "channels": [{"universe": 1, "channel": 1, "value": 255}]}),
+            serde_json::json!({"multicast": "yes", "channels": [{"universe": 1, "channel": 1, "value": 255}]}),
+            serde_json::json!({"multicast": false, "channels": [{"universe": 1, "channel": 1, "value": 255}]}),
+            serde_json::json!({"interface": "eth0", "channels": [{"universe": 1, "channel": 1, "value": 255}]}),
+        ] {
+            assert!(config(invalid.clone()).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -1,45 +1,307 @@
-use std::{net::{ Ipv4Addr, SocketAddr}, sync::Arc, thread, time::{Duration, UNIX_EPOCH}};
+use std::{io, net::{ IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs}, process, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}, thread, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
 use colored::Colorize;
-use rosc::{encoder, OscBundle, OscError, OscMessage, OscPacket, OscTime, OscType};
-use socket2::{Domain, Protocol, SockAddr, Socket, Type};
-use crossbeam::channel::Receiver;
+use rosc::{encoder, OscPacket};
+use crossbeam::channel::{Receiver, Sender};
 
-use lt_utilities::audio_features::{Features, OSC_ADDR_BROADRANGERMS, OSC_ADDR_FLUX, OSC_ADDR_HIGHRANGERMS, OSC_ADDR_LOWRANGERMS, OSC_ADDR_MIDRANGERMS, OSC_ADDR_SPECTRALCENTROID, OSC_ADDR_ZCR};
+use lt_utilities::audio_features::FeatureFrame;
+use lt_utilities::namespace::Namespace;
+
+use crate::destination::{Destination, DestinationKind};
+use crate::heartbeat::{ServerInfo, ServerState, HEARTBEAT_INTERVAL};
+use crate::layout::{frame_packets, MessageLayout};
+use crate::mapping::{MappedTarget, Mapping};
+use crate::network::{MulticastConfig, NetworkInterface, SenderSockets};
+use crate::output_rate::{FrameAggregator, OutputRate};
+use crate::stream::{send_to_streams, StreamConnections};
+use crate::subscription::{Subscription, UdpSubscribers};
+use crate::timetag::{offset_time, to_timetag};
+
+/// First message of every feature bundle, its argument counts the bundles sent to a destination
+pub const OSC_ADDR_SEQUENCE: &str = "/lt/sequence";
 
 pub struct LunaTechServer {
-    socket: Arc<Socket>,
-    addrs: SockAddr,
-    rx: Option<Arc<Receiver<Features>>>,
+    sockets: SenderSockets,
+    port: u16,
+    broadcast_addr: Ipv4Addr,
+    interface: Option<NetworkInterface>,
+    multicast: Option<MulticastConfig>,
+    destinations: Vec<Arc<Destination>>,
+    rx: Option<Arc<Receiver<FeatureFrame>>>,
+    /// Receive every frame besides the destinations, such as the WebSocket server
+    frame_listeners: Vec<Sender<FeatureFrame>>,
+    /// TCP clients receiving every bundle and heartbeat
+    streams: Option<StreamConnections>,
+    /// Clients that subscribed over the control port
+    subscribers: Option<Arc<UdpSubscribers>>,
+    /// Programs sent single scaled features, see `add_mappings`
+    mapped_targets: Vec<Arc<MappedTarget>>,
+    namespace: Arc<Namespace>,
+    /// Layout of the packets sent to destinations, subscribers choose their own
+    layout: MessageLayout,
+    started_at: Instant,
+    /// Sent with every heartbeat, the uptime is filled in when sending
+    info: Arc<Mutex<ServerInfo>>,
+    /// Cleared when the server is dropped to stop its threads
+    alive: Arc<AtomicBool>,
+}
+
+/// Id made of the process id and start time, unique for every server process
+fn new_server_id() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.subsec_nanos()).unwrap_or(0);
+    format!("{:x}-{:08x}", process::id(), nanos)
 }
 
 impl LunaTechServer {
     pub fn new(port: u16) -> Self {
-        let socket: Socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).expect("Failed to create socket");
-        socket.set_broadcast(true).expect("Failed to set broadcast");
-        socket.set_reuse_address(true).expect("Failed to set reuse address");
+        let mut server = Self {
+            sockets: SenderSockets::default(),
+            port,
+            broadcast_addr: Ipv4Addr::BROADCAST,
+            interface: None,
+            multicast: None,
+            destinations: Vec::new(),
+            rx: None,
+            frame_listeners: Vec::new(),
+            streams: None,
+            subscribers: None,
+            mapped_targets: Vec::new(),
+            namespace: Arc::new(Namespace::default()),
+            layout: MessageLayout::default(),
+            started_at: Instant::now(),
+            info: Arc::new(Mutex::new(ServerInfo {
+                server_id: new_server_id(),
+                uptime: Duration::ZERO,
+                sample_rate: 0,
+                device_name: String::new(),
+                state: ServerState::Stopped,
+            })),
+            alive: Arc::new(AtomicBool::new(true)),
+        };
+        if let Err(e) = server.set_broadcast(true) {
+            println!("Failed to broadcast: {}", e.to_string().bold().red());
+        }
+        server
+    }
+
+    /// Enables or disables sending to the IPv4 broadcast address, other destinations are unaffected
+    pub fn set_broadcast(&mut self, enabled: bool) -> io::Result<()> {
+        self.destinations.retain(|destination| destination.kind != DestinationKind::Broadcast);
+        if enabled {
+            let addr = SocketAddr::new(self.broadcast_addr.into(), self.port);
+            let socket = self.sockets.socket_for(&addr)?;
+            self.destinations.insert(0, Destination::new(addr, DestinationKind::Broadcast, socket).into());
+        }
+        Ok(())
+    }
+
+    /// Broadcasts to a directed subnet broadcast such as 10.0.0.255 instead of 255.255.255.255
+    pub fn set_broadcast_addr(&mut self, addr: Ipv4Addr) -> io::Result<()> {
+        self.broadcast_addr = addr;
+        if self.destinations.iter().any(|destination| destination.kind == DestinationKind::Broadcast) {
+            self.set_broadcast(true)?;
+        }
+        Ok(())
+    }
+
+    /// Sends every packet through a single local interface and broadcasts on its subnet.
+    /// Existing destinations are moved to the interface, keeping their offsets, and their statistics are reset
+    pub fn set_interface(&mut self, interface: NetworkInterface) -> io::Result<()> {
+        if let Some(broadcast) = interface.broadcast {
+            self.broadcast_addr = broadcast;
+        }
+        self.sockets = SenderSockets::new(Some(interface.clone()));
+        self.interface = Some(interface);
+
+        let destinations = std::mem::take(&mut self.destinations);
+        for destination in &destinations {
+            match destination.kind {
+                DestinationKind::Broadcast => self.set_broadcast(true)?,
+                DestinationKind::Unicast => { self.add_destination(destination.addr, DestinationKind::Unicast)?; },
+                DestinationKind::Multicast => {
+                    if let Some(multicast) = self.multicast.clone() {
+                        self.set_multicast(&multicast)?;
+                    }
+                },
+            }
+        }
+        for destination in &self.destinations {
+            // Broadcast addresses change with the interface, there is only one of each kind
+            let same = |previous: &&Arc<Destination>| previous.kind == destination.kind && (destination.kind != DestinationKind::Unicast || previous.addr == destination.addr);
+            if let Some(previous) = destinations.iter().find(same) {
+                destination.set_offset_ms(previous.offset_ms());
+                destination.set_hold(previous.hold());
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds a unicast destination given as `host:port`, or `host` to use the server port.
+    /// IPv6 addresses are written as `[::1]:3000`
+    pub fn add_target(&mut self, target: &str) -> io::Result<Arc<Destination>> {
+        let addr = self.resolve_target(target)?;
+        self.add_destination(addr, DestinationKind::Unicast)
+    }
+
+    fn resolve_target(&self, target: &str) -> io::Result<SocketAddr> {
+        let mut addrs = match target.to_socket_addrs() {
+            Ok(addrs) => addrs.collect::<Vec<SocketAddr>>(),
+            Err(_) => (target.trim_start_matches('[').trim_end_matches(']'), self.port).to_socket_addrs()?.collect::<Vec<SocketAddr>>(),
+        };
+
+        // Prefer IPv4 for host names resolving to both families
+        addrs.sort_by_key(|addr| addr.is_ipv6());
+        addrs.into_iter().next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("No address found for {}", target))
+        })
+    }
+
+    /// Sends each mapping to its target as a scaled message, besides the feature bundles.
+    /// Targets are resolved like `add_target` and not moved by a later `set_interface`
+    pub fn add_mappings(&mut self, mappings: &[Mapping]) -> io::Result<Vec<Arc<Destination>>> {
+        let mut targets: Vec<MappedTarget> = Vec::new();
+        for mapping in mappings {
+            let addr = self.resolve_target(&mapping.target)?;
+            match targets.iter_mut().find(|target| target.destination.addr == addr) {
+                Some(target) => target.mappings.push(mapping.clone()),
+                None => {
+                    let destination = Arc::new(Destination::new(addr, DestinationKind::Unicast, self.sockets.socket_for(&addr)?));
+                    targets.push(MappedTarget { destination, mappings: vec![mapping.clone()] });
+                }
+            }
+        }
+        let destinations = targets.iter().map(|target| target.destination.clone()).collect();
+        self.mapped_targets.extend(targets.into_iter().map(Arc::new));
+        Ok(destinations)
+    }
+
+    /// Targets of the mappings, with the mappings sent to each
+    pub fn mapped_targets(&self) -> &[Arc<MappedTarget>] {
+        &self.mapped_targets
+    }
+
+    /// Sends to a multicast group, replacing any previously set group
+    pub fn set_multicast(&mut self, config: &MulticastConfig) -> io::Result<Arc<Destination>> {
+        config.validate()?;
+        let mut config = config.clone();
+        // Default to the pinned interface unless one is given explicitly
+        if let Some(interface) = &self.interface {
+            if let (IpAddr::V4(addr), true) = (interface.addr, config.interface.is_unspecified()) {
+                config.interface = addr;
+            }
+            if config.interface_index == 0 {
+                config.interface_index = interface.index.unwrap_or(0);
+            }
+        }
+
+        let addr = SocketAddr::new(config.group, self.port);
+        let socket = self.sockets.socket_for(&addr)?;
+        config.apply_to_sender(&socket)?;
+        self.multicast = Some(config);
+
+        self.destinations.retain(|destination| destination.kind != DestinationKind::Multicast);
+        self.add_destination(addr, DestinationKind::Multicast)
+    }
+
+    fn add_destination(&mut self, addr: SocketAddr, kind: DestinationKind) -> io::Result<Arc<Destination>> {
+        let destination = Arc::new(Destination::new(addr, kind, self.sockets.socket_for(&addr)?));
+        self.destinations.push(destination.clone());
+        Ok(destination)
+    }
+
+    pub fn destinations(&self) -> &[Arc<Destination>] {
+        &self.destinations
+    }
+
+    /// Sets the timetag offset of every current destination, see `Destination::set_offset_ms`
+    pub fn set_offset_ms(&self, offset_ms: i64) {
+        self.destinations.iter().for_each(|destination| destination.set_offset_ms(offset_ms));
+    }
+
+    /// Sets the output rate of every current destination, see `Destination::set_rate`
+    pub fn set_rate(&self, rate: Option<OutputRate>) {
+        self.destinations.iter().for_each(|destination| destination.set_rate(rate));
+    }
+
+    /// Addresses features are sent on, takes effect when the server is started
+    pub fn set_namespace(&mut self, namespace: Namespace) {
+        self.namespace = Arc::new(namespace);
+    }
+
+    pub fn namespace(&self) -> &Namespace {
+        &self.namespace
+    }
+
+    /// Layout of the packets sent to destinations, takes effect when the server is started
+    pub fn set_layout(&mut self, layout: MessageLayout) {
+        self.layout = layout;
+    }
+
+    /// Current heartbeat contents
+    pub fn server_info(&self) -> ServerInfo {
+        let mut info = self.info.lock().expect("Server info lock poisoned").clone();
+        info.uptime = self.started_at.elapsed();
+        info
+    }
+
+    /// Sets the device reported in heartbeats
+    pub fn set_device_info(&self, device_name: &str, sample_rate: u32) {
+        if let Ok(mut info) = self.info.lock() {
+            info.device_name = device_name.to_owned();
+            info.sample_rate = sample_rate;
+        }
+    }
 
-        let addrs: SocketAddr = SocketAddr::new(Ipv4Addr::BROADCAST.into(), port);
-        let addrs: SockAddr = SockAddr::from(addrs);
-        
-        Self { socket: socket.into(), addrs, rx: None}
+    /// Sets the state reported in heartbeats, `start_server` sets it to running
+    pub fn set_state(&self, state: ServerState) {
+        if let Ok(mut info) = self.info.lock() {
+            info.state = state;
+        }
     }
 
-    // Currently unused
+    /// Sends `/lt/heartbeat` to every destination until the server is dropped
     pub fn start_heartbeat_thread(&self) {
-        let socket = self.socket.clone();
-        let addrs = self.addrs.clone();
+        let destinations = self.destinations.clone();
+        let streams = self.streams.clone();
+        let subscribers = self.subscribers.clone();
+        let info = self.info.clone();
+        let alive = self.alive.clone();
+        let started_at = self.started_at;
         thread::spawn(move || {
-            loop {
-                let current_time_ms = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis();
-                let data = format!("{{\"host\":\"lt\",\"time\":{}}}", current_time_ms); 
-                let utf8_buffer = data.as_bytes();
-                let _ = socket.send_to(&utf8_buffer, &addrs);
-                thread::sleep(Duration::from_millis(60));
+            while alive.load(Ordering::Relaxed) {
+                let message = match info.lock() {
+                    Ok(info) => ServerInfo { uptime: started_at.elapsed(), ..info.clone() }.to_message(),
+                    Err(_) => break,
+                };
+                if let Ok(buf) = encoder::encode(&OscPacket::Message(message)) {
+                    destinations.iter().for_each(|destination| destination.send(&buf));
+                    if let Some(streams) = &streams {
+                        send_to_streams(streams, |_| vec![buf.clone()]);
+                    }
+                    if let Some(subscribers) = &subscribers {
+                        subscribers.send(&buf);
+                    }
+                }
+                thread::sleep(HEARTBEAT_INTERVAL);
             }
         });
     }
 
-    pub fn set_thread_receiver(&mut self, rx: Receiver<Features>) {
+    /// Forwards every frame to `tx` as well, takes effect when the server is started
+    pub fn add_frame_listener(&mut self, tx: Sender<FeatureFrame>) {
+        self.frame_listeners.push(tx);
+    }
+
+    /// Also sends every bundle and heartbeat to the clients of a stream server, see `StreamServer::start`
+    pub fn set_stream_connections(&mut self, streams: StreamConnections) {
+        self.streams = Some(streams);
+    }
+
+    /// Also sends to the clients that subscribed over a control server, see `ControlServer::subscribers`
+    pub fn set_subscribers(&mut self, subscribers: Arc<UdpSubscribers>) {
+        self.subscribers = Some(subscribers);
+    }
+
+    pub fn set_thread_receiver(&mut self, rx: Receiver<FeatureFrame>) {
         self.rx = Some(rx.into());
     }
 
@@ -48,98 +310,239 @@ impl LunaTechServer {
             panic!("Cannot start server without data input channel");
         }
 
-        let addrs = self.addrs.clone();
-        let socket = self.socket.clone();
+        let destinations = self.destinations.clone();
 
         let receiver = match &self.rx {
             Some(receiver) => receiver, 
             None => panic!("Cannot start server without data input channel"),
         };
 
-        // self.start_heartbeat_thread();
+        self.set_state(ServerState::Running);
+        self.start_heartbeat_thread();
+
+        // Destinations with a fixed rate are sent to by their own thread from an aggregator
+        let aggregators = destinations.iter().map(|destination| {
+            destination.rate().map(|rate| {
+                let aggregator = Arc::new(Mutex::new(FrameAggregator::new(rate.aggregation)));
+                start_rate_thread(destination.clone(), rate, aggregator.clone(), self.output_format(), self.alive.clone());
+                aggregator
+            })
+        }).collect::<Vec<Option<Arc<Mutex<FrameAggregator>>>>>();
 
+        let frame_listeners = self.frame_listeners.clone();
+        let streams = self.streams.clone();
+        let subscribers = self.subscribers.clone();
+        let mapped_targets = self.mapped_targets.clone();
+        let (subscription, namespace) = self.output_format();
         let rx = receiver.clone(); 
         thread::spawn(move || {
-            let start_time: std::time::Instant = std::time::Instant::now();
-            let epoch_start: Duration = UNIX_EPOCH.elapsed().unwrap_or(Duration::from_secs(0));
-            let mut audio_features: Result<Features, crossbeam::channel::RecvError>;
+            let mut audio_features: Result<FeatureFrame, crossbeam::channel::RecvError>;
             loop {
                 audio_features = rx.recv();
-                if let Ok(features) = audio_features {
-                    let secs = (epoch_start.as_secs() + start_time.elapsed().as_secs()) as u32;
-                    let frac = start_time.elapsed().subsec_micros();
-                    if let Ok(buf) = features_to_osc(features, secs, frac) {
-                        let result = socket.send_to(&buf, &addrs);
-                        match result {
-                            Ok(_) => {},
-                            Err(e) => {
-                                println!("Error sending audio features: {}", e.to_string().bold().red());
+                if let Ok(frame) = audio_features {
+                    destinations.iter().zip(&aggregators).for_each(|(destination, aggregator)| match aggregator {
+                        Some(aggregator) => {
+                            if let Ok(mut aggregator) = aggregator.lock() {
+                                aggregator.push(frame.clone());
                             }
                         }
+                        None => send_frame(destination, &frame, &subscription, &namespace),
+                    });
+                    if let Some(streams) = &streams {
+                        send_to_streams(streams, |connection| connection.encode_frame(&frame, &namespace));
+                    }
+                    if let Some(subscribers) = &subscribers {
+                        subscribers.send_frame(&frame, &namespace);
                     }
+                    mapped_targets.iter().for_each(|target| target.send_frame(&frame));
+                    frame_listeners.iter().for_each(|listener| {
+                        let _ = listener.send(frame.clone());
+                    });
+                } else {
+                    // The device monitor was replaced, a restarted server has its own thread
+                    break;
                 }
          
         }});  
         }
     }
 
+impl LunaTechServer {
+    /// What destinations are sent: every feature in the server layout
+    fn output_format(&self) -> (Subscription, Arc<Namespace>) {
+        (Subscription { layout: self.layout, ..Default::default() }, self.namespace.clone())
+    }
+}
+
+impl Drop for LunaTechServer {
+    fn drop(&mut self) {
+        self.alive.store(false, Ordering::Relaxed);
+    }
+}
 
-fn features_to_osc(features: Features, secs: u32, frac: u32) -> Result<Vec<u8>, OscError> {
-    let (broad_range_rms, 
-        low_range_rms, 
-        mid_range_rms, 
-        high_range_rms, 
-        zcr, 
-        spectral_centroid, 
-        flux) = features;
-
-    encoder::encode(&OscPacket::Bundle(OscBundle {
-        timetag: {
-            OscTime::from((secs, frac))
-        },
-        content: vec![
-            OscPacket::Message(OscMessage {
-                addr: OSC_ADDR_BROADRANGERMS.to_string(),
-                args: vec![
-                    OscType::Float(broad_range_rms),
-                ],
-            }),
-            OscPacket::Message(OscMessage {
-                addr: OSC_ADDR_LOWRANGERMS.to_string(),
-                args: vec![
-                    OscType::Float(low_range_rms),
-                ],
-            }),
-            OscPacket::Message(OscMessage {
-                addr: OSC_ADDR_MIDRANGERMS.to_string(),
-                args: vec![
-                    OscType::Float(mid_range_rms),
-                ],
-            }),
-            OscPacket::Message(OscMessage {
-                addr: OSC_ADDR_HIGHRANGERMS.to_string(),
-                args: vec![
-                    OscType::Float(high_range_rms),
-                ],
-            }),
-            OscPacket::Message(OscMessage {
-                addr: OSC_ADDR_ZCR.to_string(),
-                args: vec![
-                    OscType::Float(zcr),
-                ],
-            }),
-            OscPacket::Message(OscMessage {
-                addr: OSC_ADDR_SPECTRALCENTROID.to_string(),
-                args: vec![
-                    OscType::Float(spectral_centroid),
-                ],
-            }),
-            OscPacket::Message(OscMessage {
-                addr: OSC_ADDR_FLUX.to_string(),
-                args: vec![
-                    OscType::Float(flux),
-                ],
-            }),
-        ],
-    }))
-}
\ No newline at end of file
+
+/// Sends a frame at a fixed rate until the server is dropped or stops receiving frames
+fn start_rate_thread(destination: Arc<Destination>, rate: OutputRate, aggregator: Arc<Mutex<FrameAggregator>>, (subscription, namespace): (Subscription, Arc<Namespace>), alive: Arc<AtomicBool>) {
+    thread::spawn(move || {
+        let period = rate.period();
+        let mut next_send = Instant::now();
+        // The receiving thread holds the other reference
+        while alive.load(Ordering::Relaxed) && Arc::strong_count(&aggregator) > 1 {
+            let frame = aggregator.lock().ok().and_then(|mut aggregator| aggregator.take());
+            if let Some(frame) = frame {
+                send_frame(&destination, &frame, &subscription, &namespace);
+            }
+
+            next_send += period;
+            let now = Instant::now();
+            if next_send > now {
+                thread::sleep(next_send - now);
+            } else {
+                // Skip the missed sends instead of bursting to catch up
+                next_send = now;
+            }
+        }
+    });
+}
+
+fn send_frame(destination: &Destination, frame: &FeatureFrame, subscription: &Subscription, namespace: &Namespace) {
+    let timetag = to_timetag(offset_time(frame.captured_at, destination.offset_ms()));
+    frame_packets(frame, timetag, destination.next_sequence(), subscription, namespace).iter().for_each(|packet| {
+        if let Ok(buf) = encoder::encode(packet) {
+            destination.send_features(&buf);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+
+    use crate::timetag::from_timetag;
+
+    use super::*;
+
+    fn unicast_server(port: u16) -> LunaTechServer {
+        let mut server = LunaTechServer::new(port);
+        server.set_broadcast(false).unwrap();
+        server
+    }
+
+    #[test]
+    fn adds_unicast_targets() {
+        let mut server = unicast_server(3000);
+        assert!(server.destinations().is_empty());
+        server.add_target("127.0.0.1:9000").unwrap();
+        server.add_target("127.0.0.1").unwrap();
+        let addrs = server.destinations().iter().map(|destination| destination.addr.to_string()).collect::<Vec<String>>();
+        assert_eq!(addrs, vec!["127.0.0.1:9000", "127.0.0.1:3000"]);
+        assert!(server.destinations().iter().all(|destination| destination.kind == DestinationKind::Unicast));
+    }
+
+    #[test]
+    fn broadcast_is_combined_with_targets() {
+        let mut server = LunaTechServer::new(3000);
+        server.add_target("127.0.0.1:9000").unwrap();
+        let kinds = |server: &LunaTechServer| server.destinations().iter().map(|destination| destination.kind).collect::<Vec<DestinationKind>>();
+        assert_eq!(kinds(&server), vec![DestinationKind::Broadcast, DestinationKind::Unicast]);
+        assert_eq!(server.destinations()[0].addr, SocketAddr::from((Ipv4Addr::BROADCAST, 3000)));
+
+        server.set_broadcast_addr(Ipv4Addr::new(10, 0, 0, 255)).unwrap();
+        assert_eq!(server.destinations()[0].addr, SocketAddr::from(([10, 0, 0, 255], 3000)));
+        server.set_broadcast(false).unwrap();
+        assert_eq!(kinds(&server), vec![DestinationKind::Unicast]);
+    }
+
+    #[test]
+    fn adds_ipv6_targets() {
+        let mut server = unicast_server(3000);
+        server.add_target("[::1]:9000").unwrap();
+        server.add_target("[::1]").unwrap();
+        server.add_target("::1").unwrap();
+        let addrs = server.destinations().iter().map(|destination| destination.addr.to_string()).collect::<Vec<String>>();
+        assert_eq!(addrs, vec!["[::1]:9000", "[::1]:3000", "[::1]:3000"]);
+        server.set_multicast(&MulticastConfig::new("ff02::1234".parse().unwrap())).unwrap();
+        assert_eq!(server.destinations()[3].addr.to_string(), "[ff02::1234]:3000");
+    }
+
+    #[test]
+    fn replaces_the_multicast_group() {
+        let mut server = unicast_server(3000);
+        server.add_target("127.0.0.1:9000").unwrap();
+        assert!(server.set_multicast(&MulticastConfig::new(Ipv4Addr::new(10, 0, 0, 1).into())).is_err());
+        server.set_multicast(&MulticastConfig::new(Ipv4Addr::new(239, 1, 2, 3).into())).unwrap();
+        server.set_multicast(&MulticastConfig::new(Ipv4Addr::new(239, 1, 2, 4).into())).unwrap();
+        let multicast = server.destinations().iter().filter(|destination| destination.kind == DestinationKind::Multicast).collect::<Vec<_>>();
+        assert_eq!(multicast.len(), 1);
+        assert_eq!(multicast[0].addr, SocketAddr::from(([239, 1, 2, 4], 3000)));
+        assert_eq!(server.destinations().len(), 2);
+    }
+
+    #[test]
+    fn moves_destinations_to_the_interface() {
+        let mut server = LunaTechServer::new(3000);
+        let target = server.add_target("127.0.0.1:9000").unwrap();
+        target.set_offset_ms(40);
+        server.set_interface(NetworkInterface {
+            name: "lo".to_owned(),
+            index: Some(1),
+            addr: Ipv4Addr::LOCALHOST.into(),
+            netmask: Ipv4Addr::new(255, 0, 0, 0).into(),
+            broadcast: Some(Ipv4Addr::new(127, 255, 255, 255)),
+            is_loopback: true,
+        }).unwrap();
+
+        let destinations = server.destinations();
+        assert_eq!(destinations.len(), 2);
+        assert_eq!(destinations[0].addr, SocketAddr::from(([127, 255, 255, 255], 3000)));
+        assert_eq!((destinations[1].addr, destinations[1].offset_ms()), (SocketAddr::from(([127, 0, 0, 1], 9000)), 40));
+        assert!(!Arc::ptr_eq(&destinations[1], &target));
+    }
+
+    #[test]
+    fn sends_frames_to_every_target() {
+        let receivers = [UdpSocket::bind("127.0.0.1:0").unwrap(), UdpSocket::bind("127.0.0.1:0").unwrap()];
+        let mut server = unicast_server(3000);
+        for receiver in &receivers {
+            receiver.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+            server.add_target(&receiver.local_addr().unwrap().to_string()).unwrap();
+        }
+        let (tx, rx) = crossbeam::channel::unbounded();
+        server.set_thread_receiver(rx);
+        server.start_server();
+        tx.send(FeatureFrame::default()).unwrap();
+
+        let mut buf = [0; 4096];
+        for receiver in &receivers {
+            assert!(receiver.recv(&mut buf).unwrap() > 0);
+        }
+        for destination in server.destinations() {
+            assert!(destination.stats.packets_sent() >= 1);
+            assert_eq!(destination.stats.send_errors(), 0);
+        }
+    }
+
+    #[test]
+    fn offsets_the_timetags_of_each_destination() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut server = unicast_server(3000);
+        server.add_target(&receiver.local_addr().unwrap().to_string()).unwrap();
+        server.set_offset_ms(-40);
+        let (tx, rx) = crossbeam::channel::unbounded();
+        server.set_thread_receiver(rx);
+        server.start_server();
+
+        let captured_at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        tx.send(FeatureFrame { captured_at, ..Default::default() }).unwrap();
+        let mut buf = [0; 4096];
+        let timetag = loop {
+            let len = receiver.recv(&mut buf).unwrap();
+            // Skip heartbeats
+            if let Ok((_, OscPacket::Bundle(bundle))) = rosc::decoder::decode_udp(&buf[..len]) {
+                break bundle.timetag;
+            }
+        };
+        assert_eq!(from_timetag(timetag), Some(captured_at - Duration::from_millis(40)));
+    }
+}
@@ -0,0 +1,240 @@
+use std::{io::{self, Read, Write}, net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream}, sync::{Arc, Mutex}, thread, time::Duration};
+use colored::Colorize;
+use crossbeam::channel::Sender;
+use rosc::{encoder, OscPacket};
+
+use lt_utilities::audio_features::FeatureFrame;
+use lt_utilities::namespace::Namespace;
+
+use crate::control::{ack_message, error_message, flatten_messages, ControlCommand};
+use crate::subscription::Subscriber;
+
+const SLIP_END: u8 = 0xC0;
+const SLIP_ESC: u8 = 0xDB;
+const SLIP_ESC_END: u8 = 0xDC;
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+/// A stalled client is disconnected when a write takes longer than this
+pub const STREAM_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Frames a packet for an OSC 1.1 stream, using double ended SLIP
+pub fn slip_encode(packet: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(packet.len() + 2);
+    encoded.push(SLIP_END);
+    for byte in packet {
+        match *byte {
+            SLIP_END => encoded.extend_from_slice(&[SLIP_ESC, SLIP_ESC_END]),
+            SLIP_ESC => encoded.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+            byte => encoded.push(byte),
+        }
+    }
+    encoded.push(SLIP_END);
+    encoded
+}
+
+/// Splits a SLIP framed stream back into packets
+#[derive(Debug, Default)]
+pub struct SlipDecoder {
+    packet: Vec<u8>,
+    escaped: bool,
+}
+
+impl SlipDecoder {
+    /// Returns the packets completed by `bytes`, the rest is kept for the next call
+    pub fn decode(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut packets = Vec::new();
+        for byte in bytes {
+            match (*byte, self.escaped) {
+                (SLIP_END, _) => {
+                    // Double ended framing puts two END bytes between packets
+                    if !self.packet.is_empty() {
+                        packets.push(std::mem::take(&mut self.packet));
+                    }
+                    self.escaped = false;
+                }
+                (SLIP_ESC, false) => self.escaped = true,
+                (SLIP_ESC_END, true) => {
+                    self.packet.push(SLIP_END);
+                    self.escaped = false;
+                }
+                (SLIP_ESC_ESC, true) => {
+                    self.packet.push(SLIP_ESC);
+                    self.escaped = false;
+                }
+                (byte, _) => {
+                    // Invalid escapes are kept as they are
+                    self.packet.push(byte);
+                    self.escaped = false;
+                }
+            }
+        }
+        packets
+    }
+}
+
+/// A client connected to the stream port
+pub struct StreamConnection {
+    pub addr: SocketAddr,
+    tx: Sender<Vec<u8>>,
+    /// Changed by `/lt/ctl/subscribe` messages the client sends over the stream
+    subscriber: Mutex<Subscriber>,
+}
+
+impl StreamConnection {
+    /// Writes the packets sent to the connection and reads its subscriptions on their own threads
+    pub fn new(stream: TcpStream) -> io::Result<Arc<Self>> {
+        let addr = stream.peer_addr()?;
+        stream.set_nodelay(true)?;
+        stream.set_write_timeout(Some(STREAM_WRITE_TIMEOUT))?;
+        let reader = stream.try_clone()?;
+
+        let (tx, rx) = crossbeam::channel::unbounded::<Vec<u8>>();
+        thread::spawn(move || {
+            let mut stream = stream;
+            for packet in rx {
+                if let Err(e) = stream.write_all(&slip_encode(&packet)) {
+                    println!("Stream client {} disconnected: {}", addr, e.to_string().bold().red());
+                    break;
+                }
+            }
+        });
+
+        let connection = Arc::new(Self { addr, tx, subscriber: Mutex::new(Subscriber::default()) });
+        let reading = connection.clone();
+        thread::spawn(move || reading.read_commands(reader));
+        Ok(connection)
+    }
+
+    /// Queues a packet, false once the connection is closed
+    pub fn send(&self, packet: &[u8]) -> bool {
+        self.tx.send(packet.to_vec()).is_ok()
+    }
+
+    /// Encodes a frame as the client subscribed to it, nothing if its rate doesn't allow sending yet
+    pub fn encode_frame(&self, frame: &FeatureFrame, namespace: &Namespace) -> Vec<Vec<u8>> {
+        self.subscriber.lock().map(|mut subscriber| subscriber.encode_if_due(frame, namespace)).unwrap_or_default()
+    }
+
+    fn read_commands(&self, mut reader: TcpStream) {
+        let mut decoder = SlipDecoder::default();
+        let mut buf = [0u8; 4096];
+        loop {
+            let size = match reader.read(&mut buf) {
+                Ok(0) | Err(_) => return,
+                Ok(size) => size,
+            };
+            for packet in decoder.decode(&buf[..size]) {
+                let Ok((_, packet)) = rosc::decoder::decode_udp(&packet) else {
+                    continue;
+                };
+                let mut messages = Vec::new();
+                flatten_messages(packet, &mut messages);
+                for msg in messages {
+                    let reply = match ControlCommand::from_message(&msg) {
+                        Ok(ControlCommand::Subscribe { subscription, .. }) => {
+                            if let Ok(mut subscriber) = self.subscriber.lock() {
+                                *subscriber = Subscriber::new(subscription);
+                            }
+                            ack_message(&msg.addr)
+                        }
+                        Ok(_) => error_message(&msg.addr, "Only subscriptions are accepted over streams"),
+                        Err(reason) => error_message(&msg.addr, &reason),
+                    };
+                    if let Ok(buf) = encoder::encode(&OscPacket::Message(reply)) {
+                        self.send(&buf);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Connections shared between the stream server and every `LunaTechServer` sending to them
+pub type StreamConnections = Arc<Mutex<Vec<Arc<StreamConnection>>>>;
+
+/// Sends to each connection, dropping the closed ones
+pub fn send_to_streams(connections: &StreamConnections, packets: impl Fn(&StreamConnection) -> Vec<Vec<u8>>) {
+    if let Ok(mut connections) = connections.lock() {
+        connections.retain(|connection| packets(connection).iter().all(|buf| connection.send(buf)));
+    }
+}
+
+/// Accepts OSC 1.1 stream clients over TCP, for links where UDP packets get lost
+pub struct StreamServer {
+    listener: TcpListener,
+    connections: StreamConnections,
+}
+
+impl StreamServer {
+    pub fn new(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port))?;
+        Ok(Self { listener, connections: Arc::new(Mutex::new(Vec::new())) })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts connections and returns them, see `LunaTechServer::set_stream_connections`
+    pub fn start(self) -> StreamConnections {
+        let connections = self.connections.clone();
+        thread::spawn(move || {
+            for stream in self.listener.incoming() {
+                match stream.and_then(StreamConnection::new) {
+                    Ok(connection) => {
+                        println!("Stream client {} connected", connection.addr.to_string().bold().green());
+                        if let Ok(mut connections) = self.connections.lock() {
+                            connections.push(connection);
+                        }
+                    }
+                    Err(e) => println!("Stream connection failed: {}", e.to_string().bold().red()),
+                }
+            }
+        });
+        connections
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_end_and_esc_bytes() {
+        assert_eq!(slip_encode(&[1, SLIP_END, 2, SLIP_ESC, 3]), vec![SLIP_END, 1, SLIP_ESC, SLIP_ESC_END, 2, SLIP_ESC, SLIP_ESC_ESC, 3, SLIP_END]);
+        assert_eq!(slip_encode(&[]), vec![SLIP_END, SLIP_END]);
+    }
+
+    #[test]
+    fn round_trips_packets() {
+        let packets = vec![vec![SLIP_END, SLIP_ESC, SLIP_ESC_END, SLIP_ESC_ESC], b"/lt/Flux\0\0\0\0,f\0\0".to_vec(), (0..=255).collect()];
+        let stream = packets.iter().flat_map(|packet| slip_encode(packet)).collect::<Vec<u8>>();
+        assert_eq!(SlipDecoder::default().decode(&stream), packets);
+    }
+
+    #[test]
+    fn keeps_packets_split_across_reads() {
+        let packets = vec![vec![1, SLIP_END, 2], vec![SLIP_ESC, 3, 4]];
+        let stream = packets.iter().flat_map(|packet| slip_encode(packet)).collect::<Vec<u8>>();
+        // Every split point, including between an escape and the escaped byte
+        for split in 0..=stream.len() {
+            let mut decoder = SlipDecoder::default();
+            let mut decoded = decoder.decode(&stream[..split]);
+            decoded.extend(decoder.decode(&stream[split..]));
+            assert_eq!(decoded, packets, "split at {}", split);
+        }
+        // One byte at a time
+        let mut decoder = SlipDecoder::default();
+        assert_eq!(stream.iter().flat_map(|byte| decoder.decode(&[*byte])).collect::<Vec<Vec<u8>>>(), packets);
+    }
+
+    #[test]
+    fn accepts_single_ended_framing() {
+        assert_eq!(SlipDecoder::default().decode(&[1, 2, SLIP_END, 3, SLIP_END]), vec![vec![1, 2], vec![3]]);
+    }
+
+    #[test]
+    fn keeps_bytes_after_invalid_escapes() {
+        assert_eq!(SlipDecoder::default().decode(&[SLIP_END, SLIP_ESC, 7, SLIP_END]), vec![vec![7]]);
+    }
+}
@@ -0,0 +1,358 @@
+use std::{fmt, net::{SocketAddr, UdpSocket}, str::FromStr, sync::{Arc, Mutex}, time::{Duration, Instant, UNIX_EPOCH}};
+use rosc::encoder;
+use serde_json::{json, Map, Value};
+
+use lt_utilities::audio_features::FeatureFrame;
+use lt_utilities::namespace::Namespace;
+use lt_utilities::osc_pattern::{is_valid_pattern, pattern_matches};
+
+use crate::dmx::MIN_REFRESH_RATE;
+use crate::layout::{frame_packets, MessageLayout};
+use crate::timetag::to_timetag;
+
+/// Most clients that can subscribe over the control port at once
+pub const MAX_UDP_SUBSCRIBERS: usize = 64;
+/// Subscriptions over the control port end unless they are renewed within this time
+pub const SUBSCRIPTION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Encoding of the frames sent to a subscriber
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StreamFormat {
+    /// Binary OSC bundles, as sent to destinations
+    #[default]
+    Osc,
+    /// Text frames, see `frame_to_json`
+    Json,
+}
+
+impl FromStr for StreamFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(StreamFormat::Json),
+            "osc" => Ok(StreamFormat::Osc),
+            _ => Err(format!("Unknown format {}, expected json or osc", s)),
+        }
+    }
+}
+
+impl fmt::Display for StreamFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamFormat::Json => f.write_str("json"),
+            StreamFormat::Osc => f.write_str("osc"),
+        }
+    }
+}
+
+/// What a subscriber wants to receive
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Subscription {
+    /// OSC address patterns of the values to send, such as `/lt/SpectrumBands/*`.
+    /// `None` sends every feature
+    pub addresses: Option<Vec<String>>,
+    /// Maximum frames per second, `None` sends every frame
+    pub rate: Option<f32>,
+    pub format: StreamFormat,
+    /// Layout of OSC packets, JSON frames are not affected
+    pub layout: MessageLayout,
+}
+
+impl Subscription {
+    /// Addresses and values of the frame the subscription selects.
+    /// Arrays whose address doesn't match are sent element by element where the element addresses do
+    pub fn select(&self, frame: &FeatureFrame, namespace: &Namespace) -> Vec<(String, Vec<f32>)> {
+        let matches = |addr: &str| match &self.addresses {
+            Some(patterns) => patterns.iter().any(|pattern| pattern_matches(pattern, addr)),
+            None => true,
+        };
+
+        let mut selected = frame.values().into_iter()
+            .map(|(name, value)| (namespace.address(name), vec![value]))
+            .filter(|(addr, _)| matches(addr))
+            .collect::<Vec<(String, Vec<f32>)>>();
+        frame.arrays().into_iter().for_each(|(name, values)| {
+            let addr = namespace.address(name);
+            if matches(&addr) {
+                selected.push((addr, values.to_vec()));
+            } else if self.addresses.is_some() {
+                selected.extend(values.iter().enumerate()
+                    .map(|(index, value)| (namespace.element_address(name, index), vec![*value]))
+                    .filter(|(addr, _)| matches(addr)));
+            }
+        });
+        selected
+    }
+
+    /// Minimum time between two frames
+    pub fn period(&self) -> Option<Duration> {
+        self.rate.and_then(|rate| Duration::try_from_secs_f32(1. / rate).ok())
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(pattern) = self.addresses.iter().flatten().find(|pattern| !is_valid_pattern(pattern)) {
+            return Err(format!("Invalid address pattern {}", pattern));
+        }
+        match self.rate {
+            Some(rate) if !rate.is_finite() || rate < MIN_REFRESH_RATE => Err(format!("Rate must be a number of at least {}", MIN_REFRESH_RATE)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies the fields given in a subscribe message such as
+    /// `{"type": "subscribe", "addresses": ["/lt/Flux"], "rate": 30, "format": "osc", "layout": "messages"}`.
+    /// Feature names given as `features` are added to the addresses.
+    /// A null field resets it, missing fields are kept
+    pub fn update(&mut self, message: &Value, namespace: &Namespace) -> Result<(), String> {
+        if message.get("type").and_then(Value::as_str) != Some("subscribe") {
+            return Err("Expected a subscribe message".to_owned());
+        }
+
+        let strings = |field: &str| -> Result<Option<Option<Vec<String>>>, String> {
+            match message.get(field) {
+                Some(Value::Null) => Ok(Some(None)),
+                Some(Value::Array(values)) => values.iter().map(|value| {
+                    value.as_str().map(str::to_owned).ok_or_else(|| format!("{} must be strings", field))
+                }).collect::<Result<Vec<String>, String>>().map(|values| Some(Some(values))),
+                Some(_) => Err(format!("{} must be a list", field)),
+                None => Ok(None),
+            }
+        };
+
+        let mut subscription = self.clone();
+        let addresses = strings("addresses")?;
+        let features = strings("features")?.map(|names| names.map(|names| names.iter().map(|name| namespace.address(name)).collect::<Vec<String>>()));
+        if addresses.is_some() || features.is_some() {
+            // Null in either field sends everything again
+            let given = [addresses, features].into_iter().flatten().collect::<Option<Vec<Vec<String>>>>();
+            subscription.addresses = given.map(|given| given.concat());
+        }
+        match message.get("rate") {
+            Some(Value::Null) => subscription.rate = None,
+            Some(rate) => subscription.rate = Some(rate.as_f64().ok_or_else(|| format!("Rate must be a number of at least {}", MIN_REFRESH_RATE))? as f32),
+            None => {}
+        }
+        if let Some(format) = message.get("format") {
+            subscription.format = format.as_str().ok_or_else(|| "Format must be a string".to_owned())?.parse()?;
+        }
+        if let Some(layout) = message.get("layout") {
+            subscription.layout = layout.as_str().ok_or_else(|| "Layout must be a string".to_owned())?.parse()?;
+        }
+
+        subscription.validate()?;
+        *self = subscription;
+        Ok(())
+    }
+}
+
+/// A subscription and what was sent to it
+#[derive(Debug, Default)]
+pub struct Subscriber {
+    pub subscription: Subscription,
+    sequence: u32,
+    last_sent: Option<Instant>,
+}
+
+impl Subscriber {
+    pub fn new(subscription: Subscription) -> Self {
+        Self { subscription, sequence: 0, last_sent: None }
+    }
+
+    /// Whether the rate allows sending another frame
+    pub fn is_due(&self) -> bool {
+        match (self.subscription.period(), self.last_sent) {
+            (Some(period), Some(last_sent)) => last_sent.elapsed() >= period,
+            _ => true,
+        }
+    }
+
+    /// Encodes a frame in the subscribed format and layout and counts it as sent
+    pub fn encode(&mut self, frame: &FeatureFrame, namespace: &Namespace) -> Vec<Vec<u8>> {
+        let packets = match self.subscription.format {
+            StreamFormat::Json => vec![frame_to_json(frame, self.sequence, &self.subscription, namespace).to_string().into_bytes()],
+            StreamFormat::Osc => frame_packets(frame, to_timetag(frame.captured_at), self.sequence, &self.subscription, namespace)
+                .iter()
+                .filter_map(|packet| encoder::encode(packet).ok())
+                .collect(),
+        };
+        self.sequence = self.sequence.wrapping_add(1);
+        self.last_sent = Some(Instant::now());
+        packets
+    }
+
+    /// Encodes a frame if the rate allows sending it
+    pub fn encode_if_due(&mut self, frame: &FeatureFrame, namespace: &Namespace) -> Vec<Vec<u8>> {
+        if self.is_due() { self.encode(frame, namespace) } else { Vec::new() }
+    }
+}
+
+/// `{"type": "frame", "frame": 12, "time": 1700000000.5, "sequence": 3, "features": {"Flux": 0.2, "SpectrumBands/3": 0.1, ...}}`
+pub fn frame_to_json(frame: &FeatureFrame, sequence: u32, subscription: &Subscription, namespace: &Namespace) -> Value {
+    let mut features = Map::new();
+    subscription.select(frame, namespace).into_iter().for_each(|(addr, values)| {
+        // Keyed by feature name, or name and index for single values of arrays
+        let key = match (namespace.feature_name(&addr), namespace.feature_element(&addr)) {
+            (Some(name), _) => name.to_owned(),
+            (None, Some((name, index))) if frame.get_array(name).is_some_and(|vals| index < vals.len()) => format!("{}/{}", name, index),
+            (None, Some(_)) => return,
+            (None, None) => addr.clone(),
+        };
+        let value = match (values.as_slice(), frame.get_array(&key).is_some()) {
+            ([value], false) => json!(value),
+            (values, _) => json!(values),
+        };
+        features.insert(key, value);
+    });
+
+    json!({
+        "type": "frame",
+        "frame": frame.frame,
+        "time": frame.captured_at.duration_since(UNIX_EPOCH).map(|time| time.as_secs_f64()).unwrap_or(0.),
+        "sample_rate": frame.sample_rate,
+        "channel_count": frame.channel_count,
+        "sequence": sequence,
+        "features": features,
+    })
+}
+
+/// A client subscribed over the control port
+struct UdpSubscriber {
+    addr: SocketAddr,
+    subscriber: Subscriber,
+    /// Last time the client sent its subscription
+    renewed: Instant,
+}
+
+/// Clients that subscribed over the control port, they are sent to from the control socket.
+/// Source addresses of UDP packets can be forged, so their number is capped and they expire unless renewed
+pub struct UdpSubscribers {
+    socket: Arc<UdpSocket>,
+    subscribers: Mutex<Vec<UdpSubscriber>>,
+    timeout: Duration,
+}
+
+impl UdpSubscribers {
+    pub fn new(socket: Arc<UdpSocket>) -> Self {
+        Self { socket, subscribers: Mutex::new(Vec::new()), timeout: SUBSCRIPTION_TIMEOUT }
+    }
+
+    /// Time after which subscribers that didn't subscribe again are dropped
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Replaces the subscription of `addr`, if it had one, and renews it
+    pub fn subscribe(&self, addr: SocketAddr, subscription: Subscription) -> Result<(), String> {
+        let mut subscribers = self.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+        self.expire(&mut subscribers);
+        subscribers.retain(|subscriber| subscriber.addr != addr);
+        if subscribers.len() >= MAX_UDP_SUBSCRIBERS {
+            return Err(format!("Too many subscribers, at most {}", MAX_UDP_SUBSCRIBERS));
+        }
+        subscribers.push(UdpSubscriber { addr, subscriber: Subscriber::new(subscription), renewed: Instant::now() });
+        Ok(())
+    }
+
+    /// Returns false if `addr` was not subscribed
+    pub fn unsubscribe(&self, addr: SocketAddr) -> bool {
+        let mut subscribers = self.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+        let count = subscribers.len();
+        subscribers.retain(|subscriber| subscriber.addr != addr);
+        subscribers.len() != count
+    }
+
+    pub fn addrs(&self) -> Vec<SocketAddr> {
+        let mut subscribers = self.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+        self.expire(&mut subscribers);
+        subscribers.iter().map(|subscriber| subscriber.addr).collect()
+    }
+
+    /// Sends a frame to every subscriber it is due for
+    pub fn send_frame(&self, frame: &FeatureFrame, namespace: &Namespace) {
+        let mut subscribers = self.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+        self.expire(&mut subscribers);
+        subscribers.iter_mut().for_each(|subscriber| {
+            subscriber.subscriber.encode_if_due(frame, namespace).iter().for_each(|buf| {
+                let _ = self.socket.send_to(buf, subscriber.addr);
+            });
+        });
+    }
+
+    /// Sends a packet such as a heartbeat to every subscriber
+    pub fn send(&self, buf: &[u8]) {
+        self.addrs().iter().for_each(|addr| {
+            let _ = self.socket.send_to(buf, addr);
+        });
+    }
+
+    fn expire(&self, subscribers: &mut Vec<UdpSubscriber>) {
+        subscribers.retain(|subscriber| subscriber.renewed.elapsed() < self.timeout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn subscribers() -> UdpSubscribers {
+        UdpSubscribers::new(Arc::new(UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap()))
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port)
+    }
+
+    #[test]
+    fn validates_rates() {
+        for rate in [30., MIN_REFRESH_RATE] {
+            assert!(Subscription { rate: Some(rate), ..Default::default() }.validate().is_ok(), "{}", rate);
+        }
+        for rate in [0., -1., 1e-39, 0.001, f32::NAN, f32::INFINITY] {
+            assert!(Subscription { rate: Some(rate), ..Default::default() }.validate().is_err(), "{}", rate);
+        }
+        assert_eq!(Subscription { rate: Some(4.), ..Default::default() }.period(), Some(Duration::from_millis(250)));
+        // Unvalidated rates never panic
+        assert_eq!(Subscription { rate: Some(1e-39), ..Default::default() }.period(), None);
+        assert!(Subscriber::new(Subscription { rate: Some(1e-39), ..Default::default() }).is_due());
+    }
+
+    #[test]
+    fn limits_rates() {
+        let mut subscriber = Subscriber::new(Subscription { rate: Some(1.), ..Default::default() });
+        assert_eq!(subscriber.encode_if_due(&FeatureFrame::default(), &Namespace::default()).len(), 1);
+        assert!(subscriber.encode_if_due(&FeatureFrame::default(), &Namespace::default()).is_empty());
+    }
+
+    #[test]
+    fn caps_subscribers() {
+        let subscribers = subscribers();
+        for port in 0..MAX_UDP_SUBSCRIBERS as u16 {
+            assert_eq!(subscribers.subscribe(addr(10000 + port), Subscription::default()), Ok(()));
+        }
+        assert!(subscribers.subscribe(addr(20000), Subscription::default()).is_err());
+        // Renewing doesn't count as another subscriber
+        assert_eq!(subscribers.subscribe(addr(10000), Subscription::default()), Ok(()));
+        assert_eq!(subscribers.addrs().len(), MAX_UDP_SUBSCRIBERS);
+
+        assert!(subscribers.unsubscribe(addr(10000)));
+        assert!(!subscribers.unsubscribe(addr(10000)));
+        assert_eq!(subscribers.subscribe(addr(20000), Subscription::default()), Ok(()));
+    }
+
+    #[test]
+    fn expires_subscribers() {
+        let mut subscribers = subscribers();
+        subscribers.set_timeout(Duration::from_millis(200));
+        subscribers.subscribe(addr(10000), Subscription::default()).unwrap();
+        subscribers.subscribe(addr(10001), Subscription::default()).unwrap();
+        std::thread::sleep(Duration::from_millis(120));
+        subscribers.subscribe(addr(10001), Subscription::default()).unwrap();
+        std::thread::sleep(Duration::from_millis(120));
+        assert_eq!(subscribers.addrs(), vec![addr(10001)]);
+        std::thread::sleep(Duration::from_millis(120));
+        assert!(subscribers.addrs().is_empty());
+    }
+}
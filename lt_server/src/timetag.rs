@@ -0,0 +1,100 @@
+use std::time::{Duration, Instant, SystemTime};
+use rosc::OscTime;
+
+/// The OSC timetag meaning "now", also used for times before 1970
+pub const IMMEDIATELY: OscTime = OscTime { seconds: 0, fractional: 1 };
+/// Seconds from the NTP epoch (1900) to the UNIX epoch (1970)
+pub const NTP_UNIX_OFFSET: u32 = 2_208_988_800;
+
+/// Wall clock read once and advanced with a monotonic clock,
+/// so consecutive times never jump when the system clock is adjusted
+#[derive(Clone, Copy, Debug)]
+pub struct MonotonicClock {
+    anchor_system: SystemTime,
+    anchor_instant: Instant,
+}
+
+impl MonotonicClock {
+    pub fn new() -> Self {
+        Self { anchor_system: SystemTime::now(), anchor_instant: Instant::now() }
+    }
+
+    pub fn now(&self) -> SystemTime {
+        self.anchor_system + self.anchor_instant.elapsed()
+    }
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Moves a time later by a positive number of milliseconds or earlier by a negative one
+pub fn offset_time(time: SystemTime, offset_ms: i64) -> SystemTime {
+    let offset = Duration::from_millis(offset_ms.unsigned_abs());
+    if offset_ms >= 0 {
+        time + offset
+    } else {
+        time.checked_sub(offset).unwrap_or(time)
+    }
+}
+
+/// NTP timetag of a time, seconds since 1900 and fractions of 2^-32 seconds
+pub fn to_timetag(time: SystemTime) -> OscTime {
+    OscTime::try_from(time).unwrap_or(IMMEDIATELY)
+}
+
+/// Time of a timetag, `None` for `IMMEDIATELY` and times before 1970
+pub fn from_timetag(timetag: OscTime) -> Option<SystemTime> {
+    if timetag.seconds < NTP_UNIX_OFFSET {
+        return None;
+    }
+    Some(SystemTime::from(timetag))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+
+    #[test]
+    fn converts_to_ntp_time() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_500);
+        let timetag = to_timetag(time);
+        assert_eq!(timetag.seconds, NTP_UNIX_OFFSET + 1_700_000_000);
+        // Half a second is 2^31 fractions
+        assert_eq!(timetag.fractional, 1 << 31);
+    }
+
+    #[test]
+    fn round_trips_within_a_fraction() {
+        let time = UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789);
+        let round_trip = from_timetag(to_timetag(time)).unwrap();
+        let difference = round_trip.duration_since(time).unwrap_or_else(|e| e.duration());
+        assert!(difference < Duration::from_nanos(1), "{:?}", difference);
+    }
+
+    #[test]
+    fn keeps_immediately_apart_from_times() {
+        assert_eq!(from_timetag(IMMEDIATELY), None);
+        assert_eq!(from_timetag(OscTime { seconds: NTP_UNIX_OFFSET - 1, fractional: 0 }), None);
+        assert_eq!(from_timetag(OscTime { seconds: NTP_UNIX_OFFSET, fractional: 0 }), Some(UNIX_EPOCH));
+    }
+
+    #[test]
+    fn offsets_times_both_ways() {
+        let time = UNIX_EPOCH + Duration::from_secs(10);
+        assert_eq!(offset_time(time, 40), time + Duration::from_millis(40));
+        assert_eq!(offset_time(time, -40), time - Duration::from_millis(40));
+        assert_eq!(offset_time(UNIX_EPOCH, -40), UNIX_EPOCH - Duration::from_millis(40));
+    }
+
+    #[test]
+    fn monotonic_clock_never_goes_back() {
+        let clock = MonotonicClock::new();
+        let first = clock.now();
+        assert!(clock.now() >= first);
+    }
+}
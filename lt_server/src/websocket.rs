@@ -0,0 +1,195 @@
+use std::{io, net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream}, sync::{Arc, Mutex}, thread, time::Duration};
+use colored::Colorize;
+use crossbeam::channel::{Receiver, Sender};
+use serde_json::{json, Value};
+use tungstenite::{Message, WebSocket};
+
+use lt_utilities::audio_features::FeatureFrame;
+use lt_utilities::namespace::Namespace;
+
+use crate::subscription::{StreamFormat, Subscriber, Subscription};
+
+/// How long a connection waits for subscribe messages before checking for frames
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/// Streams frames to browsers and other clients that can't receive UDP
+pub struct WebSocketServer {
+    listener: TcpListener,
+    clients: Arc<Mutex<Vec<Sender<FeatureFrame>>>>,
+    namespace: Namespace,
+}
+
+impl WebSocketServer {
+    pub fn new(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port))?;
+        Ok(Self { listener, clients: Arc::new(Mutex::new(Vec::new())), namespace: Namespace::default() })
+    }
+
+    /// Addresses used for OSC frames and subscriptions, takes effect when the server is started
+    pub fn set_namespace(&mut self, namespace: Namespace) {
+        self.namespace = namespace;
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts connections and returns the sender frames are streamed from,
+    /// see `LunaTechServer::add_frame_listener`
+    pub fn start(self) -> Sender<FeatureFrame> {
+        let (tx, rx) = crossbeam::channel::unbounded::<FeatureFrame>();
+
+        let clients = self.clients.clone();
+        thread::spawn(move || {
+            for frame in rx {
+                if let Ok(mut clients) = clients.lock() {
+                    clients.retain(|client| client.send(frame.clone()).is_ok());
+                }
+            }
+        });
+
+        thread::spawn(move || {
+            for stream in self.listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let (client_tx, client_rx) = crossbeam::channel::unbounded();
+                        if let Ok(mut clients) = self.clients.lock() {
+                            clients.push(client_tx);
+                        }
+                        let namespace = self.namespace.clone();
+                        thread::spawn(move || {
+                            let peer = stream.peer_addr().ok();
+                            if let Err(e) = handle_client(stream, client_rx, &namespace) {
+                                println!("WebSocket client {:?} disconnected: {}", peer, e.to_string().bold().red());
+                            }
+                        });
+                    }
+                    Err(e) => println!("WebSocket connection failed: {}", e.to_string().bold().red()),
+                }
+            }
+        });
+
+        tx
+    }
+}
+
+fn handle_client(stream: TcpStream, frames: Receiver<FeatureFrame>, namespace: &Namespace) -> io::Result<()> {
+    let mut socket = tungstenite::accept(stream).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    socket.get_mut().set_read_timeout(Some(POLL_INTERVAL))?;
+
+    // Browsers get JSON unless they subscribe to OSC
+    let mut subscriber = Subscriber::new(Subscription { format: StreamFormat::Json, ..Default::default() });
+    let mut latest: Option<FeatureFrame> = None;
+    loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                let reply = handle_subscribe(text.as_str(), &mut subscriber.subscription, namespace);
+                send(&mut socket, Message::text(reply.to_string()))?;
+            }
+            Ok(Message::Close(_)) | Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e)) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+            Err(e) => return Err(io::Error::other(e.to_string())),
+        }
+
+        if let Some(frame) = frames.try_iter().last() {
+            latest = Some(frame);
+        }
+        if !subscriber.is_due() {
+            continue;
+        }
+        let Some(frame) = latest.take() else {
+            continue;
+        };
+        for buf in subscriber.encode(&frame, namespace) {
+            let message = match subscriber.subscription.format {
+                StreamFormat::Json => Message::text(String::from_utf8_lossy(&buf).into_owned()),
+                StreamFormat::Osc => Message::binary(buf),
+            };
+            send(&mut socket, message)?;
+        }
+    }
+}
+
+/// Applies a subscribe message and returns the reply, the subscription is kept if the message is rejected
+fn handle_subscribe(text: &str, subscription: &mut Subscription, namespace: &Namespace) -> Value {
+    let result = serde_json::from_str::<Value>(text)
+        .map_err(|e| e.to_string())
+        .and_then(|message| subscription.update(&message, namespace));
+    match result {
+        Ok(_) => json!({
+            "type": "subscribed",
+            "addresses": subscription.addresses,
+            "rate": subscription.rate,
+            "format": subscription.format.to_string(),
+            "layout": subscription.layout.to_string(),
+        }),
+        Err(e) => json!({ "type": "error", "message": e }),
+    }
+}
+
+fn send(socket: &mut WebSocket<TcpStream>, message: Message) -> io::Result<()> {
+    socket.send(message).map_err(|e| io::Error::other(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::MessageLayout;
+
+    fn subscribe(text: &str, subscription: &mut Subscription) -> Value {
+        handle_subscribe(text, subscription, &Namespace::default())
+    }
+
+    #[test]
+    fn replies_with_the_subscription() {
+        let mut subscription = Subscription { format: StreamFormat::Json, ..Default::default() };
+        let reply = subscribe(r#"{"type": "subscribe", "addresses": ["/lt/*RMS"], "features": ["Flux"], "rate": 30, "format": "osc", "layout": "messages"}"#, &mut subscription);
+        assert_eq!(reply, json!({"type": "subscribed", "addresses": ["/lt/*RMS", "/lt/Flux"], "rate": 30., "format": "osc", "layout": "messages"}));
+        assert_eq!(subscription.format, StreamFormat::Osc);
+        assert_eq!(subscription.layout, MessageLayout::Messages);
+
+        // Missing fields are kept, null resets them
+        let reply = subscribe(r#"{"type": "subscribe", "addresses": null, "rate": null}"#, &mut subscription);
+        assert_eq!(reply, json!({"type": "subscribed", "addresses": null, "rate": null, "format": "osc", "layout": "messages"}));
+    }
+
+    #[test]
+    fn rejected_messages_keep_the_subscription() {
+        let mut subscription = Subscription { rate: Some(10.), ..Default::default() };
+        for text in [
+            "not json",
+            r#"{"type": "unsubscribe"}"#,
+            r#"{"type": "subscribe", "rate": 1e-39}"#,
+            r#"{"type": "subscribe", "rate": 0}"#,
+            r#"{"type": "subscribe", "rate": "fast"}"#,
+            r#"{"type": "subscribe", "addresses": ["/lt/["]}"#,
+            r#"{"type": "subscribe", "addresses": "/lt/Flux"}"#,
+            r#"{"type": "subscribe", "format": "xml"}"#,
+        ] {
+            let reply = subscribe(text, &mut subscription);
+            assert_eq!(reply["type"], "error", "{}", text);
+            assert!(reply["message"].is_string(), "{}", text);
+        }
+        assert_eq!(subscription, Subscription { rate: Some(10.), ..Default::default() });
+    }
+
+    #[test]
+    fn streams_frames_to_clients() {
+        let server = WebSocketServer::new(0).unwrap();
+        let port = server.local_addr().unwrap().port();
+        let frames = server.start();
+        let (mut socket, _) = tungstenite::connect(format!("ws://127.0.0.1:{}", port)).unwrap();
+
+        socket.send(Message::text(r#"{"type": "subscribe", "features": ["Flux"]}"#)).unwrap();
+        let reply = serde_json::from_str::<Value>(socket.read().unwrap().to_text().unwrap()).unwrap();
+        assert_eq!(reply["type"], "subscribed");
+        assert_eq!(reply["format"], "json");
+
+        frames.send(FeatureFrame { frame: 7, flux: 0.5, ..Default::default() }).unwrap();
+        let frame = serde_json::from_str::<Value>(socket.read().unwrap().to_text().unwrap()).unwrap();
+        assert_eq!(frame["type"], "frame");
+        assert_eq!(frame["frame"], 7);
+        assert_eq!(frame["features"], json!({"Flux": 0.5}));
+    }
+}
@@ -1,13 +1,107 @@
+use std::borrow::Cow;
+
 pub type OscAddress = &'static str;
 
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FeatureKind {
+    Scalar,
+    /// Number of values, the maximum number for definitions made with `feature!`
+    Array(usize),
+}
+
+/// Describes a feature for clients, meters and metadata queries
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FeatureDescriptor {
+    pub name: Cow<'static, str>,
+    pub kind: FeatureKind,
+    pub units: Cow<'static, str>,
+    /// Expected range of the values
+    pub min: f32,
+    pub max: f32,
+    /// Suggested exponential smoothing factor, 0 disables smoothing
+    pub smoothing: f32,
+    pub description: Cow<'static, str>,
+}
+
+impl FeatureDescriptor {
+    /// Scalar feature without any metadata
+    pub fn scalar(name: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            name: name.into(),
+            kind: FeatureKind::Scalar,
+            units: Cow::Borrowed(""),
+            min: 0.0,
+            max: 1.0,
+            smoothing: 0.0,
+            description: Cow::Borrowed(""),
+        }
+    }
+
+    /// Copy of the descriptor holding `len` values
+    pub fn with_len(&self, len: usize) -> Self {
+        Self { kind: FeatureKind::Array(len), ..self.clone() }
+    }
+
+    /// Number of values the feature holds
+    pub fn len(&self) -> usize {
+        match self.kind {
+            FeatureKind::Scalar => 1,
+            FeatureKind::Array(len) => len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_array(&self) -> bool {
+        matches!(self.kind, FeatureKind::Array(_))
+    }
+}
+
+/// Plain atomic float feature without metadata
 #[macro_export]
 macro_rules! atomic_float {
     ($name:ident) => {
-        pub type $name = f32;
+        $crate::feature!($name {
+            units: "",
+            range: [0.0, 1.0],
+            smoothing: 0.0,
+            description: "",
+        });
+    };
+}
 
+/// Defines a feature: its value type, a name constant, a descriptor constant and an atomic storage type.
+/// `Name[N]` defines an array feature holding at most N values. Addresses come from `Namespace`
+#[macro_export]
+macro_rules! feature {
+    (@consts $name:ident, $kind:expr, $units:expr, $min:expr, $max:expr, $smoothing:expr, $description:expr) => {
         paste::paste! {
-            pub const [<OSC_ADDR_$name:upper>]: atomic_float::OscAddress = concat!("/lt/", stringify!($name)); 
+            pub const [<FEATURE_NAME_$name:upper>]: &str = stringify!($name);
+            pub const [<FEATURE_$name:upper>]: $crate::atomic_float::FeatureDescriptor = $crate::atomic_float::FeatureDescriptor {
+                name: std::borrow::Cow::Borrowed(stringify!($name)),
+                kind: $kind,
+                units: std::borrow::Cow::Borrowed($units),
+                min: $min,
+                max: $max,
+                smoothing: $smoothing,
+                description: std::borrow::Cow::Borrowed($description),
+            };
         }
+    };
+
+    ($name:ident {
+        units: $units:expr,
+        range: [$min:expr, $max:expr],
+        smoothing: $smoothing:expr,
+        description: $description:expr $(,)?
+    }) => {
+        pub type $name = f32;
+
+        $crate::feature!(@consts $name, $crate::atomic_float::FeatureKind::Scalar, $units, $min, $max, $smoothing, $description);
 
         paste::paste! {
             pub struct [<$name Atomic>] {
@@ -20,15 +114,71 @@ macro_rules! atomic_float {
                         value: std::sync::atomic::AtomicU32::new((val).to_bits()),
                     }
                 }
-    
+
                 pub fn get(&self) -> f32 {
-                    f32::from_bits(self.value.load(Ordering::SeqCst))
+                    f32::from_bits(self.value.load(std::sync::atomic::Ordering::SeqCst))
                 }
-    
+
                 pub fn set(&self, val: f32) {
-                    self.value.store(val.to_bits(), Ordering::SeqCst);
+                    self.value.store(val.to_bits(), std::sync::atomic::Ordering::SeqCst);
+                }
+            }
+        }
+    };
+
+    ($name:ident[$capacity:expr] {
+        units: $units:expr,
+        range: [$min:expr, $max:expr],
+        smoothing: $smoothing:expr,
+        description: $description:expr $(,)?
+    }) => {
+        pub type $name = Vec<f32>;
+
+        $crate::feature!(@consts $name, $crate::atomic_float::FeatureKind::Array($capacity), $units, $min, $max, $smoothing, $description);
+
+        paste::paste! {
+            /// Atomic slice of values, longer inputs are truncated to the capacity
+            pub struct [<$name Atomic>] {
+                values: Box<[std::sync::atomic::AtomicU32]>,
+                len: std::sync::atomic::AtomicUsize,
+            }
+
+            impl [<$name Atomic>] {
+                pub fn new(vals: &[f32]) -> Self {
+                    let atomic = Self {
+                        values: (0..$capacity).map(|_| std::sync::atomic::AtomicU32::new(0)).collect(),
+                        len: std::sync::atomic::AtomicUsize::new(0),
+                    };
+                    atomic.set(vals);
+                    atomic
+                }
+
+                pub fn capacity(&self) -> usize {
+                    self.values.len()
+                }
+
+                pub fn len(&self) -> usize {
+                    self.len.load(std::sync::atomic::Ordering::SeqCst)
+                }
+
+                pub fn is_empty(&self) -> bool {
+                    self.len() == 0
+                }
+
+                pub fn get(&self) -> Vec<f32> {
+                    self.values[..self.len()].iter().map(|value| {
+                        f32::from_bits(value.load(std::sync::atomic::Ordering::SeqCst))
+                    }).collect()
+                }
+
+                pub fn set(&self, vals: &[f32]) {
+                    let len = vals.len().min(self.values.len());
+                    self.values.iter().zip(&vals[..len]).for_each(|(value, val)| {
+                        value.store(val.to_bits(), std::sync::atomic::Ordering::SeqCst);
+                    });
+                    self.len.store(len, std::sync::atomic::Ordering::SeqCst);
                 }
             }
         }
     };
-}
\ No newline at end of file
+}
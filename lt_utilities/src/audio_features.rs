@@ -1,17 +1,236 @@
-use std::sync::{atomic::{Ordering}, Arc};
+use std::{collections::{BTreeMap, HashMap}, sync::{Arc, RwLock}, time::{SystemTime, UNIX_EPOCH}};
 
-use crate::atomic_float;
+use crate::atomic_float::FeatureDescriptor;
+use crate::feature;
+use crate::snapshot::{Snapshot, SnapshotCell};
 
-atomic_float!(BroadRangeRMS);
-atomic_float!(LowRangeRMS);
-atomic_float!(MidRangeRMS);
-atomic_float!(HighRangeRMS);
-atomic_float!(ZCR);
-atomic_float!(SpectralCentroid);
-atomic_float!(Flux);
+/// Most values an array feature without a descriptor may hold, element indices received are checked against it
+pub const MAX_ARRAY_LEN: usize = 1024;
 
-pub type Features = (BroadRangeRMS, LowRangeRMS, MidRangeRMS, HighRangeRMS, ZCR, SpectralCentroid, Flux);
+feature!(BroadRangeRMS {
+    units: "",
+    range: [0.0, 1.0],
+    smoothing: 0.5,
+    description: "RMS of the log compressed spectrum",
+});
+feature!(LowRangeRMS {
+    units: "",
+    range: [0.0, 1.0],
+    smoothing: 0.5,
+    description: "RMS of the log compressed spectrum from 0 to 250 Hz",
+});
+feature!(MidRangeRMS {
+    units: "",
+    range: [0.0, 1.0],
+    smoothing: 0.5,
+    description: "RMS of the log compressed spectrum from 250 to 4000 Hz",
+});
+feature!(HighRangeRMS {
+    units: "",
+    range: [0.0, 1.0],
+    smoothing: 0.5,
+    description: "RMS of the log compressed spectrum from 4000 to 20000 Hz",
+});
+feature!(ZCR {
+    units: "crossings/sample",
+    range: [0.0, 1.0],
+    smoothing: 0.3,
+    description: "Zero crossing rate of the signal",
+});
+feature!(SpectralCentroid {
+    units: "Hz",
+    range: [0.0, 22050.0],
+    smoothing: 0.3,
+    description: "Magnitude weighted mean frequency of the spectrum",
+});
+feature!(Flux {
+    units: "",
+    range: [0.0, 100.0],
+    smoothing: 0.0,
+    description: "Change of the spectrum since the previous buffer",
+});
+feature!(SpectrumBands[64] {
+    units: "",
+    range: [0.0, 1.0],
+    smoothing: 0.5,
+    description: "RMS of logarithmically spaced bands of the log compressed spectrum",
+});
+feature!(Chroma[12] {
+    units: "",
+    range: [0.0, 1.0],
+    smoothing: 0.5,
+    description: "Energy of each pitch class from C to B, relative to the strongest",
+});
+feature!(MFCC[13] {
+    units: "",
+    range: [-100.0, 100.0],
+    smoothing: 0.3,
+    description: "Mel frequency cepstral coefficients of the spectrum",
+});
 
+/// Every built in feature
+pub const FEATURES: &[FeatureDescriptor] = &[
+    FEATURE_BROADRANGERMS,
+    FEATURE_LOWRANGERMS,
+    FEATURE_MIDRANGERMS,
+    FEATURE_HIGHRANGERMS,
+    FEATURE_ZCR,
+    FEATURE_SPECTRALCENTROID,
+    FEATURE_FLUX,
+    FEATURE_SPECTRUMBANDS,
+    FEATURE_CHROMA,
+    FEATURE_MFCC,
+];
+
+/// Descriptor of a built in feature
+pub fn descriptor(name: &str) -> Option<&'static FeatureDescriptor> {
+    FEATURES.iter().find(|descriptor| descriptor.name == name)
+}
+
+/// Features computed from one audio buffer
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct FeatureFrame {
+    /// Number of buffers analyzed before this one
+    pub frame: u64,
+    /// Time the audio buffer was captured
+    #[cfg_attr(feature = "serde", serde(with = "crate::codec::unix_seconds"))]
+    pub captured_at: SystemTime,
+    pub sample_rate: u32,
+    pub channel_count: u16,
+    pub broad_range_rms: BroadRangeRMS,
+    pub low_range_rms: LowRangeRMS,
+    pub mid_range_rms: MidRangeRMS,
+    pub high_range_rms: HighRangeRMS,
+    pub zcr: ZCR,
+    pub spectral_centroid: SpectralCentroid,
+    pub flux: Flux,
+    pub spectrum_bands: SpectrumBands,
+    pub chroma: Chroma,
+    pub mfcc: MFCC,
+    /// Values of registered features that have no dedicated field
+    pub extensions: BTreeMap<String, f32>,
+    /// Values of registered array features that have no dedicated field
+    pub array_extensions: BTreeMap<String, Vec<f32>>,
+}
+
+impl FeatureFrame {
+    pub fn get(&self, name: &str) -> Option<f32> {
+        match name {
+            FEATURE_NAME_BROADRANGERMS => Some(self.broad_range_rms),
+            FEATURE_NAME_LOWRANGERMS => Some(self.low_range_rms),
+            FEATURE_NAME_MIDRANGERMS => Some(self.mid_range_rms),
+            FEATURE_NAME_HIGHRANGERMS => Some(self.high_range_rms),
+            FEATURE_NAME_ZCR => Some(self.zcr),
+            FEATURE_NAME_SPECTRALCENTROID => Some(self.spectral_centroid),
+            FEATURE_NAME_FLUX => Some(self.flux),
+            _ => self.extensions.get(name).copied(),
+        }
+    }
+
+    pub fn set(&mut self, name: &str, val: f32) {
+        match name {
+            FEATURE_NAME_BROADRANGERMS => self.broad_range_rms = val,
+            FEATURE_NAME_LOWRANGERMS => self.low_range_rms = val,
+            FEATURE_NAME_MIDRANGERMS => self.mid_range_rms = val,
+            FEATURE_NAME_HIGHRANGERMS => self.high_range_rms = val,
+            FEATURE_NAME_ZCR => self.zcr = val,
+            FEATURE_NAME_SPECTRALCENTROID => self.spectral_centroid = val,
+            FEATURE_NAME_FLUX => self.flux = val,
+            _ => {
+                self.extensions.insert(name.to_owned(), val);
+            }
+        }
+    }
+
+    /// Every value by name, built in features first
+    pub fn values(&self) -> Vec<(&str, f32)> {
+        let mut values = vec![
+            (FEATURE_NAME_BROADRANGERMS, self.broad_range_rms),
+            (FEATURE_NAME_LOWRANGERMS, self.low_range_rms),
+            (FEATURE_NAME_MIDRANGERMS, self.mid_range_rms),
+            (FEATURE_NAME_HIGHRANGERMS, self.high_range_rms),
+            (FEATURE_NAME_ZCR, self.zcr),
+            (FEATURE_NAME_SPECTRALCENTROID, self.spectral_centroid),
+            (FEATURE_NAME_FLUX, self.flux),
+        ];
+        values.extend(self.extensions.iter().map(|(name, val)| (name.as_str(), *val)));
+        values
+    }
+
+    pub fn get_array(&self, name: &str) -> Option<&[f32]> {
+        match name {
+            FEATURE_NAME_SPECTRUMBANDS => Some(&self.spectrum_bands),
+            FEATURE_NAME_CHROMA => Some(&self.chroma),
+            FEATURE_NAME_MFCC => Some(&self.mfcc),
+            _ => self.array_extensions.get(name).map(|vals| vals.as_slice()),
+        }
+    }
+
+    pub fn set_array(&mut self, name: &str, vals: Vec<f32>) {
+        match name {
+            FEATURE_NAME_SPECTRUMBANDS => self.spectrum_bands = vals,
+            FEATURE_NAME_CHROMA => self.chroma = vals,
+            FEATURE_NAME_MFCC => self.mfcc = vals,
+            _ => {
+                self.array_extensions.insert(name.to_owned(), vals);
+            }
+        }
+    }
+
+    /// Sets one value of an array feature, growing the array with zeros if needed.
+    /// Indices beyond the most values the feature may hold are dropped, returns whether the value was set
+    pub fn set_array_element(&mut self, name: &str, index: usize, val: f32) -> bool {
+        if index >= descriptor(name).map(FeatureDescriptor::len).unwrap_or(MAX_ARRAY_LEN) {
+            return false;
+        }
+        let mut vals = self.get_array(name).map(|vals| vals.to_vec()).unwrap_or_default();
+        if vals.len() <= index {
+            vals.resize(index + 1, 0.);
+        }
+        vals[index] = val;
+        self.set_array(name, vals);
+        true
+    }
+
+    /// Every array value by name, built in features first
+    pub fn arrays(&self) -> Vec<(&str, &[f32])> {
+        let mut arrays = vec![
+            (FEATURE_NAME_SPECTRUMBANDS, self.spectrum_bands.as_slice()),
+            (FEATURE_NAME_CHROMA, self.chroma.as_slice()),
+            (FEATURE_NAME_MFCC, self.mfcc.as_slice()),
+        ];
+        arrays.extend(self.array_extensions.iter().map(|(name, vals)| (name.as_str(), vals.as_slice())));
+        arrays
+    }
+}
+
+impl Default for FeatureFrame {
+    fn default() -> Self {
+        Self {
+            frame: 0,
+            captured_at: UNIX_EPOCH,
+            sample_rate: 0,
+            channel_count: 0,
+            broad_range_rms: 0.0,
+            low_range_rms: 0.0,
+            mid_range_rms: 0.0,
+            high_range_rms: 0.0,
+            zcr: 0.0,
+            spectral_centroid: 0.0,
+            flux: 0.0,
+            spectrum_bands: Vec::new(),
+            chroma: Vec::new(),
+            mfcc: Vec::new(),
+            extensions: BTreeMap::new(),
+            array_extensions: BTreeMap::new(),
+        }
+    }
+}
+
+/// Latest feature values, shared between threads.
+/// Each field is updated on its own, so use `snapshot` when values need to come from the same frame
 pub struct AtomicAudioFeatures {
     pub broad_range_rms: Arc<BroadRangeRMSAtomic>,
     pub low_range_rms: Arc<LowRangeRMSAtomic>,
@@ -20,6 +239,85 @@ pub struct AtomicAudioFeatures {
     pub zcr: Arc<ZCRAtomic>,
     pub spectral_centroid: Arc<SpectralCentroidAtomic>,
     pub flux: Arc<FluxAtomic>,
+    pub spectrum_bands: Arc<SpectrumBandsAtomic>,
+    pub chroma: Arc<ChromaAtomic>,
+    pub mfcc: Arc<MFCCAtomic>,
+    /// Values of registered features that have no dedicated field
+    pub extensions: RwLock<HashMap<String, f32>>,
+    /// Values of registered array features that have no dedicated field
+    pub array_extensions: RwLock<HashMap<String, Vec<f32>>>,
+    frames: SnapshotCell<FeatureFrame>,
+}
+
+impl AtomicAudioFeatures {
+    pub fn get(&self, name: &str) -> Option<f32> {
+        match name {
+            FEATURE_NAME_BROADRANGERMS => Some(self.broad_range_rms.get()),
+            FEATURE_NAME_LOWRANGERMS => Some(self.low_range_rms.get()),
+            FEATURE_NAME_MIDRANGERMS => Some(self.mid_range_rms.get()),
+            FEATURE_NAME_HIGHRANGERMS => Some(self.high_range_rms.get()),
+            FEATURE_NAME_ZCR => Some(self.zcr.get()),
+            FEATURE_NAME_SPECTRALCENTROID => Some(self.spectral_centroid.get()),
+            FEATURE_NAME_FLUX => Some(self.flux.get()),
+            _ => self.extensions.read().ok()?.get(name).copied(),
+        }
+    }
+
+    pub fn set(&self, name: &str, val: f32) {
+        match name {
+            FEATURE_NAME_BROADRANGERMS => self.broad_range_rms.set(val),
+            FEATURE_NAME_LOWRANGERMS => self.low_range_rms.set(val),
+            FEATURE_NAME_MIDRANGERMS => self.mid_range_rms.set(val),
+            FEATURE_NAME_HIGHRANGERMS => self.high_range_rms.set(val),
+            FEATURE_NAME_ZCR => self.zcr.set(val),
+            FEATURE_NAME_SPECTRALCENTROID => self.spectral_centroid.set(val),
+            FEATURE_NAME_FLUX => self.flux.set(val),
+            _ => {
+                if let Ok(mut extensions) = self.extensions.write() {
+                    extensions.insert(name.to_owned(), val);
+                }
+            }
+        }
+    }
+
+    pub fn get_array(&self, name: &str) -> Option<Vec<f32>> {
+        match name {
+            FEATURE_NAME_SPECTRUMBANDS => Some(self.spectrum_bands.get()),
+            FEATURE_NAME_CHROMA => Some(self.chroma.get()),
+            FEATURE_NAME_MFCC => Some(self.mfcc.get()),
+            _ => self.array_extensions.read().ok()?.get(name).cloned(),
+        }
+    }
+
+    pub fn set_array(&self, name: &str, vals: &[f32]) {
+        match name {
+            FEATURE_NAME_SPECTRUMBANDS => self.spectrum_bands.set(vals),
+            FEATURE_NAME_CHROMA => self.chroma.set(vals),
+            FEATURE_NAME_MFCC => self.mfcc.set(vals),
+            _ => {
+                if let Ok(mut array_extensions) = self.array_extensions.write() {
+                    array_extensions.insert(name.to_owned(), vals.to_vec());
+                }
+            }
+        }
+    }
+
+    /// Stores every value of a frame and publishes the frame as a whole
+    pub fn store(&self, frame: &FeatureFrame) {
+        frame.values().into_iter().for_each(|(name, val)| self.set(name, val));
+        frame.arrays().into_iter().for_each(|(name, vals)| self.set_array(name, vals));
+        self.frames.publish(frame.clone());
+    }
+
+    /// Latest stored frame, with every value from that same frame
+    pub fn snapshot(&self) -> Snapshot<FeatureFrame> {
+        self.frames.snapshot()
+    }
+
+    /// Number of frames stored so far
+    pub fn sequence(&self) -> u64 {
+        self.frames.sequence()
+    }
 }
 
 impl Default for AtomicAudioFeatures {
@@ -32,6 +330,99 @@ impl Default for AtomicAudioFeatures {
             zcr: Arc::new(ZCRAtomic::new(0.0)),
             spectral_centroid: Arc::new(SpectralCentroidAtomic::new(0.0)),
             flux: Arc::new(FluxAtomic::new(0.0)),
+            spectrum_bands: Arc::new(SpectrumBandsAtomic::new(&[])),
+            chroma: Arc::new(ChromaAtomic::new(&[])),
+            mfcc: Arc::new(MFCCAtomic::new(&[])),
+            extensions: RwLock::new(HashMap::new()),
+            array_extensions: RwLock::new(HashMap::new()),
+            frames: SnapshotCell::default(),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_and_writes_values_by_name() {
+        let mut frame = FeatureFrame::default();
+        frame.set(FEATURE_NAME_ZCR, 0.25);
+        frame.set(FEATURE_NAME_SPECTRALCENTROID, 440.);
+        frame.set("Custom", 0.5);
+        assert_eq!((frame.zcr, frame.spectral_centroid), (0.25, 440.));
+        assert_eq!(frame.get(FEATURE_NAME_ZCR), Some(0.25));
+        assert_eq!(frame.get(FEATURE_NAME_FLUX), Some(0.));
+        assert_eq!(frame.get("Custom"), Some(0.5));
+        assert_eq!(frame.get("Missing"), None);
+
+        frame.set_array(FEATURE_NAME_SPECTRUMBANDS, vec![0.1, 0.2]);
+        frame.set_array("CustomBands", vec![1.]);
+        assert_eq!(frame.spectrum_bands, vec![0.1, 0.2]);
+        assert_eq!(frame.get_array("CustomBands"), Some([1.].as_slice()));
+        assert_eq!(frame.get_array("Missing"), None);
+    }
+
+    #[test]
+    fn lists_built_in_values_first() {
+        let mut frame = FeatureFrame { broad_range_rms: 1., flux: 2., ..Default::default() };
+        frame.set("Custom", 3.);
+        frame.set_array("CustomBands", vec![4.]);
+        let values = frame.values();
+        assert_eq!(values.len(), 8);
+        assert_eq!(values[0], (FEATURE_NAME_BROADRANGERMS, 1.));
+        assert_eq!(values[6], (FEATURE_NAME_FLUX, 2.));
+        assert_eq!(values[7], ("Custom", 3.));
+        let names = frame.arrays().into_iter().map(|(name, _)| name).collect::<Vec<&str>>();
+        assert_eq!(names, vec![FEATURE_NAME_SPECTRUMBANDS, FEATURE_NAME_CHROMA, FEATURE_NAME_MFCC, "CustomBands"]);
+    }
+
+    #[test]
+    fn sets_array_elements_within_the_feature_length() {
+        let mut frame = FeatureFrame::default();
+        assert!(frame.set_array_element(FEATURE_NAME_SPECTRUMBANDS, 2, 0.5));
+        assert_eq!(frame.spectrum_bands, vec![0., 0., 0.5]);
+        assert!(frame.set_array_element(FEATURE_NAME_SPECTRUMBANDS, FEATURE_SPECTRUMBANDS.len() - 1, 1.));
+        assert_eq!(frame.spectrum_bands.len(), FEATURE_SPECTRUMBANDS.len());
+    }
+
+    #[test]
+    fn drops_array_elements_beyond_the_feature_length() {
+        let mut frame = FeatureFrame::default();
+        assert!(!frame.set_array_element(FEATURE_NAME_SPECTRUMBANDS, FEATURE_SPECTRUMBANDS.len(), 1.));
+        assert!(!frame.set_array_element(FEATURE_NAME_SPECTRUMBANDS, 4_000_000_000, 1.));
+        assert!(frame.spectrum_bands.is_empty());
+        assert!(!frame.set_array_element(FEATURE_NAME_CHROMA, FEATURE_CHROMA.len(), 1.));
+        assert!(frame.chroma.is_empty());
+        assert!(!frame.set_array_element("Custom", MAX_ARRAY_LEN, 1.));
+        assert_eq!(frame.get_array("Custom"), None);
+        assert!(frame.set_array_element("Custom", 11, 1.));
+        assert_eq!(frame.get_array("Custom").map(|vals| vals.len()), Some(12));
+    }
+
+    #[test]
+    fn stores_array_values() {
+        let bands = SpectrumBandsAtomic::new(&[0.25, 0.5]);
+        assert_eq!((bands.capacity(), bands.len()), (64, 2));
+        assert_eq!(bands.get(), vec![0.25, 0.5]);
+        bands.set(&[1.]);
+        assert_eq!(bands.get(), vec![1.]);
+        bands.set(&[]);
+        assert!(bands.is_empty());
+        assert_eq!(bands.get(), Vec::<f32>::new());
+    }
+
+    #[test]
+    fn truncates_array_values_to_the_capacity() {
+        let vals = (0..20).map(|val| val as f32).collect::<Vec<f32>>();
+        let chroma = ChromaAtomic::new(&vals);
+        assert_eq!(chroma.len(), FEATURE_CHROMA.len());
+        assert_eq!(chroma.get(), vals[..12]);
+
+        let features = AtomicAudioFeatures::default();
+        features.set_array(FEATURE_NAME_MFCC, &vals);
+        assert_eq!(features.get_array(FEATURE_NAME_MFCC), Some(vals[..13].to_vec()));
+        features.set_array("Custom", &vals);
+        assert_eq!(features.get_array("Custom"), Some(vals));
+    }
+}
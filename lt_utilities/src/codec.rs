@@ -0,0 +1,134 @@
+use std::{fmt, str::FromStr};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Encodings for sending feature frames over transports other than OSC
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+#[derive(Debug)]
+pub enum CodecError {
+    Json(serde_json::Error),
+    MessagePackEncode(rmp_serde::encode::Error),
+    MessagePackDecode(rmp_serde::decode::Error),
+    CborEncode(ciborium::ser::Error<std::io::Error>),
+    CborDecode(ciborium::de::Error<std::io::Error>),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::Json(e) => write!(f, "JSON: {}", e),
+            CodecError::MessagePackEncode(e) => write!(f, "MessagePack: {}", e),
+            CodecError::MessagePackDecode(e) => write!(f, "MessagePack: {}", e),
+            CodecError::CborEncode(e) => write!(f, "CBOR: {}", e),
+            CodecError::CborDecode(e) => write!(f, "CBOR: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+impl Codec {
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        match self {
+            Codec::Json => serde_json::to_vec(value).map_err(CodecError::Json),
+            // Named fields keep MessagePack maps readable from other languages
+            Codec::MessagePack => rmp_serde::to_vec_named(value).map_err(CodecError::MessagePackEncode),
+            Codec::Cbor => {
+                let mut buf = Vec::new();
+                ciborium::into_writer(value, &mut buf).map_err(CodecError::CborEncode)?;
+                Ok(buf)
+            }
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        match self {
+            Codec::Json => serde_json::from_slice(bytes).map_err(CodecError::Json),
+            Codec::MessagePack => rmp_serde::from_slice(bytes).map_err(CodecError::MessagePackDecode),
+            Codec::Cbor => ciborium::from_reader(bytes).map_err(CodecError::CborDecode),
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(Codec::Json),
+            "msgpack" | "messagepack" => Ok(Codec::MessagePack),
+            "cbor" => Ok(Codec::Cbor),
+            _ => Err(format!("Unknown codec: {}", s)),
+        }
+    }
+}
+
+/// Serializes a `SystemTime` as fractional seconds since the unix epoch
+pub(crate) mod unix_seconds {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        let secs = time.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO).as_secs_f64();
+        serializer.serialize_f64(secs)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        let secs = f64::deserialize(deserializer)?;
+        Ok(UNIX_EPOCH + Duration::try_from_secs_f64(secs).unwrap_or(Duration::ZERO))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+    use crate::audio_features::FeatureFrame;
+
+    fn frame() -> FeatureFrame {
+        let mut frame = FeatureFrame {
+            frame: 12,
+            captured_at: UNIX_EPOCH + Duration::from_millis(1_700_000_000_500),
+            sample_rate: 48000,
+            channel_count: 2,
+            flux: 3.5,
+            spectrum_bands: vec![0.25, 0.5, 0.75],
+            ..Default::default()
+        };
+        frame.set("Loudness", -14.);
+        frame.set_array("Chroma", vec![0.; 12]);
+        frame
+    }
+
+    #[test]
+    fn round_trips_frames() {
+        for codec in [Codec::Json, Codec::MessagePack, Codec::Cbor] {
+            let bytes = codec.encode(&frame()).unwrap();
+            assert_eq!(codec.decode::<FeatureFrame>(&bytes).unwrap(), frame(), "{:?}", codec);
+        }
+    }
+
+    #[test]
+    fn reports_invalid_data() {
+        for codec in [Codec::Json, Codec::MessagePack, Codec::Cbor] {
+            assert!(codec.decode::<FeatureFrame>(&[0xC1, 0xFF, 0x00]).is_err(), "{:?}", codec);
+        }
+    }
+
+    #[test]
+    fn parses_names() {
+        assert_eq!("JSON".parse::<Codec>(), Ok(Codec::Json));
+        assert_eq!("msgpack".parse::<Codec>(), Ok(Codec::MessagePack));
+        assert_eq!("messagepack".parse::<Codec>(), Ok(Codec::MessagePack));
+        assert_eq!("cbor".parse::<Codec>(), Ok(Codec::Cbor));
+        assert!("xml".parse::<Codec>().is_err());
+    }
+}
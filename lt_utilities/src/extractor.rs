@@ -0,0 +1,143 @@
+use crate::atomic_float::FeatureDescriptor;
+
+/// Analysis data for a single channel of an audio buffer
+pub struct AnalysisFrame<'a> {
+    pub channel_index: usize,
+    pub sample_rate: u32,
+    /// Time domain samples of the channel
+    pub samples: &'a [f32],
+    /// Normalized FFT magnitudes
+    pub magnitudes: &'a [f32],
+    /// Log compressed FFT magnitudes
+    pub log_magnitudes: &'a [f32],
+    /// Frequency of each magnitude bin in Hz
+    pub freqs: &'a [f32],
+}
+
+/// Computes one or more named feature values from an analysis frame
+pub trait Extractor: Send {
+    /// Features produced, in the order their values are returned by `extract`
+    fn features(&self) -> Vec<FeatureDescriptor>;
+
+    /// Computes the values for a single channel.
+    /// Scalar features take one value and array features as many as their length
+    fn extract(&mut self, frame: &AnalysisFrame) -> Vec<f32>;
+
+    /// Applies a runtime parameter such as `bands`, returns whether the extractor uses it
+    fn set_parameter(&mut self, _name: &str, _value: f32) -> bool {
+        false
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.features().into_iter().map(|feature| feature.name.into_owned()).collect()
+    }
+
+    /// Combines the per channel results of one value, defaults to the mean
+    fn combine(&self, channel_values: &[f32]) -> f32 {
+        if channel_values.is_empty() {
+            return 0.;
+        }
+        channel_values.iter().sum::<f32>() / channel_values.len() as f32
+    }
+}
+
+/// Ordered set of extractors run by the analyzer for every buffer
+#[derive(Default)]
+pub struct ExtractorRegistry {
+    extractors: Vec<Box<dyn Extractor>>,
+}
+
+impl ExtractorRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<E: Extractor + 'static>(&mut self, extractor: E) {
+        self.extractors.push(Box::new(extractor));
+    }
+
+    /// Every feature produced by the registered extractors
+    pub fn features(&self) -> Vec<FeatureDescriptor> {
+        self.extractors.iter().flat_map(|extractor| extractor.features()).collect()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.extractors.iter().flat_map(|extractor| extractor.names()).collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Box<dyn Extractor>> {
+        self.extractors.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Box<dyn Extractor>> {
+        self.extractors.iter_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.extractors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.extractors.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::atomic_float::FeatureKind;
+
+    use super::*;
+
+    struct Fixed {
+        name: &'static str,
+        value: f32,
+    }
+
+    impl Extractor for Fixed {
+        fn features(&self) -> Vec<FeatureDescriptor> {
+            vec![FeatureDescriptor::scalar(self.name), FeatureDescriptor::scalar("Levels").with_len(2)]
+        }
+
+        fn extract(&mut self, _frame: &AnalysisFrame) -> Vec<f32> {
+            vec![self.value, 0., 1.]
+        }
+
+        fn set_parameter(&mut self, name: &str, value: f32) -> bool {
+            if name != "value" {
+                return false;
+            }
+            self.value = value;
+            true
+        }
+    }
+
+    #[test]
+    fn lists_features_in_registration_order() {
+        let mut registry = ExtractorRegistry::new();
+        assert!(registry.is_empty());
+        registry.register(Fixed { name: "First", value: 0. });
+        registry.register(Fixed { name: "Second", value: 0. });
+        assert_eq!(registry.len(), 2);
+        assert_eq!(registry.names(), vec!["First", "Levels", "Second", "Levels"]);
+        assert_eq!(registry.features()[1].kind, FeatureKind::Array(2));
+    }
+
+    #[test]
+    fn passes_parameters_to_extractors() {
+        let mut registry = ExtractorRegistry::new();
+        registry.register(Fixed { name: "First", value: 0. });
+        assert!(registry.iter_mut().any(|extractor| extractor.set_parameter("value", 0.5)));
+        assert!(!registry.iter_mut().any(|extractor| extractor.set_parameter("other", 0.5)));
+
+        let frame = AnalysisFrame { channel_index: 0, sample_rate: 48000, samples: &[], magnitudes: &[], log_magnitudes: &[], freqs: &[] };
+        let values = registry.iter_mut().flat_map(|extractor| extractor.extract(&frame)).collect::<Vec<f32>>();
+        assert_eq!(values, vec![0.5, 0., 1.]);
+    }
+
+    #[test]
+    fn combines_channels_with_the_mean() {
+        let extractor = Fixed { name: "First", value: 0. };
+        assert_eq!(extractor.combine(&[0.25, 0.75, 0.5]), 0.5);
+        assert_eq!(extractor.combine(&[]), 0.);
+    }
+}
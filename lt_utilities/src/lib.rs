@@ -2,6 +2,12 @@ use std::sync::{Arc, Mutex};
 
 pub mod atomic_float;
 pub mod audio_features;
+#[cfg(feature = "serde")]
+pub mod codec;
+pub mod extractor;
+pub mod namespace;
+pub mod osc_pattern;
+pub mod snapshot;
 
 pub type ArcMutex<T> = Arc<Mutex<T>>;
 #[macro_export]
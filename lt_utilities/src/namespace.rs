@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+
+use crate::atomic_float::FeatureDescriptor;
+
+/// Prefix of the default namespace, feature addresses are `/lt/<name>`
+pub const DEFAULT_PREFIX: &str = "/lt";
+/// Last part of the address of the message holding a whole frame
+pub const FRAME_ADDRESS: &str = "frame";
+
+/// Characters with a meaning in OSC address patterns, which can't be part of an address
+pub const RESERVED_CHARS: &[char] = &[' ', '#', '*', ',', '?', '[', ']', '{', '}'];
+
+/// Maps feature names to the OSC addresses they are sent on and back
+#[derive(Clone, Debug, PartialEq)]
+pub struct Namespace {
+    prefix: String,
+    /// Inserted after the prefix, to tell several servers apart
+    instance: Option<String>,
+    /// Addresses replacing the generated ones, by feature name
+    overrides: BTreeMap<String, String>,
+}
+
+impl Default for Namespace {
+    fn default() -> Self {
+        Self::new(DEFAULT_PREFIX)
+    }
+}
+
+impl Namespace {
+    /// Namespace under `prefix`, such as `/lt` or `/venue/audio`
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            prefix: format!("/{}", prefix.trim_matches('/')).trim_end_matches('/').to_owned(),
+            instance: None,
+            overrides: BTreeMap::new(),
+        }
+    }
+
+    /// Sends features on `/<prefix>/<instance>/<name>`, as in `/lt/stageA/Flux`
+    pub fn set_instance(&mut self, instance: Option<&str>) {
+        self.instance = instance.map(|instance| instance.trim_matches('/').to_owned()).filter(|instance| !instance.is_empty());
+    }
+
+    /// Sends a feature on `addr` instead of the generated address
+    pub fn set_override(&mut self, name: &str, addr: &str) {
+        self.overrides.insert(name.to_owned(), addr.to_owned());
+    }
+
+    /// Checks the prefix, instance and overrides, and that no two of the addresses of `features`,
+    /// the overridden features and the frame message are the same or one contains the other
+    pub fn validate(&self, features: &[FeatureDescriptor]) -> Result<(), String> {
+        if let Some(instance) = self.instance.as_ref().filter(|instance| instance.contains(RESERVED_CHARS)) {
+            return Err(format!("Invalid instance {}", instance));
+        }
+        if self.prefix.contains(RESERVED_CHARS) {
+            return Err(format!("Invalid prefix {}", self.prefix));
+        }
+        if let Some((name, addr)) = self.overrides.iter().find(|(_, addr)| !addr.starts_with('/') || addr.ends_with('/') || addr.contains(RESERVED_CHARS)) {
+            return Err(format!("Invalid address {} for {}", addr, name));
+        }
+
+        let mut addresses = features.iter().map(|feature| feature.name.as_ref())
+            .chain(self.overrides.keys().map(String::as_str).filter(|name| !features.iter().any(|feature| feature.name == *name)))
+            .map(|name| (self.address(name), name.to_owned()))
+            .collect::<Vec<(String, String)>>();
+        addresses.push((self.frame_address(), "the frame message".to_owned()));
+        for (index, (addr, name)) in addresses.iter().enumerate() {
+            for (other_addr, other) in &addresses[index + 1..] {
+                if addr == other_addr {
+                    return Err(format!("Address {} is used by both {} and {}", addr, name, other));
+                }
+                // Array elements and OSCQuery containers are below an address
+                let (outer, inner) = if other_addr.len() < addr.len() { ((other_addr, other), (addr, name)) } else { ((addr, name), (other_addr, other)) };
+                if inner.0.strip_prefix(outer.0.as_str()).is_some_and(|rest| rest.starts_with('/')) {
+                    return Err(format!("Address {} of {} contains the address {} of {}", outer.0, outer.1, inner.0, inner.1));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn instance(&self) -> Option<&str> {
+        self.instance.as_deref()
+    }
+
+    /// Feature names and the addresses replacing their generated ones
+    pub fn overrides(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.overrides.iter().map(|(name, addr)| (name.as_str(), addr.as_str()))
+    }
+
+    /// Address every generated address starts with
+    pub fn base(&self) -> String {
+        match &self.instance {
+            Some(instance) => format!("{}/{}", self.prefix, instance),
+            None => self.prefix.clone(),
+        }
+    }
+
+    /// Address a feature is sent on
+    pub fn address(&self, name: &str) -> String {
+        match self.overrides.get(name) {
+            Some(addr) => addr.clone(),
+            None => format!("{}/{}", self.base(), name),
+        }
+    }
+
+    /// Address of a single value of an array feature, such as `/lt/SpectrumBands/3`
+    pub fn element_address(&self, name: &str, index: usize) -> String {
+        format!("{}/{}", self.address(name), index)
+    }
+
+    /// Address of the message holding every value of a frame
+    pub fn frame_address(&self) -> String {
+        format!("{}/{}", self.base(), FRAME_ADDRESS)
+    }
+
+    /// Feature name of an address, if it is in this namespace
+    pub fn feature_name<'a>(&'a self, addr: &'a str) -> Option<&'a str> {
+        if let Some((name, _)) = self.overrides.iter().find(|(_, override_addr)| *override_addr == addr) {
+            return Some(name);
+        }
+        let name = addr.strip_prefix(&self.base())?.strip_prefix('/')?;
+        (!name.is_empty() && !self.overrides.contains_key(name)).then_some(name)
+    }
+
+    /// Array feature name and index of an element address
+    pub fn feature_element<'a>(&'a self, addr: &'a str) -> Option<(&'a str, usize)> {
+        let (addr, index) = addr.rsplit_once('/')?;
+        Some((self.feature_name(addr)?, index.parse().ok()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_features::FEATURES;
+
+    #[test]
+    fn builds_addresses() {
+        let mut namespace = Namespace::new("venue/audio/");
+        assert_eq!(namespace.address("Flux"), "/venue/audio/Flux");
+        namespace.set_instance(Some("/stageA/"));
+        assert_eq!(namespace.address("Flux"), "/venue/audio/stageA/Flux");
+        assert_eq!(namespace.element_address("SpectrumBands", 3), "/venue/audio/stageA/SpectrumBands/3");
+        assert_eq!(namespace.frame_address(), "/venue/audio/stageA/frame");
+        namespace.set_instance(Some(""));
+        assert_eq!(namespace.base(), "/venue/audio");
+        assert_eq!(Namespace::default().address("ZCR"), "/lt/ZCR");
+    }
+
+    #[test]
+    fn finds_feature_names() {
+        let mut namespace = Namespace::default();
+        namespace.set_override("Flux", "/show/flux");
+        assert_eq!(namespace.feature_name("/lt/ZCR"), Some("ZCR"));
+        assert_eq!(namespace.feature_name("/show/flux"), Some("Flux"));
+        // Overridden features are no longer sent on the generated address
+        assert_eq!(namespace.feature_name("/lt/Flux"), None);
+        assert_eq!(namespace.feature_name("/lt/"), None);
+        assert_eq!(namespace.feature_name("/other/ZCR"), None);
+        assert_eq!(namespace.feature_name("/ltZCR"), None);
+    }
+
+    #[test]
+    fn finds_array_elements() {
+        let mut namespace = Namespace::default();
+        namespace.set_override("SpectrumBands", "/show/bands");
+        assert_eq!(namespace.feature_element("/show/bands/3"), Some(("SpectrumBands", 3)));
+        assert_eq!(namespace.feature_element("/lt/Chroma/11"), Some(("Chroma", 11)));
+        assert_eq!(namespace.feature_element("/lt/Chroma/x"), None);
+        assert_eq!(namespace.feature_element("/lt/Chroma/-1"), None);
+        assert_eq!(namespace.feature_element("/lt/3"), None);
+    }
+
+    #[test]
+    fn validates_addresses() {
+        assert!(Namespace::default().validate(FEATURES).is_ok());
+        assert!(Namespace::new("/my show").validate(FEATURES).is_err());
+
+        let mut namespace = Namespace::default();
+        namespace.set_instance(Some("stage*"));
+        assert!(namespace.validate(FEATURES).is_err());
+
+        let invalid = ["show/flux", "/show/flux/", "/show/{flux}"];
+        for addr in invalid {
+            let mut namespace = Namespace::default();
+            namespace.set_override("Flux", addr);
+            assert!(namespace.validate(FEATURES).is_err(), "{}", addr);
+        }
+    }
+
+    #[test]
+    fn rejects_shared_addresses() {
+        let mut namespace = Namespace::default();
+        namespace.set_override("Flux", "/show/a");
+        namespace.set_override("ZCR", "/show/a");
+        assert_eq!(namespace.validate(FEATURES), Err("Address /show/a is used by both ZCR and Flux".to_owned()));
+
+        // The generated address of another feature
+        let mut namespace = Namespace::default();
+        namespace.set_override("Flux", "/lt/ZCR");
+        assert_eq!(namespace.validate(FEATURES), Err("Address /lt/ZCR is used by both ZCR and Flux".to_owned()));
+
+        let mut namespace = Namespace::default();
+        namespace.set_override("Flux", "/lt/frame");
+        assert!(namespace.validate(FEATURES).is_err());
+
+        // Features that are not sent don't take an address
+        let mut namespace = Namespace::default();
+        namespace.set_override("Flux", "/lt/ZCR");
+        assert!(namespace.validate(&[FeatureDescriptor::scalar("Flux")]).is_ok());
+    }
+
+    #[test]
+    fn rejects_addresses_containing_others() {
+        let mut namespace = Namespace::default();
+        namespace.set_override("BroadRangeRMS", "/lt");
+        assert_eq!(namespace.validate(FEATURES), Err(format!("Address /lt of BroadRangeRMS contains the address {} of {}", namespace.address(&FEATURES[1].name), FEATURES[1].name)));
+
+        // Element addresses are below the array address
+        let mut namespace = Namespace::default();
+        namespace.set_override("Flux", "/lt/SpectrumBands/3");
+        assert!(namespace.validate(FEATURES).is_err());
+
+        // Sharing only the start of a part is fine
+        let mut namespace = Namespace::default();
+        namespace.set_override("Flux", "/lt/ZCRate");
+        assert!(namespace.validate(FEATURES).is_ok());
+    }
+}
@@ -0,0 +1,136 @@
+/// Whether `address` matches the OSC 1.0 address pattern `pattern`.
+/// Each part between slashes supports `?`, `*`, `[abc]`, `[a-z]`, `[!abc]` and `{foo,bar}`
+pub fn pattern_matches(pattern: &str, address: &str) -> bool {
+    let patterns = pattern.split('/').collect::<Vec<&str>>();
+    let parts = address.split('/').collect::<Vec<&str>>();
+    patterns.len() == parts.len() && patterns.iter().zip(parts).all(|(pattern, part)| {
+        part_matches(&pattern.chars().collect::<Vec<char>>(), &part.chars().collect::<Vec<char>>())
+    })
+}
+
+/// Whether `pattern` can be matched against addresses
+pub fn is_valid_pattern(pattern: &str) -> bool {
+    let balanced = |open: char, close: char| {
+        let mut depth = 0;
+        pattern.chars().all(|c| {
+            if c == open { depth += 1; }
+            if c == close { depth -= 1; }
+            (0..=1).contains(&depth)
+        }) && depth == 0
+    };
+    pattern.starts_with('/') && balanced('[', ']') && balanced('{', '}')
+}
+
+fn part_matches(pattern: &[char], part: &[char]) -> bool {
+    match pattern.first() {
+        None => part.is_empty(),
+        Some('*') => (0..=part.len()).any(|skip| part_matches(&pattern[1..], &part[skip..])),
+        Some('?') => !part.is_empty() && part_matches(&pattern[1..], &part[1..]),
+        Some('[') => {
+            let (Some(end), Some(c)) = (pattern.iter().position(|c| *c == ']'), part.first()) else {
+                return false;
+            };
+            let (negated, set) = match &pattern[1..end] {
+                ['!', set @ ..] => (true, set),
+                set => (false, set),
+            };
+            in_set(set, *c) != negated && part_matches(&pattern[end + 1..], &part[1..])
+        }
+        Some('{') => {
+            let Some(end) = pattern.iter().position(|c| *c == '}') else {
+                return false;
+            };
+            pattern[1..end].split(|c| *c == ',').any(|alternative| {
+                part.starts_with(alternative) && part_matches(&pattern[end + 1..], &part[alternative.len()..])
+            })
+        }
+        Some(c) => part.first() == Some(c) && part_matches(&pattern[1..], &part[1..]),
+    }
+}
+
+/// Whether `c` is in a bracket set such as `a-z0` without the brackets
+fn in_set(set: &[char], c: char) -> bool {
+    let mut index = 0;
+    while index < set.len() {
+        // A dash at either end is a literal dash
+        if index + 2 < set.len() && set[index + 1] == '-' {
+            if (set[index]..=set[index + 2]).contains(&c) {
+                return true;
+            }
+            index += 3;
+        } else {
+            if set[index] == c {
+                return true;
+            }
+            index += 1;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_literal_addresses() {
+        assert!(pattern_matches("/lt/Flux", "/lt/Flux"));
+        assert!(!pattern_matches("/lt/Flux", "/lt/flux"));
+        assert!(!pattern_matches("/lt/Flux", "/lt/Flux/0"));
+        assert!(!pattern_matches("/lt", "/lt/Flux"));
+    }
+
+    #[test]
+    fn wildcards_stay_within_a_part() {
+        assert!(pattern_matches("/lt/*", "/lt/Flux"));
+        assert!(pattern_matches("/lt/*RMS", "/lt/LowRangeRMS"));
+        assert!(pattern_matches("/lt/*Range*", "/lt/MidRangeRMS"));
+        assert!(pattern_matches("/lt/Flux*", "/lt/Flux"));
+        assert!(!pattern_matches("/lt/*", "/lt/SpectrumBands/3"));
+        assert!(pattern_matches("/lt/*/*", "/lt/SpectrumBands/3"));
+        assert!(pattern_matches("/lt/Z?R", "/lt/ZCR"));
+        assert!(!pattern_matches("/lt/Z?R", "/lt/ZR"));
+        assert!(!pattern_matches("/lt/Z?", "/lt/ZCR"));
+    }
+
+    #[test]
+    fn matches_bracket_sets() {
+        assert!(pattern_matches("/lt/SpectrumBands/[0-3]", "/lt/SpectrumBands/2"));
+        assert!(!pattern_matches("/lt/SpectrumBands/[0-3]", "/lt/SpectrumBands/4"));
+        assert!(pattern_matches("/lt/SpectrumBands/[0-37]", "/lt/SpectrumBands/7"));
+        assert!(pattern_matches("/lt/[!a-z]lux", "/lt/Flux"));
+        assert!(!pattern_matches("/lt/[!a-z]lux", "/lt/flux"));
+        assert!(!pattern_matches("/lt/[!A-Z]*", "/lt/Flux"));
+        assert!(!pattern_matches("/lt/SpectrumBands/[0-3]", "/lt/SpectrumBands/"));
+    }
+
+    #[test]
+    fn dashes_at_the_ends_of_sets_are_literal() {
+        assert!(in_set(&['-', 'a'], '-'));
+        assert!(in_set(&['a', '-'], '-'));
+        assert!(!in_set(&['a', '-'], 'b'));
+        assert!(in_set(&['a', '-', 'c', 'x'], 'b'));
+        assert!(in_set(&['a', '-', 'c', 'x'], 'x'));
+        assert!(!in_set(&[], 'a'));
+    }
+
+    #[test]
+    fn matches_alternatives() {
+        assert!(pattern_matches("/lt/{Flux,ZCR}", "/lt/Flux"));
+        assert!(pattern_matches("/lt/{Flux,ZCR}", "/lt/ZCR"));
+        assert!(!pattern_matches("/lt/{Flux,ZCR}", "/lt/SpectralCentroid"));
+        assert!(pattern_matches("/lt/{Low,High}RangeRMS", "/lt/HighRangeRMS"));
+        assert!(!pattern_matches("/lt/{Low,High}RangeRMS", "/lt/MidRangeRMS"));
+        assert!(pattern_matches("/lt/{Mid,Mi}dRangeRMS", "/lt/MidRangeRMS"));
+    }
+
+    #[test]
+    fn validates_patterns() {
+        assert!(is_valid_pattern("/lt/[0-3]/{a,b}"));
+        assert!(!is_valid_pattern("lt/Flux"));
+        assert!(!is_valid_pattern("/lt/[0-3"));
+        assert!(!is_valid_pattern("/lt/]0-3["));
+        assert!(!is_valid_pattern("/lt/{a,{b}}"));
+        assert!(!is_valid_pattern("/lt/{a,b"));
+    }
+}
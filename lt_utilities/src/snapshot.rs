@@ -0,0 +1,124 @@
+use std::{ops::Deref, sync::{atomic::{AtomicU64, Ordering}, Arc, RwLock}};
+
+/// A published value together with its sequence number
+#[derive(Debug)]
+pub struct Snapshot<T> {
+    /// Number of values published before this one
+    pub sequence: u64,
+    value: Arc<T>,
+}
+
+impl<T> Clone for Snapshot<T> {
+    fn clone(&self) -> Self {
+        Self { sequence: self.sequence, value: self.value.clone() }
+    }
+}
+
+impl<T> Deref for Snapshot<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+/// Double buffered cell that publishes whole values at once.
+/// The next value is built outside of the lock so writers only hold it for a pointer swap,
+/// and readers always get a value exactly as it was published.
+pub struct SnapshotCell<T> {
+    current: RwLock<Snapshot<T>>,
+    published: AtomicU64,
+}
+
+impl<T> SnapshotCell<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            current: RwLock::new(Snapshot { sequence: 0, value: Arc::new(value) }),
+            published: AtomicU64::new(0),
+        }
+    }
+
+    pub fn publish(&self, value: T) {
+        let value = Arc::new(value);
+        // A swap can't leave the value half written, so a writer that panicked doesn't stop publishing
+        let mut current = self.current.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let sequence = current.sequence + 1;
+        *current = Snapshot { sequence, value };
+        self.published.store(sequence, Ordering::Release);
+    }
+
+    pub fn snapshot(&self) -> Snapshot<T> {
+        match self.current.read() {
+            Ok(current) => current.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    /// Sequence number of the latest value, cheap enough to poll for changes
+    pub fn sequence(&self) -> u64 {
+        self.published.load(Ordering::Acquire)
+    }
+}
+
+impl<T: Default> Default for SnapshotCell<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{panic, thread};
+
+    use super::*;
+
+    #[test]
+    fn publishes_values_in_sequence() {
+        let cell = SnapshotCell::new(1);
+        assert_eq!((cell.sequence(), *cell.snapshot()), (0, 1));
+        cell.publish(2);
+        cell.publish(3);
+        let snapshot = cell.snapshot();
+        assert_eq!((snapshot.sequence, *snapshot, cell.sequence()), (2, 3, 2));
+        assert_eq!(*SnapshotCell::<u32>::default().snapshot(), 0);
+    }
+
+    #[test]
+    fn snapshots_keep_their_value() {
+        let cell = SnapshotCell::new(vec![1, 2]);
+        let snapshot = cell.snapshot();
+        cell.publish(vec![3, 4, 5]);
+        assert_eq!(*snapshot, vec![1, 2]);
+        assert_eq!(snapshot.clone().sequence, 0);
+        assert_eq!(*cell.snapshot(), vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn readers_never_see_partial_values() {
+        let cell = Arc::new(SnapshotCell::new((0u64, 0u64)));
+        let writer = {
+            let cell = cell.clone();
+            thread::spawn(move || (1..=10_000).for_each(|value| cell.publish((value, value))))
+        };
+        let mut last = 0;
+        while last < 10_000 {
+            let snapshot = cell.snapshot();
+            assert_eq!(snapshot.0, snapshot.1);
+            assert!(snapshot.sequence >= last);
+            last = snapshot.sequence;
+        }
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn publishes_after_a_writer_panicked() {
+        let cell = SnapshotCell::new(1);
+        let _ = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let _current = cell.current.write().unwrap();
+            panic!("writer failed");
+        }));
+        assert!(cell.current.is_poisoned());
+        cell.publish(2);
+        assert_eq!((*cell.snapshot(), cell.sequence()), (2, 1));
+    }
+}